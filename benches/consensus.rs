@@ -0,0 +1,112 @@
+// Benchmark of the column consensus of the UMI families
+// The pairs of the HIV DR control library (tests/data/hivdr_control) are filtered with the v1 DR params and grouped into UMI families,
+// then the R1 and R2 consensus of every family (at least 3 reads) are computed with the weighted strategy,
+// with `consensus` (one fixed-size tally per column), then with a baseline of a `HashMap` per column.
+// Run with `cargo bench --bench consensus`.
+
+use std::collections::HashMap;
+
+use bio::io::fastq::Record;
+use criterion::{Criterion, criterion_group, criterion_main};
+
+use virust_tcs::helper::consensus::{
+    ConsensusInput, ConsensusParams, ConsensusStrategy, consensus,
+    consensus_base_column_with_quality,
+};
+use virust_tcs::helper::io::{FASTQ_CHUNK_SIZE, PairedFastqReader};
+use virust_tcs::helper::params::Params;
+use virust_tcs::helper::tcs_helper::{
+    PairedRecordFilterResult, filter_r1_r2_pairs, validate_files,
+};
+
+fn umi_families() -> Vec<Vec<Record>> {
+    let params = Params::from_preset("v1").unwrap().validate().unwrap();
+    let fastq_files = validate_files("tests/data/hivdr_control").unwrap();
+    let pairs = PairedFastqReader::from_files(&fastq_files)
+        .unwrap()
+        .read_chunk(FASTQ_CHUNK_SIZE);
+
+    let mut families: HashMap<(String, String), (Vec<Record>, Vec<Record>)> = HashMap::new();
+    for (r1, r2) in &pairs {
+        if let Ok(PairedRecordFilterResult::Valid(pair)) = filter_r1_r2_pairs(r1, r2, &params) {
+            let family = families
+                .entry((pair.region, pair.umi.umi_information_block))
+                .or_default();
+            family.0.push(pair.r1);
+            family.1.push(pair.r2);
+        }
+    }
+    families
+        .into_values()
+        .filter(|(r1, _)| r1.len() >= 3)
+        .flat_map(|(r1, r2)| [r1, r2])
+        .collect()
+}
+
+/// The column consensus (bases and Phred+33 qualities) before the fixed-size tally: the bases and qualities of each column
+/// are collected, and `consensus_base_column_with_quality` sums the weights in a `HashMap`.
+fn per_column_hashmap_consensus(records: &[Record], params: ConsensusParams) -> (Vec<u8>, Vec<u8>) {
+    let seqs = records.iter().map(|r| r.seq().to_vec()).collect::<Vec<_>>();
+    let quals = records
+        .iter()
+        .map(|r| r.qual().to_vec())
+        .collect::<Vec<_>>();
+    (0..seqs[0].len())
+        .map(|i| {
+            let bases = seqs.iter().map(|r| r[i]).collect::<Vec<u8>>();
+            let col_quals = quals.iter().map(|r| r[i]).collect::<Vec<u8>>();
+            consensus_base_column_with_quality(&bases, &col_quals, params.k(), params.q0())
+                .unwrap_or((b'N', b'!'))
+        })
+        .unzip()
+}
+
+fn consensus_benchmark(c: &mut Criterion) {
+    let families = umi_families();
+    let params = ConsensusParams::default();
+    let strategy = ConsensusStrategy::Weighted(params);
+    let same_length = families
+        .iter()
+        .filter(|records| {
+            records
+                .iter()
+                .all(|r| r.seq().len() == records[0].seq().len())
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    println!(
+        "{} R1 and R2 read groups of UMI families, {} reads",
+        same_length.len(),
+        same_length
+            .iter()
+            .map(|records| records.len())
+            .sum::<usize>()
+    );
+    for records in &same_length {
+        let result = consensus(strategy, ConsensusInput::Fastq(records)).unwrap();
+        let (seq, qual) = per_column_hashmap_consensus(records, params);
+        assert_eq!(result.seq, seq);
+        assert_eq!(result.qual.unwrap(), qual);
+    }
+
+    let mut group = c.benchmark_group("consensus");
+    group.sample_size(10);
+    group.bench_function("fixed_array", |b| {
+        b.iter(|| {
+            for records in &same_length {
+                let _ = consensus(strategy, ConsensusInput::Fastq(records));
+            }
+        })
+    });
+    group.bench_function("per_column_hashmap", |b| {
+        b.iter(|| {
+            for records in &same_length {
+                let _ = per_column_hashmap_consensus(records, params);
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, consensus_benchmark);
+criterion_main!(benches);
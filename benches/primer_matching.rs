@@ -0,0 +1,63 @@
+// Benchmark of the primer matching methods of filter_r1_r2_pairs
+// The pairs of the HIV DR control library (tests/data/hivdr_control) are filtered with the v1 DR params,
+// with every region using the fixed window method, then the alignment method.
+// Run with `cargo bench --bench primer_matching`.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+
+use virust_tcs::helper::io::{FASTQ_CHUNK_SIZE, PairedFastqReader};
+use virust_tcs::helper::params::{Params, PrimerMatchingMethod, ValidatedParams};
+use virust_tcs::helper::tcs_helper::{
+    PairedRecordFilterResult, filter_r1_r2_pairs, validate_files,
+};
+
+fn validated_params(method: PrimerMatchingMethod) -> ValidatedParams {
+    let mut params = Params::from_preset("v1").unwrap();
+    for region_params in params.primer_pairs.iter_mut() {
+        region_params.primer_matching = Some(method);
+    }
+    params.validate().unwrap()
+}
+
+fn primer_matching_benchmark(c: &mut Criterion) {
+    let fastq_files = validate_files("tests/data/hivdr_control").unwrap();
+    let pairs = PairedFastqReader::from_files(&fastq_files)
+        .unwrap()
+        .read_chunk(FASTQ_CHUNK_SIZE);
+
+    let mut group = c.benchmark_group("filter_r1_r2_pairs");
+    group.sample_size(10);
+    for (name, method) in [
+        ("fixed_window", PrimerMatchingMethod::FixedWindow),
+        ("alignment", PrimerMatchingMethod::Alignment),
+    ] {
+        let params = validated_params(method);
+        let valid_pairs = pairs
+            .iter()
+            .filter(|(r1, r2)| {
+                matches!(
+                    filter_r1_r2_pairs(r1, r2, &params),
+                    Ok(PairedRecordFilterResult::Valid(_))
+                )
+            })
+            .count();
+        println!(
+            "{}: {} of {} pairs matched a region",
+            name,
+            valid_pairs,
+            pairs.len()
+        );
+
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                for (r1, r2) in &pairs {
+                    let _ = filter_r1_r2_pairs(r1, r2, &params);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, primer_matching_benchmark);
+criterion_main!(benches);
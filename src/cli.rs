@@ -2,6 +2,8 @@ use clap::builder::styling::{AnsiColor, Color};
 use clap::builder::styling::{Style, Styles};
 use clap::{ColorChoice, Parser, Subcommand};
 
+use crate::helper::consensus::ConsensusMethod;
+
 pub const BANNER: &str = "\x1b[0;91m████████  ██████ ███████     ██████  ██ ██████  ███████ ██      ██ ███    ██ ███████\x1b[0m\n\
                           \x1b[0;93m   ██    ██      ██          ██   ██ ██ ██   ██ ██      ██      ██ ████   ██ ██\x1b[0m\n\
                           \x1b[0;92m   ██    ██      ███████     ██████  ██ ██████  █████   ██      ██ ██ ██  ██ █████\x1b[0m\n\
@@ -30,6 +32,10 @@ pub enum Commands {
         #[arg(short, long)]
         input: String,
 
+        /// Output directory path, the input directory is not modified if given
+        #[arg(short, long)]
+        output: Option<String>,
+
         /// param file path
         #[arg(short, long)]
         param: String,
@@ -47,6 +53,23 @@ pub enum Commands {
         /// Default at 30
         #[arg(long, default_value_t = 30)]
         midpoint: u8,
+
+        /// Consensus method of the regions without a consensus strategy in the param file,
+        /// `bayesian` uses the platform error rate of the param file as the prior
+        #[arg(long, value_enum, default_value_t = ConsensusMethod::Weighted)]
+        consensus_method: ConsensusMethod,
+
+        /// Resume from the checkpoints of a previous interrupted run with the same input files and params
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+
+        /// Batch mode, the input directory is a batch directory, each subdirectory is one library
+        #[arg(long, default_value_t = false)]
+        batch: bool,
+
+        /// Number of libraries processed at the same time in batch mode
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
     },
 
     /// Generate a param file through CLI
@@ -55,10 +78,14 @@ pub enum Commands {
 
     /// Run the TCS HIV-1 DR Pipeline,
     DR {
-        /// Input directory path
+        /// Batch directory path, each subdirectory is one library
         #[arg(short, long)]
         input: String,
 
+        /// Output directory path, the input directory is not modified if given
+        #[arg(short, long)]
+        output: Option<String>,
+
         /// DR version number
         #[arg(short, long, default_value_t = String::from("v1"))]
         version: String,
@@ -66,6 +93,14 @@ pub enum Commands {
         /// keep original files
         #[arg(long, default_value_t = false)]
         keep_original: bool,
+
+        /// Resume from the checkpoints of a previous interrupted run with the same input files
+        #[arg(long, default_value_t = false)]
+        resume: bool,
+
+        /// Number of libraries processed at the same time
+        #[arg(short, long, default_value_t = 1)]
+        jobs: usize,
     },
 
     /// List param for the DR pipeline, w/o aurguments it will list all available version numbers.
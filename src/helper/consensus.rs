@@ -1,16 +1,22 @@
 use std::collections::HashMap;
 use std::error::Error;
 
+use bio::alignment::AlignmentOperation;
+use bio::alignment::pairwise::banded;
 use bio::io::fasta;
 use bio::io::fastq;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::helper::tcs_helper::utils::get_iupac_code;
+
 // MARK: ConsensusParams
 /// Consensus parameters for the consensus function.
 /// The `k` parameter controls the steepness of the logistic curve.
 /// The `q0` parameter controls the horizontal shift of the curve.
 /// These parameters are used to adjust the confidence level of the consensus base.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ConsensusParams {
     k: f64,
     q0: f64,
@@ -54,11 +60,55 @@ impl ConsensusParams {
 /// The `Weighted` variant uses a logistic function to adjust the confidence level based on quality scores.
 /// The `Supermajority` variant uses a super-majority cutoff.
 /// The `SimpleMajority` variant uses a simple majority rule.
-#[derive(Debug, Copy, Clone)]
+/// The `Bayesian` variant computes the posterior probability of each base from the Phred error probabilities of the reads,
+/// with the platform error rate (e.g. 0.02) as the prior probability of an error before sequencing.
+/// In param files, the strategy is written as `{"Weighted": {"k": 0.2, "q0": 30.0}}`, `{"Supermajority": 0.7}`, `"SimpleMajority"`
+/// or `{"Bayesian": 0.02}`.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub enum ConsensusStrategy {
     Weighted(ConsensusParams),
     Supermajority(f64),
     SimpleMajority,
+    Bayesian(f64),
+}
+
+// MARK: ConsensusMethod
+/// Quality-aware consensus method selected from the command line, used for the regions without their own strategy in the params.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+pub enum ConsensusMethod {
+    /// Logistic-transformed quality weights, with the steepness and midpoint parameters.
+    #[default]
+    Weighted,
+    /// Posterior probability from the Phred error probabilities, with the platform error rate of the region as the prior.
+    Bayesian,
+}
+
+impl ConsensusMethod {
+    /// The consensus strategy of the method for a region with `platform_error_rate`.
+    pub fn strategy(
+        &self,
+        steepness: f64,
+        midpoint: f64,
+        platform_error_rate: f64,
+    ) -> ConsensusStrategy {
+        match self {
+            ConsensusMethod::Weighted => {
+                ConsensusStrategy::Weighted(ConsensusParams::new(steepness, midpoint))
+            }
+            ConsensusMethod::Bayesian => ConsensusStrategy::Bayesian(platform_error_rate),
+        }
+    }
+}
+
+// MARK: UnequalLengthConsensus
+/// How the consensus of records of different lengths (e.g. a UMI family with a homopolymer indel) is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum UnequalLengthConsensus {
+    /// The consensus fails with `ConsensusError::InvalidSequenceLength`, as in the original TCS pipeline.
+    #[default]
+    Reject,
+    /// The records are aligned to the modal-length consensus before the column consensus, see `consensus_aligned`.
+    Align,
 }
 
 // MARK: ConsensusInput
@@ -91,7 +141,7 @@ pub enum ConsensusError {
     InvalidRecordsNumber(usize),
     #[error("All sequences must be the same length")]
     InvalidSequenceLength,
-    #[error("Missing quality scores for the Weighted strategy")]
+    #[error("Missing quality scores for the Weighted or Bayesian strategy")]
     MissingQualityScores,
 }
 
@@ -189,14 +239,12 @@ pub fn consensus(
     input: ConsensusInput,
 ) -> Result<ConsensusResult, Box<dyn Error + Send + Sync>> {
     // Extract sequence and (optionally) qualities by input type
-    let (seqs, quals_opt): (Vec<Vec<u8>>, Option<Vec<Vec<u8>>>) = match input {
+    let (seqs, quals_opt): (Vec<&[u8]>, Option<Vec<&[u8]>>) = match input {
         ConsensusInput::Fastq(records) => (
-            records.iter().map(|r| r.seq().to_vec()).collect(),
-            Some(records.iter().map(|r| r.qual().to_vec()).collect()),
+            records.iter().map(|r| r.seq()).collect(),
+            Some(records.iter().map(|r| r.qual()).collect()),
         ),
-        ConsensusInput::Fasta(records) => {
-            (records.iter().map(|r| r.seq().to_vec()).collect(), None)
-        }
+        ConsensusInput::Fasta(records) => (records.iter().map(|r| r.seq()).collect(), None),
     };
 
     let n_records = seqs.len();
@@ -209,56 +257,474 @@ pub fn consensus(
     if !seqs.iter().all(|r| r.len() == seq_len) {
         return Err(ConsensusError::InvalidSequenceLength.into());
     }
+    let quality_required = matches!(
+        strategy,
+        ConsensusStrategy::Weighted(_) | ConsensusStrategy::Bayesian(_)
+    );
+    if seq_len > 0 && quality_required && quals_opt.is_none() {
+        return Err(ConsensusError::MissingQualityScores.into());
+    }
+
+    // logistic weight of each Phred+33 quality character, computed once per family
+    let weight_table = match &strategy {
+        ConsensusStrategy::Weighted(params) => Some(logistic_weight_table(params.k, params.q0)),
+        _ => None,
+    };
+
     let mut consensus = Vec::with_capacity(seq_len);
     let mut consensus_quals = Vec::with_capacity(seq_len);
 
     for i in 0..seq_len {
-        let bases = seqs.iter().map(|r| r[i]).collect::<Vec<u8>>();
+        let tally = match &strategy {
+            ConsensusStrategy::Bayesian(_) => None,
+            _ => ColumnTally::from_column(&seqs, quals_opt.as_deref(), weight_table.as_ref(), i),
+        };
+        let Some(tally) = tally else {
+            // Bayesian strategy, or a column with a base outside of the tally (e.g. an IUPAC code)
+            let (base, qual) = consensus_column_fallback(&strategy, &seqs, quals_opt.as_deref(), i);
+            consensus.push(base);
+            if let Some(qual) = qual {
+                consensus_quals.push(qual);
+            }
+            continue;
+        };
 
         match &strategy {
-            ConsensusStrategy::Weighted(params) => {
-                // need qualities for this strategy
-                if let Some(quals) = &quals_opt {
-                    let col_quals = quals.iter().map(|r| r[i]).collect::<Vec<u8>>();
-                    match consensus_base_column_with_quality(
-                        &bases, &col_quals, params.k, params.q0,
-                    ) {
-                        Some((base, qual)) => {
-                            consensus.push(base);
-                            consensus_quals.push(qual);
-                        }
-                        None => {
-                            consensus.push(b'N');
-                            consensus_quals.push(b'!');
-                        }
-                    }
-                } else {
-                    return Err(ConsensusError::MissingQualityScores.into());
-                }
+            ConsensusStrategy::Weighted(_) => {
+                let (base, qual) = tally.weighted_base();
+                consensus.push(base);
+                consensus_quals.push(qual);
             }
             ConsensusStrategy::Supermajority(cutoff) => {
                 // Ensure cutoff is within valid range, but won't throw an error, force it to be between 0.5 and 1.0
                 let cutoff = cutoff.max(0.5);
                 let cutoff = cutoff.min(1.0);
-                let base = consensus_base_supermajority(&bases, cutoff);
+                let base = tally.supermajority_base(n_records, cutoff);
                 consensus.push(base);
+                if quals_opt.is_some() {
+                    consensus_quals.push(tally.supporting_quality(base));
+                }
             }
             ConsensusStrategy::SimpleMajority => {
-                let base = consensus_base_simply_majority(&bases);
+                let base = tally.simple_majority_base();
                 consensus.push(base);
+                if quals_opt.is_some() {
+                    consensus_quals.push(tally.supporting_quality(base));
+                }
             }
+            ConsensusStrategy::Bayesian(_) => unreachable!("the Bayesian strategy has no tally"),
         }
     }
 
     Ok(ConsensusResult {
         seq: consensus,
-        qual: match strategy {
-            ConsensusStrategy::Weighted(_) => Some(consensus_quals),
-            _ => None,
-        },
+        qual: quals_opt.map(|_| consensus_quals),
     })
 }
 
+// MARK: ColumnTally
+/// Bases of the column tally, in the order of the tally arrays.
+const TALLY_BASES: [u8; 6] = *b"ACGTN-";
+
+fn tally_index(base: u8) -> Option<usize> {
+    match base {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        b'N' => Some(4),
+        b'-' => Some(5),
+        _ => None,
+    }
+}
+
+/// Logistic weight (see `logistic_quality_prob`) of each Phred+33 quality character.
+fn logistic_weight_table(k: f64, q0: f64) -> [f64; 256] {
+    let mut table = [0.0; 256];
+    for (qual_char, weight) in table.iter_mut().enumerate() {
+        *weight = logistic_quality_prob((qual_char as u8).wrapping_sub(33) as f64, k, q0);
+    }
+    table
+}
+
+/// Tally of one column over A, C, G, T, N and gap: the number of reads, the summed logistic weights (Weighted strategy)
+/// and the highest quality (FASTQ) of each base.
+/// The consensus of a tally is the same as the consensus of the per-column functions
+/// (`consensus_base_column_with_quality`, `consensus_base_supermajority`, `consensus_base_simply_majority`).
+#[derive(Debug, Default)]
+struct ColumnTally {
+    counts: [usize; 6],
+    weights: [f64; 6],
+    max_quals: [u8; 6],
+}
+
+impl ColumnTally {
+    /// Tallies column `i` in one pass over the reads.
+    /// Returns `None` if a read has a base outside of the tally, the column then goes through `consensus_column_fallback`.
+    fn from_column(
+        seqs: &[&[u8]],
+        quals: Option<&[&[u8]]>,
+        weight_table: Option<&[f64; 256]>,
+        i: usize,
+    ) -> Option<Self> {
+        let mut tally = ColumnTally::default();
+        for (r, seq) in seqs.iter().enumerate() {
+            let j = tally_index(seq[i])?;
+            tally.counts[j] += 1;
+            if let Some(quals) = quals {
+                let qual = quals[r][i];
+                tally.max_quals[j] = tally.max_quals[j].max(qual);
+                if let Some(weight_table) = weight_table {
+                    tally.weights[j] += weight_table[qual as usize];
+                }
+            }
+        }
+        Some(tally)
+    }
+
+    fn present(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..TALLY_BASES.len()).filter(|&j| self.counts[j] > 0)
+    }
+
+    /// Same as `consensus_base_column_with_quality`.
+    fn weighted_base(&self) -> (u8, u8) {
+        let max_weight = self
+            .present()
+            .map(|j| self.weights[j])
+            .fold(f64::NEG_INFINITY, f64::max);
+        let mut top_bases = self
+            .present()
+            .filter(|&j| (self.weights[j] - max_weight).abs() < 1e-6);
+        let (Some(top), None) = (top_bases.next(), top_bases.next()) else {
+            return (b'N', b'!');
+        };
+
+        let total_weight: f64 = self.present().map(|j| self.weights[j]).sum();
+        let p_error = if total_weight > 0.0 {
+            1.0 - (max_weight / total_weight)
+        } else {
+            1.0
+        };
+        let p_error = p_error.max(1e-10);
+        let q_consensus = (-10.0 * p_error.log10()).min(60.0);
+        (TALLY_BASES[top], q_consensus.round() as u8 + 33)
+    }
+
+    /// Same as `consensus_base_supermajority`.
+    fn supermajority_base(&self, total: usize, cutoff: f64) -> u8 {
+        self.present()
+            .find(|&j| (self.counts[j] as f64) / (total as f64) > cutoff)
+            .map_or(b'N', |j| TALLY_BASES[j])
+    }
+
+    /// Same as `consensus_base_simply_majority`.
+    fn simple_majority_base(&self) -> u8 {
+        let max_count = self.counts.iter().copied().max().unwrap_or(0);
+        let mut top_bases = self.present().filter(|&j| self.counts[j] == max_count);
+        match (top_bases.next(), top_bases.next()) {
+            (Some(top), None) => TALLY_BASES[top],
+            _ => b'N',
+        }
+    }
+
+    /// Same as `supporting_quality`: the highest quality of the reads supporting the consensus base, `!` for `N`.
+    fn supporting_quality(&self, consensus_base: u8) -> u8 {
+        match tally_index(consensus_base) {
+            Some(j) if consensus_base != b'N' && self.counts[j] > 0 => self.max_quals[j],
+            _ => b'!',
+        }
+    }
+}
+
+/// Consensus base (and quality for FASTQ) of column `i` with the per-column functions,
+/// for the Bayesian strategy and the columns with bases outside of the `ColumnTally`.
+fn consensus_column_fallback(
+    strategy: &ConsensusStrategy,
+    seqs: &[&[u8]],
+    quals: Option<&[&[u8]]>,
+    i: usize,
+) -> (u8, Option<u8>) {
+    let bases = seqs.iter().map(|r| r[i]).collect::<Vec<u8>>();
+    let col_quals = quals.map(|quals| quals.iter().map(|r| r[i]).collect::<Vec<u8>>());
+    let with_supporting_quality = |base: u8| {
+        let qual = col_quals
+            .as_ref()
+            .map(|col_quals| supporting_quality(&bases, col_quals, base));
+        (base, qual)
+    };
+    match strategy {
+        ConsensusStrategy::Weighted(params) => {
+            let col_quals = col_quals.as_deref().unwrap_or_default();
+            let (base, qual) =
+                consensus_base_column_with_quality(&bases, col_quals, params.k, params.q0)
+                    .unwrap_or((b'N', b'!'));
+            (base, Some(qual))
+        }
+        ConsensusStrategy::Supermajority(cutoff) => {
+            with_supporting_quality(consensus_base_supermajority(&bases, cutoff.clamp(0.5, 1.0)))
+        }
+        ConsensusStrategy::SimpleMajority => {
+            with_supporting_quality(consensus_base_simply_majority(&bases))
+        }
+        ConsensusStrategy::Bayesian(platform_error_rate) => {
+            let col_quals = col_quals.as_deref().unwrap_or_default();
+            let (base, qual) =
+                consensus_base_column_bayesian(&bases, col_quals, *platform_error_rate);
+            (base, Some(qual))
+        }
+    }
+}
+
+// MARK: Aligned consensus
+/// Gap penalties of the alignment of a record to the modal-length reference in `consensus_aligned`,
+/// a 1-base indel (-6) is preferred over 4 or more mismatches (-8) in a homopolymer.
+const ALIGNMENT_GAP_OPEN: i32 = -5;
+const ALIGNMENT_GAP_EXTEND: i32 = -1;
+/// K-mer length of the seeds of the banded alignment in `consensus_aligned`.
+const ALIGNMENT_KMER_LENGTH: usize = 8;
+/// Bases added to the length difference of the record and the reference for the band width of the alignment.
+const ALIGNMENT_BAND_PADDING: usize = 4;
+
+/// Consensus of records of different lengths.
+/// Records of the same length go straight to `consensus`. Otherwise the records are projected onto a common frame
+/// with `project_records`, the column consensus of the projected records is computed with `strategy`,
+/// and the columns where the gap wins are removed.
+///
+/// The consensus of a family with a homopolymer indel in some of its reads is the sequence of the majority of the reads.
+pub fn consensus_aligned(
+    strategy: ConsensusStrategy,
+    input: ConsensusInput,
+) -> Result<ConsensusResult, Box<dyn Error + Send + Sync>> {
+    let records: Vec<(&[u8], Option<&[u8]>)> = match &input {
+        ConsensusInput::Fastq(records) => {
+            records.iter().map(|r| (r.seq(), Some(r.qual()))).collect()
+        }
+        ConsensusInput::Fasta(records) => records.iter().map(|r| (r.seq(), None)).collect(),
+    };
+    if records.len() < 2
+        || records
+            .iter()
+            .all(|(seq, _)| seq.len() == records[0].0.len())
+    {
+        return consensus(strategy, input);
+    }
+
+    let aligned_records = project_records(strategy, &records)?;
+    let aligned_consensus = column_consensus(strategy, &aligned_records)?;
+
+    let kept = aligned_consensus
+        .seq
+        .iter()
+        .map(|&base| base != b'-')
+        .collect::<Vec<bool>>();
+    Ok(ConsensusResult {
+        seq: aligned_consensus
+            .seq
+            .iter()
+            .zip(&kept)
+            .filter(|&(_, &keep)| keep)
+            .map(|(&base, _)| base)
+            .collect(),
+        qual: aligned_consensus.qual.map(|qual| {
+            qual.iter()
+                .zip(&kept)
+                .filter(|&(_, &keep)| keep)
+                .map(|(&q, _)| q)
+                .collect()
+        }),
+    })
+}
+
+/// Projects FASTQ records of different lengths onto a common frame, as for `consensus_aligned` (see `project_records`),
+/// so that they can be compared position by position (e.g. for the heterogeneity of a UMI family).
+/// Records of the same length are returned unchanged.
+pub fn align_fastq_records(
+    strategy: ConsensusStrategy,
+    records: &[fastq::Record],
+) -> Result<Vec<fastq::Record>, Box<dyn Error + Send + Sync>> {
+    if records
+        .iter()
+        .all(|r| r.seq().len() == records[0].seq().len())
+    {
+        return Ok(records.to_vec());
+    }
+    let seqs: Vec<(&[u8], Option<&[u8]>)> =
+        records.iter().map(|r| (r.seq(), Some(r.qual()))).collect();
+    Ok(project_records(strategy, &seqs)?
+        .into_iter()
+        .zip(records)
+        .map(|((seq, qual), record)| {
+            fastq::Record::with_attrs(record.id(), record.desc(), &seq, &qual.unwrap())
+        })
+        .collect())
+}
+
+/// Sequence and qualities (for FASTQ) of a projected record.
+type OwnedRecord = (Vec<u8>, Option<Vec<u8>>);
+
+/// Projects records (with qualities for FASTQ) of different lengths onto the coordinates of a reference,
+/// the consensus (with `strategy`) of the records of the modal length (the most frequent, the longest on a tie),
+/// or the modal-length record if it is the only one of its length.
+/// Each record of another length is globally aligned to the reference (banded around the k-mer matches, a few bases wider
+/// than the length difference), its inserted bases are dropped
+/// and its deleted bases are filled with a gap (`-`) with the quality of the preceding base.
+fn project_records(
+    strategy: ConsensusStrategy,
+    records: &[(&[u8], Option<&[u8]>)],
+) -> Result<Vec<OwnedRecord>, Box<dyn Error + Send + Sync>> {
+    let mut length_counts: HashMap<usize, usize> = HashMap::new();
+    for (seq, _) in records {
+        *length_counts.entry(seq.len()).or_insert(0) += 1;
+    }
+    let (modal_length, modal_count) = length_counts
+        .into_iter()
+        .max_by_key(|&(length, count)| (count, length))
+        .unwrap();
+    let modal_records = records
+        .iter()
+        .filter(|(seq, _)| seq.len() == modal_length)
+        .map(|(seq, qual)| (seq.to_vec(), qual.map(|q| q.to_vec())))
+        .collect::<Vec<_>>();
+    let reference = if modal_count > 1 {
+        column_consensus(strategy, &modal_records)?.seq
+    } else {
+        modal_records[0].0.clone()
+    };
+
+    Ok(records
+        .iter()
+        .map(|&(seq, qual)| {
+            if seq.len() == modal_length {
+                (seq.to_vec(), qual.map(|q| q.to_vec()))
+            } else {
+                align_to_reference(seq, qual, &reference)
+            }
+        })
+        .collect())
+}
+
+/// Column consensus of owned sequences (with qualities for FASTQ) of the same length.
+fn column_consensus(
+    strategy: ConsensusStrategy,
+    records: &[(Vec<u8>, Option<Vec<u8>>)],
+) -> Result<ConsensusResult, Box<dyn Error + Send + Sync>> {
+    if records.iter().all(|(_, qual)| qual.is_some()) {
+        let fastq_records = records
+            .iter()
+            .map(|(seq, qual)| {
+                fastq::Record::with_attrs("aligned", None, seq, qual.as_ref().unwrap())
+            })
+            .collect::<Vec<_>>();
+        consensus(strategy, ConsensusInput::Fastq(&fastq_records))
+    } else {
+        let fasta_records = records
+            .iter()
+            .map(|(seq, _)| fasta::Record::with_attrs("aligned", None, seq))
+            .collect::<Vec<_>>();
+        consensus(strategy, ConsensusInput::Fasta(&fasta_records))
+    }
+}
+
+/// Projects a record onto the coordinates of the reference with a banded global alignment.
+/// The records of a UMI family differ by a few indels, so the band only needs to be a few bases wider than their length difference.
+/// Inserted bases of the record are dropped, deleted bases are filled with `-` and the quality of the preceding base.
+fn align_to_reference(
+    seq: &[u8],
+    qual: Option<&[u8]>,
+    reference: &[u8],
+) -> (Vec<u8>, Option<Vec<u8>>) {
+    let score = |a: u8, b: u8| if a == b { 1i32 } else { -1i32 };
+    let mut aligner = banded::Aligner::with_capacity(
+        seq.len(),
+        reference.len(),
+        ALIGNMENT_GAP_OPEN,
+        ALIGNMENT_GAP_EXTEND,
+        score,
+        ALIGNMENT_KMER_LENGTH,
+        seq.len().abs_diff(reference.len()) + ALIGNMENT_BAND_PADDING,
+    );
+    let alignment = aligner.global(seq, reference);
+
+    let mut aligned_seq = Vec::with_capacity(reference.len());
+    let mut aligned_qual = Vec::with_capacity(reference.len());
+    let mut i = 0;
+    for operation in &alignment.operations {
+        match operation {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                aligned_seq.push(seq[i]);
+                if let Some(qual) = qual {
+                    aligned_qual.push(qual[i]);
+                }
+                i += 1;
+            }
+            AlignmentOperation::Ins => i += 1,
+            AlignmentOperation::Del => {
+                aligned_seq.push(b'-');
+                if let Some(qual) = qual {
+                    let gap_qual = aligned_qual
+                        .last()
+                        .copied()
+                        .or(qual.get(i).copied())
+                        .unwrap_or(b'!');
+                    aligned_qual.push(gap_qual);
+                }
+            }
+            AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {}
+        }
+    }
+    (aligned_seq, qual.map(|_| aligned_qual))
+}
+
+// MARK: IUPAC consensus
+/// Consensus with IUPAC ambiguity codes at the mixed positions.
+/// The consensus is computed with `strategy` as in `consensus`, then each position where no base wins (`N`)
+/// gets the IUPAC code of the two or three bases each found in at least `min_share` of the records (see `mixed_base_iupac_code`).
+/// The quality of a mixed position stays the lowest quality (`!`), as for `N`.
+/// Used for the consensus of consensus (e.g. to find the overlap of R1 and R2) and for population-level consensus.
+pub fn consensus_iupac(
+    strategy: ConsensusStrategy,
+    input: ConsensusInput,
+    min_share: f64,
+) -> Result<ConsensusResult, Box<dyn Error + Send + Sync>> {
+    let seqs: Vec<&[u8]> = match &input {
+        ConsensusInput::Fastq(records) => records.iter().map(|r| r.seq()).collect(),
+        ConsensusInput::Fasta(records) => records.iter().map(|r| r.seq()).collect(),
+    };
+    let mut result = consensus(strategy, input.clone())?;
+    for (i, base) in result.seq.iter_mut().enumerate() {
+        if *base != b'N' {
+            continue;
+        }
+        let column = seqs.iter().map(|seq| seq[i]).collect::<Vec<u8>>();
+        if let Some(code) = mixed_base_iupac_code(&column, min_share) {
+            *base = code;
+        }
+    }
+    Ok(result)
+}
+
+/// IUPAC code of a mixed column, the code of the two or three bases (A, C, G, T) each found in at least `min_share` of the bases.
+/// Returns `None` if fewer than two or all four bases pass the share.
+pub fn mixed_base_iupac_code(bases: &[u8], min_share: f64) -> Option<u8> {
+    let mut counts: HashMap<u8, usize> = HashMap::new();
+    for &base in bases {
+        let base = base.to_ascii_uppercase();
+        if b"ACGT".contains(&base) {
+            *counts.entry(base).or_insert(0) += 1;
+        }
+    }
+    let mixed_bases = counts
+        .into_iter()
+        .filter(|&(_, count)| count as f64 / bases.len() as f64 >= min_share)
+        .map(|(base, _)| base as char)
+        .collect::<Vec<char>>();
+    if (2..=3).contains(&mixed_bases.len()) {
+        get_iupac_code(&mixed_bases).map(|code| code as u8)
+    } else {
+        None
+    }
+}
+
 // MARK: helper functions
 /// Computes a logistic-transformed probability from a Phred quality score.
 /// There is a graph in /resources that compares the original Phred quality score vs. logistic-transformed probability with differetn k and q0 values.
@@ -358,6 +824,82 @@ pub fn consensus_base_column_with_quality(
     }
 }
 
+/// Computes the consensus base and its posterior Phred+33 quality for one column.
+/// The likelihood of each candidate base (A, C, G, T and the other observed bases) is computed from the Phred error probabilities
+/// of the reads, an error being equally likely to give any other candidate, and gives the posterior of the template base of the reads
+/// with a uniform prior.
+/// The platform error rate is the prior probability of an error before sequencing (e.g. in the RT-PCR), the template base differing
+/// from the base of the molecule: the posterior of each candidate is `(1 - e) * p + e / (n - 1) * (1 - p)`,
+/// with `e` the platform error rate, `p` the posterior of the candidate as the template base and `n` the number of candidates.
+/// The consensus quality is the Phred-scaled posterior probability of the other candidates, capped at 60,
+/// so it can not be above the Phred-scaled platform error rate.
+/// Returns `N` with the lowest quality (`!`) for a tie or a column without informative bases (N in every read).
+pub fn consensus_base_column_bayesian(
+    bases: &[u8],
+    quals: &[u8],
+    platform_error_rate: f64,
+) -> (u8, u8) {
+    let mut candidates = b"ACGT".to_vec();
+    for &base in bases {
+        if base != b'N' && !candidates.contains(&base) {
+            candidates.push(base);
+        }
+    }
+    let other_candidates = (candidates.len() - 1) as f64;
+
+    let mut log_likelihoods = vec![0.0; candidates.len()];
+    let mut informative_reads = 0;
+    for (&base, &qual_char) in bases.iter().zip(quals.iter()) {
+        if base == b'N' {
+            continue;
+        }
+        informative_reads += 1;
+        let error =
+            (1.0 - phred_quality_prob(qual_char.saturating_sub(33) as f64)).clamp(1e-10, 0.75);
+        for (candidate, log_likelihood) in candidates.iter().zip(log_likelihoods.iter_mut()) {
+            *log_likelihood += if *candidate == base {
+                (1.0 - error).ln()
+            } else {
+                (error / other_candidates).ln()
+            };
+        }
+    }
+    if informative_reads == 0 {
+        return (b'N', b'!');
+    }
+
+    let max_log_likelihood = log_likelihoods
+        .iter()
+        .copied()
+        .fold(f64::NEG_INFINITY, f64::max);
+    let top_bases = log_likelihoods
+        .iter()
+        .filter(|&&l| (l - max_log_likelihood).abs() < 1e-9)
+        .count();
+    if top_bases > 1 {
+        return (b'N', b'!');
+    }
+
+    // posterior of the other candidates as the template base relative to the best one, summed without underflow
+    let best = log_likelihoods
+        .iter()
+        .position(|&l| l == max_log_likelihood)
+        .unwrap();
+    let other_odds: f64 = log_likelihoods
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != best)
+        .map(|(_, &l)| (l - max_log_likelihood).exp())
+        .sum();
+    let template_error = other_odds / (1.0 + other_odds);
+    // 1 - ((1 - e) * p + e / (n - 1) * (1 - p)), with p = 1 - template_error
+    let p_error = (template_error * (1.0 - platform_error_rate / other_candidates)
+        + platform_error_rate * (1.0 - template_error))
+        .max(1e-10);
+    let q_consensus = (-10.0 * p_error.log10()).min(60.0);
+    (candidates[best], q_consensus.round() as u8 + 33)
+}
+
 /// Compute consensus base at a position using a super-majority cutoff.
 pub fn consensus_base_supermajority(bases: &[u8], cutoff: f64) -> u8 {
     let mut counts: HashMap<u8, usize> = HashMap::new();
@@ -398,6 +940,21 @@ pub fn consensus_base_simply_majority(bases: &[u8]) -> u8 {
     }
 }
 
+/// Quality of a majority consensus base of a column for FASTQ input:
+/// the highest quality among the reads supporting the consensus base, `!` (Phred 0) for `N`.
+fn supporting_quality(bases: &[u8], col_quals: &[u8], consensus_base: u8) -> u8 {
+    if consensus_base == b'N' {
+        return b'!';
+    }
+    bases
+        .iter()
+        .zip(col_quals.iter())
+        .filter(|&(&base, _)| base == consensus_base)
+        .map(|(_, &qual)| qual)
+        .max()
+        .unwrap_or(b'!')
+}
+
 // MARK: Tests
 #[cfg(test)]
 mod tests {
@@ -418,6 +975,23 @@ mod tests {
         assert_eq!(consensus.seq, b"ACGT");
     }
 
+    #[test]
+    fn test_consensus_fastq_majority_quality() {
+        let records = vec![
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGT", b"I5II"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGT", b"5I5I"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGA", b"III5"),
+        ];
+        let input = ConsensusInput::Fastq(&records);
+        let result = consensus(ConsensusStrategy::Supermajority(0.6), input.clone()).unwrap();
+        assert_eq!(result.seq, b"ACGT");
+        assert_eq!(result.qual.unwrap(), b"IIII");
+
+        let result = consensus(ConsensusStrategy::Supermajority(0.7), input).unwrap();
+        assert_eq!(result.seq, b"ACGN");
+        assert_eq!(result.qual.unwrap(), b"III!");
+    }
+
     #[test]
     fn test_consensus_fastq_panic() {
         let records = vec![fastq::Record::with_attrs("SEQ_ID", None, b"ACGT", b"IIII")];
@@ -482,4 +1056,197 @@ mod tests {
         let consensus = consensus(strategy, input).unwrap();
         assert_eq!(consensus.seq, b"ACGN");
     }
+
+    #[test]
+    fn test_consensus_fastq_bayesian() {
+        let records = vec![
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTA", b"IIIII"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTC", b"IIII5"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGAC", b"III##"),
+        ];
+        let input = ConsensusInput::Fastq(&records);
+        let result = consensus(ConsensusStrategy::Bayesian(0.0), input.clone()).unwrap();
+        // a high quality A (Q40) outweighs a C of Q20 and a C of Q2
+        assert_eq!(result.seq, b"ACGTA");
+        let qual = result.qual.unwrap();
+        assert_eq!(&qual[..3], b"]]]");
+        assert!(qual[3] > qual[4]);
+
+        // with a platform error rate of 0.02 as the prior of an error before sequencing, the consensus quality is at most Q17
+        let result = consensus(ConsensusStrategy::Bayesian(0.02), input).unwrap();
+        assert_eq!(result.seq, b"ACGTA");
+        let qual = result.qual.unwrap();
+        assert_eq!(&qual[..3], b"222");
+        assert!(qual[3] > qual[4]);
+
+        let (base, qual) = consensus_base_column_bayesian(b"AC", b"II", 0.01);
+        assert_eq!((base, qual), (b'N', b'!'));
+        let (base, qual) = consensus_base_column_bayesian(b"NN", b"II", 0.01);
+        assert_eq!((base, qual), (b'N', b'!'));
+
+        let records = vec![
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGT"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGT"),
+        ];
+        let result = consensus(
+            ConsensusStrategy::Bayesian(0.01),
+            ConsensusInput::Fasta(&records),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_consensus_iupac() {
+        let records = vec![
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGA"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGA"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACTG"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACTG"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACTC"),
+        ];
+        let input = ConsensusInput::Fasta(&records);
+        let result = consensus(ConsensusStrategy::Supermajority(0.7), input.clone()).unwrap();
+        assert_eq!(result.seq, b"ACNN");
+
+        // G/T at 40/60 is K, A/G/C at 40/40/20 is V with a share of 0.2 and R with a share of 0.3
+        let result =
+            consensus_iupac(ConsensusStrategy::Supermajority(0.7), input.clone(), 0.2).unwrap();
+        assert_eq!(result.seq, b"ACKV");
+        let result = consensus_iupac(ConsensusStrategy::Supermajority(0.7), input, 0.3).unwrap();
+        assert_eq!(result.seq, b"ACKR");
+
+        let records = vec![
+            fastq::Record::with_attrs("SEQ_ID", None, b"AC", b"II"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"AT", b"II"),
+        ];
+        let input = ConsensusInput::Fastq(&records);
+        let result = consensus_iupac(ConsensusStrategy::SimpleMajority, input, 0.25).unwrap();
+        assert_eq!(result.seq, b"AY");
+        assert_eq!(result.qual.unwrap(), b"I!");
+
+        assert_eq!(mixed_base_iupac_code(b"ACGT", 0.2), None);
+        assert_eq!(mixed_base_iupac_code(b"AAAC", 0.3), None);
+    }
+
+    #[test]
+    fn test_align_to_reference() {
+        let fasta = fasta::Reader::from_file("tests/data/references/HXB2_PR.fasta").unwrap();
+        let reference = fasta.records().next().unwrap().unwrap().seq()[..250].to_vec();
+
+        // a read with a 2-base deletion and a read with a 1-base insertion in the middle
+        let deleted = [&reference[..120], &reference[122..]].concat();
+        let (aligned, qual) = align_to_reference(&deleted, None, &reference);
+        assert_eq!(aligned.len(), reference.len());
+        assert_eq!(aligned.iter().filter(|&&base| base == b'-').count(), 2);
+        assert!(qual.is_none());
+
+        let inserted = [&reference[..120], b"G", &reference[120..]].concat();
+        let qual = vec![b'I'; inserted.len()];
+        let (aligned, aligned_qual) = align_to_reference(&inserted, Some(&qual), &reference);
+        assert_eq!(aligned, reference);
+        assert_eq!(aligned_qual.unwrap().len(), reference.len());
+    }
+
+    #[test]
+    fn test_consensus_aligned() {
+        let records = vec![
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTTTTTACGT", b"IIIIIIIIIIII"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTTTTTACGT", b"IIIIIIIIIIII"),
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTTTTTACGT", b"IIIIIIIIIIII"),
+            // homopolymer deletion
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTTTTACGT", b"IIIIIIIIIII"),
+            // homopolymer insertion
+            fastq::Record::with_attrs("SEQ_ID", None, b"ACGTTTTTTACGT", b"IIIIIIIIIIIII"),
+        ];
+        let input = ConsensusInput::Fastq(&records);
+        assert!(consensus(ConsensusStrategy::SimpleMajority, input.clone()).is_err());
+
+        let result = consensus_aligned(ConsensusStrategy::SimpleMajority, input.clone()).unwrap();
+        assert_eq!(result.seq, b"ACGTTTTTACGT");
+        assert_eq!(result.qual.unwrap().len(), 12);
+        let result = consensus_aligned(
+            ConsensusStrategy::Weighted(ConsensusParams::default()),
+            input,
+        )
+        .unwrap();
+        assert_eq!(result.seq, b"ACGTTTTTACGT");
+
+        // the majority of the reads has the deletion
+        let records = vec![
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGTTTTACGT"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGTTTTACGT"),
+            fasta::Record::with_attrs("SEQ_ID", None, b"ACGTTTTTACGT"),
+        ];
+        let result = consensus_aligned(
+            ConsensusStrategy::SimpleMajority,
+            ConsensusInput::Fasta(&records),
+        )
+        .unwrap();
+        assert_eq!(result.seq, b"ACGTTTTACGT");
+        assert!(result.qual.is_none());
+    }
+
+    #[test]
+    fn test_consensus_tally_matches_per_column_functions() {
+        use rand::prelude::*;
+        use rand_chacha::ChaCha8Rng;
+
+        // mostly one template, with errors, Ns, gaps, IUPAC codes and ties across 6 reads
+        let mut rng = ChaCha8Rng::seed_from_u64(20);
+        let template = (0..200)
+            .map(|_| *b"ACGT".choose(&mut rng).unwrap())
+            .collect::<Vec<u8>>();
+        let records = (0..6)
+            .map(|_| {
+                let seq = template
+                    .iter()
+                    .map(|&base| {
+                        if rng.random_bool(0.3) {
+                            *b"ACGTN-R".choose(&mut rng).unwrap()
+                        } else {
+                            base
+                        }
+                    })
+                    .collect::<Vec<u8>>();
+                let qual = (0..seq.len())
+                    .map(|_| rng.random_range(35..74))
+                    .collect::<Vec<u8>>();
+                fastq::Record::with_attrs("SEQ_ID", None, &seq, &qual)
+            })
+            .collect::<Vec<_>>();
+
+        for strategy in [
+            ConsensusStrategy::Weighted(ConsensusParams::default()),
+            ConsensusStrategy::Supermajority(0.6),
+            ConsensusStrategy::SimpleMajority,
+        ] {
+            let result = consensus(strategy, ConsensusInput::Fastq(&records)).unwrap();
+            let qual = result.qual.unwrap();
+            for i in 0..template.len() {
+                let bases = records.iter().map(|r| r.seq()[i]).collect::<Vec<u8>>();
+                let quals = records.iter().map(|r| r.qual()[i]).collect::<Vec<u8>>();
+                let expected = match strategy {
+                    ConsensusStrategy::Weighted(params) => {
+                        consensus_base_column_with_quality(&bases, &quals, params.k, params.q0)
+                            .unwrap()
+                    }
+                    ConsensusStrategy::Supermajority(cutoff) => {
+                        let base = consensus_base_supermajority(&bases, cutoff);
+                        (base, supporting_quality(&bases, &quals, base))
+                    }
+                    _ => {
+                        let base = consensus_base_simply_majority(&bases);
+                        (base, supporting_quality(&bases, &quals, base))
+                    }
+                };
+                assert_eq!(
+                    (result.seq[i], qual[i]),
+                    expected,
+                    "{:?} column {}",
+                    strategy,
+                    i
+                );
+            }
+        }
+    }
 }
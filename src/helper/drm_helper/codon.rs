@@ -0,0 +1,47 @@
+// Codon translation used by the SDRM pipeline
+// The standard genetic code is stored as a 64-character table ordered by A, C, G, T at each codon position,
+// so that the index of a codon is 16 * first + 4 * second + third.
+
+const STANDARD_CODE: &[u8; 64] =
+    b"KNKNTTTTRSRSIIMIQHQHPPPPRRRRLLLLEDEDAAAAGGGGVVVV*Y*YSSSS*CWCLFLF";
+
+fn base_index(base: u8) -> Option<usize> {
+    match base.to_ascii_uppercase() {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' | b'U' => Some(3),
+        _ => None,
+    }
+}
+
+/// Translates one codon into its one-letter amino acid code with the standard genetic code.
+/// Stop codons are returned as `*`.
+/// Returns `None` if the codon is not exactly 3 bases long or contains gaps or ambiguity codes.
+pub fn translate_codon(codon: &[u8]) -> Option<u8> {
+    if codon.len() != 3 {
+        return None;
+    }
+    let mut index = 0;
+    for &base in codon {
+        index = index * 4 + base_index(base)?;
+    }
+    Some(STANDARD_CODE[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate_codon() {
+        assert_eq!(translate_codon(b"ATG"), Some(b'M'));
+        assert_eq!(translate_codon(b"gat"), Some(b'D'));
+        assert_eq!(translate_codon(b"AAT"), Some(b'N'));
+        assert_eq!(translate_codon(b"TGG"), Some(b'W'));
+        assert_eq!(translate_codon(b"TAA"), Some(b'*'));
+        assert_eq!(translate_codon(b"GGN"), None);
+        assert_eq!(translate_codon(b"A-G"), None);
+        assert_eq!(translate_codon(b"AT"), None);
+    }
+}
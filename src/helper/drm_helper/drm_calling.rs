@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use getset::{Getters, Setters};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use statrs::distribution::{Beta, ContinuousCDF};
+use virust_locator::prelude::*;
+
+use crate::helper::drm_helper::{DrmRegionConfig, translate_codon};
+use crate::helper::tcs_helper::{QcAlgorithm, QcReference, TcsQcInput};
+
+// DRM calling for the SDRM pipeline
+// Each TCS is located on the reference of the DRM version (HXB2 for HIV-1), the codons at the surveillance DRM positions are translated,
+// and every observed DRM is reported with its frequency among the TCS covering that position,
+// together with an exact (Clopper-Pearson) binomial confidence interval.
+
+/// Confidence level used for the binomial confidence interval of the DRM frequencies.
+pub const DRM_CONFIDENCE_LEVEL: f64 = 0.95;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct DrmCall {
+    #[getset(get = "pub", set = "pub")]
+    drm_class: String,
+    #[getset(get = "pub", set = "pub")]
+    position: u32,
+    #[getset(get = "pub", set = "pub")]
+    wild_type: String,
+    #[getset(get = "pub", set = "pub")]
+    mutation: String,
+    #[getset(get = "pub", set = "pub")]
+    count: usize,
+    // number of TCS with a valid codon at this position
+    #[getset(get = "pub", set = "pub")]
+    total: usize,
+    #[getset(get = "pub", set = "pub")]
+    frequency: f64,
+    #[getset(get = "pub", set = "pub")]
+    ci_lower: f64,
+    #[getset(get = "pub", set = "pub")]
+    ci_upper: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct SdrmRegionReport {
+    #[getset(get = "pub", set = "pub")]
+    region: String,
+    #[getset(get = "pub", set = "pub")]
+    drm_classes: Vec<String>,
+    #[getset(get = "pub", set = "pub")]
+    tcs_number: usize,
+    #[getset(get = "pub", set = "pub")]
+    located_tcs_number: usize,
+    #[getset(get = "pub", set = "pub")]
+    drm_calls: Vec<DrmCall>,
+}
+
+impl SdrmRegionReport {
+    pub fn new(region: &str) -> Self {
+        SdrmRegionReport {
+            region: region.to_string(),
+            drm_classes: Vec::new(),
+            tcs_number: 0,
+            located_tcs_number: 0,
+            drm_calls: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct SdrmReport {
+    #[getset(get = "pub", set = "pub")]
+    drm_version: String,
+    #[getset(get = "pub", set = "pub")]
+    input_directory: String,
+    #[getset(get = "pub", set = "pub")]
+    region_reports: Vec<SdrmRegionReport>,
+    #[getset(get = "pub")]
+    warnings: Vec<String>,
+}
+
+impl SdrmReport {
+    pub fn new(drm_version: &str, input_directory: &str) -> Self {
+        SdrmReport {
+            drm_version: drm_version.to_string(),
+            input_directory: input_directory.to_string(),
+            region_reports: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn add_region_report(&mut self, region_report: SdrmRegionReport) {
+        self.region_reports.push(region_report);
+    }
+
+    pub fn add_warning(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    pub fn to_csv_string(&self) -> Result<String, Box<dyn Error>> {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+
+        wtr.write_record([
+            "region",
+            "drm_class",
+            "position",
+            "wild_type",
+            "mutation",
+            "count",
+            "total",
+            "frequency",
+            "ci_lower",
+            "ci_upper",
+        ])?;
+
+        for region_report in &self.region_reports {
+            for call in &region_report.drm_calls {
+                wtr.write_record([
+                    region_report.region.clone(),
+                    call.drm_class.clone(),
+                    call.position.to_string(),
+                    call.wild_type.clone(),
+                    call.mutation.clone(),
+                    call.count.to_string(),
+                    call.total.to_string(),
+                    format!("{:.4}", call.frequency),
+                    format!("{:.4}", call.ci_lower),
+                    format!("{:.4}", call.ci_upper),
+                ])?;
+            }
+        }
+
+        wtr.flush()?;
+        let csv_string = String::from_utf8(wtr.into_inner()?)?;
+        Ok(csv_string)
+    }
+}
+
+/// Calls the surveillance DRMs of one region from its TCS.
+/// The TCS are located on the reference given by the `ref_info` of the region config,
+/// and only codons fully inside `seq_coord` (outside of its gap) are translated.
+/// Codons with gaps or ambiguous bases are not counted at that position.
+/// # Arguments
+/// * `config` - The `DrmRegionConfig` for the region.
+/// * `sequences` - The (joined, QC passed, and trimmed if required) TCS of the region.
+/// # Returns
+/// * `Result<SdrmRegionReport, Box<dyn Error + Send + Sync>>` - The DRMs observed in the region.
+pub fn call_drms(
+    config: &DrmRegionConfig,
+    sequences: &[&[u8]],
+) -> Result<SdrmRegionReport, Box<dyn Error + Send + Sync>> {
+    let region = config.region();
+    let mut report = SdrmRegionReport::new(region);
+    report.drm_classes = config.drm_classes().clone();
+    report.tcs_number = sequences.len();
+
+    if sequences.is_empty() {
+        return Ok(report);
+    }
+
+    let protein_start = config
+        .ref_info()
+        .ref_coord()
+        .get(region)
+        .ok_or(format!("Region name {} not found in ref_info", region))?[0];
+
+    let unique_sequences = sequences.iter().copied().unique().collect::<Vec<_>>();
+    let reference = QcReference::from_string(config.ref_info().ref_type()).ok_or(format!(
+        "Unknown reference genome {} in ref_info",
+        config.ref_info().ref_type()
+    ))?;
+    let qc_input = TcsQcInput::with_attrs(unique_sequences, reference, QcAlgorithm::SemiGlobal)
+        .ok_or("Failed to create TcsQcInput")?;
+    let located = qc_input.run_locator()?.results_map().to_owned();
+
+    let projections = sequences
+        .iter()
+        .filter_map(|seq| match located.get(seq) {
+            Some(Some(locator)) => Some(ReferenceProjection::from_locator(locator)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    report.located_tcs_number = projections.len();
+
+    for drm_class in config.drm_classes() {
+        let Some(mutations) = config.drm_list().get(drm_class) else {
+            continue;
+        };
+        for mutation in mutations {
+            let codon_start = protein_start + (mutation.position() - 1) * 3;
+            let codon_end = codon_start + 2;
+            if !config.seq_coord().covers(codon_start, codon_end) {
+                continue;
+            }
+
+            let mut aa_counts: HashMap<u8, usize> = HashMap::new();
+            let mut total = 0;
+            for projection in &projections {
+                if let Some(aa) = projection
+                    .codon_at(codon_start)
+                    .and_then(|codon| translate_codon(&codon))
+                {
+                    *aa_counts.entry(aa).or_insert(0) += 1;
+                    total += 1;
+                }
+            }
+
+            for drm in mutation.mutations() {
+                let count = aa_counts.get(&drm.as_bytes()[0]).copied().unwrap_or(0);
+                if count == 0 {
+                    continue;
+                }
+                let (ci_lower, ci_upper) =
+                    binomial_confidence_interval(count, total, DRM_CONFIDENCE_LEVEL);
+                report.drm_calls.push(DrmCall {
+                    drm_class: drm_class.clone(),
+                    position: *mutation.position(),
+                    wild_type: mutation.wild_type().clone(),
+                    mutation: drm.clone(),
+                    count,
+                    total,
+                    frequency: count as f64 / total as f64,
+                    ci_lower,
+                    ci_upper,
+                });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Exact (Clopper-Pearson) binomial confidence interval for `count` successes out of `total` trials.
+/// Returns `(0.0, 1.0)` if `total` is 0.
+pub fn binomial_confidence_interval(count: usize, total: usize, confidence: f64) -> (f64, f64) {
+    if total == 0 {
+        return (0.0, 1.0);
+    }
+    let alpha = 1.0 - confidence;
+    let x = count as f64;
+    let n = total as f64;
+
+    let lower = if count == 0 {
+        0.0
+    } else {
+        Beta::new(x, n - x + 1.0)
+            .map(|beta| beta.inverse_cdf(alpha / 2.0))
+            .unwrap_or(0.0)
+    };
+    let upper = if count >= total {
+        1.0
+    } else {
+        Beta::new(x + 1.0, n - x)
+            .map(|beta| beta.inverse_cdf(1.0 - alpha / 2.0))
+            .unwrap_or(1.0)
+    };
+    (lower, upper)
+}
+
+// query bases projected onto the reference coordinates of the locator, deletions are kept as '-', insertions are dropped.
+struct ReferenceProjection {
+    ref_start: u32,
+    bases: Vec<u8>,
+}
+
+impl ReferenceProjection {
+    fn from_locator(locator: &Locator) -> Self {
+        let bases = locator
+            .ref_aligned_string
+            .bytes()
+            .zip(locator.query_aligned_string.bytes())
+            .filter(|(r, _)| *r != b'-')
+            .map(|(_, q)| q)
+            .collect();
+        ReferenceProjection {
+            ref_start: locator.ref_start as u32,
+            bases,
+        }
+    }
+
+    fn codon_at(&self, ref_position: u32) -> Option<[u8; 3]> {
+        let offset = ref_position.checked_sub(self.ref_start)? as usize;
+        let codon = self.bases.get(offset..offset + 3)?;
+        Some([codon[0], codon[1], codon[2]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::drm_helper::{DrmList, DrmListTrait, DrmVersion};
+    use virust_locator::reference::retrieve_reference_sequence;
+
+    #[test]
+    fn test_binomial_confidence_interval() {
+        let (lower, upper) = binomial_confidence_interval(1, 5, 0.95);
+        assert!((lower - 0.00505).abs() < 1e-4);
+        assert!((upper - 0.71642).abs() < 1e-4);
+
+        let (lower, upper) = binomial_confidence_interval(0, 10, 0.95);
+        assert_eq!(lower, 0.0);
+        assert!((upper - 0.30850).abs() < 1e-4);
+
+        assert_eq!(binomial_confidence_interval(0, 0, 0.95), (0.0, 1.0));
+    }
+
+    #[test]
+    fn test_call_drms() {
+        let hxb2 = retrieve_reference_sequence("HXB2", "nt").unwrap().sequence;
+        // HIV-1 protease, HXB2 2253..2549
+        let wild_type = hxb2[2252..2549].to_vec();
+        let mut d30n = wild_type.clone();
+        // codon 30 of protease starts at 2253 + 29 * 3, GAT (D) -> AAT (N)
+        d30n[29 * 3] = b'A';
+
+        let sequences: Vec<&[u8]> = vec![&wild_type, &wild_type, &wild_type, &wild_type, &d30n];
+
+        let drm_version = DrmVersion::build_from_version("v1").unwrap();
+        let drm_list = DrmList::build().unwrap();
+        let config = DrmRegionConfig::from_drm_version(&drm_version, &drm_list, "PR").unwrap();
+
+        let report = call_drms(&config, &sequences).unwrap();
+        assert_eq!(*report.tcs_number(), 5);
+        assert_eq!(*report.located_tcs_number(), 5);
+        assert_eq!(report.drm_calls().len(), 1);
+
+        let call = &report.drm_calls()[0];
+        assert_eq!(call.drm_class(), "PI");
+        assert_eq!(*call.position(), 30);
+        assert_eq!(call.wild_type(), "D");
+        assert_eq!(call.mutation(), "N");
+        assert_eq!(*call.count(), 1);
+        assert_eq!(*call.total(), 5);
+        assert!((call.frequency() - 0.2).abs() < 1e-9);
+    }
+}
@@ -93,6 +93,19 @@ pub struct Coord {
     gap: Option<Gap>,
 }
 
+impl Coord {
+    /// Checks if the reference range `start..=end` is fully sequenced, i.e. within `minimum..=maximum` and not touching the gap.
+    pub fn covers(&self, start: u32, end: u32) -> bool {
+        if start < self.minimum || end > self.maximum {
+            return false;
+        }
+        match &self.gap {
+            Some(gap) => end < gap.minimum || start > gap.maximum,
+            None => true,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Getters, Setters, Serialize, Deserialize)]
 pub struct DrmRefInfo {
     #[getset(get = "pub", set = "pub")]
@@ -197,4 +210,18 @@ mod tests {
         dbg!(drm_non_exist.as_ref().err());
         assert!(drm_non_exist.is_err());
     }
+
+    #[test]
+    fn test_coord_covers() {
+        let drm_v1 = get_drm_version("v1").unwrap();
+        let rt = drm_v1.seq_coord().get("RT").unwrap();
+        assert!(rt.covers(2648, 2650));
+        assert!(!rt.covers(2646, 2648));
+        assert!(!rt.covers(2913, 2915));
+        assert!(rt.covers(3001, 3003));
+        assert!(!rt.covers(3256, 3258));
+
+        let pr = drm_v1.seq_coord().get("PR").unwrap();
+        assert!(pr.covers(2253, 2549));
+    }
 }
@@ -1,7 +1,11 @@
+pub mod codon;
+pub mod drm_calling;
 pub mod drm_list;
 pub mod drm_region_config;
 pub mod drm_version;
 
+pub use codon::*;
+pub use drm_calling::*;
 pub use drm_list::*;
 pub use drm_region_config::*;
 pub use drm_version::*;
@@ -3,16 +3,42 @@ use std::error::Error;
 use bio::io::fasta;
 use bio::io::fastq;
 use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use virust_locator::locator::Locator;
 
-pub const MIN_OVERLAP: usize = 10; // minimum overlap length, can be adjusted
-pub const ERROR_RATE_FOR_ENDJOINING: f64 = 0.02; // allowed error rate, can be adjusted
+use crate::helper::consensus::phred_quality_prob;
+use crate::helper::tcs_helper::utils::iupac_matches;
+
+pub const MIN_OVERLAP: usize = 10; // default minimum overlap length, can be adjusted per region
+pub const ERROR_RATE_FOR_ENDJOINING: f64 = 0.02; // default allowed error rate, can be adjusted per region
+pub const MAX_JOINED_QUALITY: u8 = 60; // cap of the Phred quality of the joined bases, as for the consensus
+
+/// Parameters of the overlap search of `find_best_overlap`.
+/// - `min_overlap`: The minimum length of the overlap required.
+/// - `error_rate`: The allowed error rate as a fraction of the overlap length.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OverlapSearchParams {
+    pub min_overlap: usize,
+    pub error_rate: f64,
+}
+
+impl Default for OverlapSearchParams {
+    /// `MIN_OVERLAP` and `ERROR_RATE_FOR_ENDJOINING`.
+    fn default() -> Self {
+        OverlapSearchParams {
+            min_overlap: MIN_OVERLAP,
+            error_rate: ERROR_RATE_FOR_ENDJOINING,
+        }
+    }
+}
 
 /// Strategy for joining two ends of sequences.
 /// This enum defines how the end joining should be performed based on the overlap information.
 /// - `Simple`: No overlap check, just concatenate the sequences.
 /// - `SimpleOverlap(usize)`: Join with a known overlap length as integer.
 /// - `Overlap(OverlapResult)`: Join with a full overlap pattern with an `OverlapResult`.
-/// - `UnknownOverlap`: Attempt to find the best overlap automatically.
+/// - `UnknownOverlap(OverlapSearchParams)`: Attempt to find the best overlap automatically with the given search parameters.
+/// - `ReferenceGuided(ReferencePlacement)`: Join by the reference coordinates of r1 and r2, aligned separately to the reference.
 /// The `Overlap` variant allows specifying a fixed overlap length, while `UnknownOverlap` will
 /// try to determine the best overlap based on the sequences provided.
 /// The `Simple` variant is useful when the sequences are known to be non-overlapping or when
@@ -28,7 +54,30 @@ pub enum EndJoiningStrategy {
     // joining with full overlap pattern.
     Overlap(OverlapResult),
     // unknown overlap, will try to find the best overlap
-    UnknownOverlap,
+    UnknownOverlap(OverlapSearchParams),
+    // join by the reference coordinates of r1 and r2
+    ReferenceGuided(ReferencePlacement),
+}
+
+/// Placement of r1 and r2 on the reference, from their locators (the alignments of each read to the reference).
+/// Used by `EndJoiningStrategy::ReferenceGuided` to join the reads by reference coordinate.
+#[derive(Debug, Clone, Getters)]
+pub struct ReferencePlacement {
+    #[getset(get = "pub")]
+    r1: Locator,
+    #[getset(get = "pub")]
+    r2: Locator,
+}
+
+impl ReferencePlacement {
+    /// Creates the placement of r1 and r2 from their locators.
+    /// Returns `None` if r2 ends before r1 starts on the reference, as r2 is supposed to be the downstream end of r1.
+    pub fn new(r1: Locator, r2: Locator) -> Option<Self> {
+        if r2.ref_end < r1.ref_start {
+            return None;
+        }
+        Some(ReferencePlacement { r1, r2 })
+    }
 }
 
 /// Input for the end joining process.
@@ -68,6 +117,7 @@ impl EndJoiningInput<'_> {
 /// This struct contains the joined sequence and optionally the quality scores.
 /// - `seq`: The joined sequence as a vector of bytes.
 /// - `quality`: An optional vector of quality scores corresponding to the joined sequence.
+/// - `overlap`: The overlap of r1 and r2 used for the joining.
 /// The `EndJoiningResult` struct is used to represent the outcome of the end joining operation.
 /// It provides the joined sequence and, if available, the quality scores.
 #[derive(Debug, Clone, Getters, Setters)]
@@ -76,6 +126,8 @@ pub struct EndJoiningResult {
     seq: Vec<u8>,
     #[getset(get = "pub")]
     quality: Option<Vec<u8>>,
+    #[getset(get = "pub")]
+    overlap: OverlapResult,
 }
 
 impl EndJoiningResult {
@@ -84,6 +136,7 @@ impl EndJoiningResult {
         EndJoiningResult {
             seq: Vec::new(),
             quality: None,
+            overlap: OverlapResult::new(),
         }
     }
 }
@@ -93,6 +146,7 @@ impl EndJoiningResult {
 /// - `offset`: The offset of the second sequence relative to the r1, the first sequence.
 /// - `overlap_len`: The length of the overlap between the two sequences.
 /// - `mismatches`: The number of mismatches in the overlapping region.
+/// - `weighted_mismatches`: The mismatches weighted by the qualities of their bases (see `find_best_overlap_with_quality`).
 /// The `OverlapResult` struct is used to represent the result of an overlap analysis between two sequences.
 /// It provides the offset of the second sequence relative to the first, the length of the overlap,
 /// and the number of mismatches found in the overlapping region.
@@ -135,7 +189,7 @@ impl EndJoiningResult {
 /// ```
 /// In this case, the offset would be 8 (`r1.len()`), and the overlap length would be 0.
 /// This indicates that r2 starts after r1 ends, with no overlap.
-#[derive(Debug, Clone, PartialEq, Getters, Setters)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters, Setters)]
 pub struct OverlapResult {
     #[getset(get = "pub")]
     offset: isize,
@@ -143,6 +197,9 @@ pub struct OverlapResult {
     overlap_len: usize,
     #[getset(get = "pub")]
     mismatches: usize,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    weighted_mismatches: f64,
 }
 
 impl OverlapResult {
@@ -152,6 +209,7 @@ impl OverlapResult {
             offset: 0,
             overlap_len: 0,
             mismatches: 0,
+            weighted_mismatches: 0.0,
         }
     }
 
@@ -170,6 +228,7 @@ impl OverlapResult {
             offset: offset as isize,
             overlap_len,
             mismatches: 0, // no mismatches in this case
+            weighted_mismatches: 0.0,
         }
     }
 }
@@ -239,9 +298,21 @@ pub fn end_joining(
             // use the provided overlap result
             overlap_result.clone()
         }
-        EndJoiningStrategy::UnknownOverlap => {
-            // find the best overlap
-            find_best_overlap(&r1, &r2, MIN_OVERLAP, ERROR_RATE_FOR_ENDJOINING)
+        EndJoiningStrategy::UnknownOverlap(search_params) => {
+            // find the best overlap, mismatches weighted by the qualities if available
+            find_best_overlap_with_quality(
+                &r1,
+                q1.as_deref(),
+                &r2,
+                q2.as_deref(),
+                search_params.min_overlap,
+                search_params.error_rate,
+                false,
+            )
+        }
+        EndJoiningStrategy::ReferenceGuided(placement) => {
+            // the reads are joined by reference coordinate, not by an overlap of the reads
+            return join_by_reference(&r1, q1.as_deref(), &r2, q2.as_deref(), placement);
         }
     };
 
@@ -270,6 +341,32 @@ pub fn find_best_overlap(
     r2: &[u8],
     min_overlap: usize,
     error_rate: f64,
+) -> OverlapResult {
+    find_best_overlap_with_quality(r1, None, r2, None, min_overlap, error_rate, false)
+}
+
+/// Finds the best overlap between two sequences as `find_best_overlap`, with the mismatches weighted by the Phred+33 qualities of their bases.
+/// The weight of a mismatch is the probability that neither of its bases is a sequencing error, so that a mismatch of two high quality bases counts
+/// as one, and a mismatch with a low quality base (or a masked `N` of quality `!`) counts little against the error rate.
+/// Without the qualities of both sequences, every mismatch counts as one.
+/// Among the overlaps of the same length, the one with the fewest weighted mismatches is the best.
+/// # Arguments
+/// - `r1`, `r1_qual`: The first sequence and its optional quality scores.
+/// - `r2`, `r2_qual`: The second sequence and its optional quality scores.
+/// - `min_overlap`: The minimum length of the overlap required.
+/// - `error_rate`: The allowed weighted mismatches as a fraction of the overlap length.
+/// - `iupac`: Whether an IUPAC ambiguity code (e.g. of a consensus of consensus) matches any of its bases,
+///   otherwise the bases must be identical.
+/// # Returns
+/// - `OverlapResult`: The best overlap found, with the raw and the weighted mismatches.
+pub fn find_best_overlap_with_quality(
+    r1: &[u8],
+    r1_qual: Option<&[u8]>,
+    r2: &[u8],
+    r2_qual: Option<&[u8]>,
+    min_overlap: usize,
+    error_rate: f64,
+    iupac: bool,
 ) -> OverlapResult {
     let len1 = r1.len() as isize;
     let len2 = r2.len() as isize;
@@ -282,38 +379,23 @@ pub fn find_best_overlap(
     let max_offset = len1 - min_overlap as isize; // right overhang can go up to the end of r1 minus min_overlap
 
     for offset in min_offset..=max_offset {
-        // Determine the overlap region in r1 and r2
-        let start1 = offset.max(0) as usize; // r1 starts
-        let start2 = (-offset).max(0) as usize; // r2 starts
-        let end1 = len1.min(offset + len2) as usize; // r1 ends
-        let overlap = end1.saturating_sub(start1); // length of the overlap
-
-        if overlap >= min_overlap
-            && (start2 + overlap) <= len2 as usize
-            && (start1 + overlap) <= len1 as usize
+        let Some(candidate) = overlap_at_offset(r1, r1_qual, r2, r2_qual, offset, iupac) else {
+            continue;
+        };
+        if candidate.overlap_len >= min_overlap
+            && candidate.weighted_mismatches <= (candidate.overlap_len as f64 * error_rate)
         {
-            let mismatches = r1[start1..end1]
-                .iter()
-                .zip(r2[start2..start2 + overlap].iter())
-                .filter(|(a, b)| a != b)
-                .count();
-            if (mismatches as f64) <= (overlap as f64 * error_rate) {
-                // favor longer overlaps;
-                let is_better = match &best {
-                    None => true,
-                    Some(best_overlap) => {
-                        overlap > best_overlap.overlap_len
-                            || (overlap == best_overlap.overlap_len
-                                && mismatches < best_overlap.mismatches)
-                    }
-                };
-                if is_better {
-                    best = Some(OverlapResult {
-                        offset,
-                        overlap_len: overlap,
-                        mismatches,
-                    });
+            // favor longer overlaps;
+            let is_better = match &best {
+                None => true,
+                Some(best_overlap) => {
+                    candidate.overlap_len > best_overlap.overlap_len
+                        || (candidate.overlap_len == best_overlap.overlap_len
+                            && candidate.weighted_mismatches < best_overlap.weighted_mismatches)
                 }
+            };
+            if is_better {
+                best = Some(candidate);
             }
         }
     }
@@ -325,16 +407,81 @@ pub fn find_best_overlap(
             offset: len1,   // no overlap found, set offset to len1
             overlap_len: 0, // overlap_len == 0 means there is no overlap.
             mismatches: 0,
+            weighted_mismatches: 0.0,
         }
     }
 }
 
+/// The overlap of r2 shifted by `offset` relative to r1 (see `OverlapResult` for the offset), with its raw and weighted mismatches
+/// as in `find_best_overlap_with_quality`, without any minimum length or error rate.
+/// Used to apply the overlap found on other sequences (e.g. a consensus of consensus) to a pair of reads of different lengths.
+/// Returns `None` if the reads do not overlap at this offset.
+pub fn overlap_at_offset(
+    r1: &[u8],
+    r1_qual: Option<&[u8]>,
+    r2: &[u8],
+    r2_qual: Option<&[u8]>,
+    offset: isize,
+    iupac: bool,
+) -> Option<OverlapResult> {
+    let len1 = r1.len() as isize;
+    let len2 = r2.len() as isize;
+    if offset >= len1 || offset <= -len2 {
+        return None;
+    }
+
+    // Determine the overlap region in r1 and r2
+    let start1 = offset.max(0) as usize; // r1 starts
+    let start2 = (-offset).max(0) as usize; // r2 starts
+    let end1 = len1.min(offset + len2) as usize; // r1 ends
+    let overlap_len = end1 - start1; // length of the overlap
+
+    let (mismatches, weighted_mismatches) = (0..overlap_len)
+        .filter(|&i| !bases_match(r1[start1 + i], r2[start2 + i], iupac))
+        .fold((0, 0.0), |(count, weighted), i| {
+            let weight = mismatch_weight(
+                r1_qual.and_then(|q| q.get(start1 + i).copied()),
+                r2_qual.and_then(|q| q.get(start2 + i).copied()),
+            );
+            (count + 1, weighted + weight)
+        });
+
+    Some(OverlapResult {
+        offset,
+        overlap_len,
+        mismatches,
+        weighted_mismatches,
+    })
+}
+
+/// Whether two bases of an overlap match, an IUPAC ambiguity code matching any of its bases with `iupac`.
+fn bases_match(a: u8, b: u8, iupac: bool) -> bool {
+    if iupac {
+        iupac_matches(a as char, b as char)
+    } else {
+        a == b
+    }
+}
+
+/// Weight of a mismatch of the overlap, the probability that neither base is a sequencing error, from their Phred+33 qualities.
+/// A mismatch counts as one without the qualities.
+fn mismatch_weight(q1: Option<u8>, q2: Option<u8>) -> f64 {
+    match (q1, q2) {
+        (Some(q1), Some(q2)) => {
+            phred_quality_prob(q1.saturating_sub(33) as f64)
+                * phred_quality_prob(q2.saturating_sub(33) as f64)
+        }
+        _ => 1.0,
+    }
+}
+
 /// Joins two sequences with an overlap based on the provided `OverlapResult`.
 /// This function handles the case where there is no overlap by simply concatenating the sequences.
 /// If there is an overlap, it creates a consensus sequence based on the overlapping region.
 /// It uses the quality scores from both sequences to determine the consensus base in the overlap region if available,
 /// base with higher quality are returned as the consensus base.
-/// It also builds the quality vector if quality scores are provided for both sequences.
+/// It also builds the quality vector if quality scores are provided for both sequences. The quality of an overlap base is the posterior of the two reads:
+/// the sum of the Phred qualities if the bases agree (capped at `MAX_JOINED_QUALITY`), the difference if they conflict.
 /// # Arguments
 /// - `r1`: A slice of bytes representing the first sequence.
 /// - `r1_qual`: An optional slice of bytes representing the quality scores for the first sequence.
@@ -346,6 +493,7 @@ pub fn find_best_overlap(
 ///   - If there is no overlap, it returns a concatenated sequence of `r1` and `r2`.
 ///   - If there is an overlap, it returns a sequence that includes the consensus bases from the overlapping region.
 ///   - The quality scores are also included if available, with the consensus quality scores calculated based on the overlapping region.
+///   - The `overlap` is kept in the result.
 fn join_with_overlap(
     r1: &[u8],
     r1_qual: Option<&[u8]>,
@@ -398,11 +546,11 @@ fn join_with_overlap(
         };
         overlap_seq.push(consensus_base);
 
-        // Quality: take max if both present
+        // Quality: posterior of the two bases if both present
         if let (Some(q1), Some(q2), Some(overlap_q)) = (r1_qual, r2_qual, &mut overlap_qual) {
-            let q1_val = q1.get(r1_idx).copied().unwrap_or(0);
-            let q2_val = q2.get(r2_idx).copied().unwrap_or(0);
-            overlap_q.push(std::cmp::max(q1_val, q2_val));
+            let q1_val = q1.get(r1_idx).copied().unwrap_or(b'!');
+            let q2_val = q2.get(r2_idx).copied().unwrap_or(b'!');
+            overlap_q.push(joined_quality(base1 == base2, q1_val, q2_val));
         }
     }
 
@@ -437,7 +585,199 @@ fn join_with_overlap(
         _ => None,
     };
 
-    EndJoiningResult { seq, quality }
+    EndJoiningResult {
+        seq,
+        quality,
+        overlap,
+    }
+}
+
+/// Bases of a read aligned to one reference position, with the bases inserted after it, and their qualities.
+/// The qualities are `!` placeholders if the read has no quality scores.
+#[derive(Debug, Clone, PartialEq)]
+struct ReferenceColumn {
+    bases: Vec<u8>,
+    quals: Vec<u8>,
+}
+
+/// Splits a read into the columns of the reference positions of its locator, from `ref_start` to `ref_end`.
+/// A deleted reference position is an empty column, and bases inserted before the first reference position are kept in the first column.
+/// Returns an error if the aligned query of the locator does not cover the whole read.
+fn reference_columns(
+    seq: &[u8],
+    qual: Option<&[u8]>,
+    locator: &Locator,
+) -> Result<Vec<ReferenceColumn>, Box<dyn Error + Send + Sync>> {
+    let mut columns: Vec<ReferenceColumn> = Vec::new();
+    let mut leading = ReferenceColumn {
+        bases: Vec::new(),
+        quals: Vec::new(),
+    };
+    let mut query_idx = 0;
+
+    for (query_char, ref_char) in locator
+        .query_aligned_string
+        .bytes()
+        .zip(locator.ref_aligned_string.bytes())
+    {
+        if ref_char != b'-' {
+            columns.push(ReferenceColumn {
+                bases: Vec::new(),
+                quals: Vec::new(),
+            });
+        }
+        if query_char == b'-' {
+            continue;
+        }
+        let base = *seq
+            .get(query_idx)
+            .ok_or("Locator of the read is longer than the read")?;
+        let base_qual = qual.and_then(|q| q.get(query_idx).copied()).unwrap_or(b'!');
+        let column = columns.last_mut().unwrap_or(&mut leading);
+        column.bases.push(base);
+        column.quals.push(base_qual);
+        query_idx += 1;
+    }
+
+    if query_idx != seq.len() {
+        return Err("Locator of the read does not cover the whole read".into());
+    }
+    match columns.first_mut() {
+        Some(first) => {
+            leading.bases.append(&mut first.bases);
+            leading.quals.append(&mut first.quals);
+            *first = leading;
+        }
+        None => return Err("Locator of the read has no reference position".into()),
+    }
+
+    Ok(columns)
+}
+
+/// Joins two sequences by the reference coordinates of their locators (see `ReferencePlacement`).
+/// The reads are walked along the reference from the start of the upstream read to the end of the downstream read:
+/// - A reference position covered by one read takes the bases of that read.
+/// - A reference position covered by both reads is merged, as in `join_with_overlap`, keeping the bases of higher quality.
+/// - A reference position covered by neither read, the gap between non-overlapping reads, is marked by an `N` of quality `!`,
+///   so that the joined sequence keeps the reference coordinates.
+///
+/// The `overlap` of the result counts the reference positions covered by both reads and their mismatches.
+/// Its offset is the position of the first base of r2 in the joined sequence (past the end of r1 and the gap if the reads do not overlap),
+/// or minus the position of the first base of r1 if r2 starts upstream of r1.
+/// Returns an error if the locators do not match the reads.
+fn join_by_reference(
+    r1: &[u8],
+    r1_qual: Option<&[u8]>,
+    r2: &[u8],
+    r2_qual: Option<&[u8]>,
+    placement: &ReferencePlacement,
+) -> Result<EndJoiningResult, Box<dyn Error + Send + Sync>> {
+    let columns1 = reference_columns(r1, r1_qual, &placement.r1)?;
+    let columns2 = reference_columns(r2, r2_qual, &placement.r2)?;
+    let start1 = placement.r1.ref_start;
+    let start2 = placement.r2.ref_start;
+    let end1 = start1 + columns1.len() - 1;
+    let end2 = start2 + columns2.len() - 1;
+
+    let mut seq = Vec::with_capacity(r1.len() + r2.len());
+    let mut quality = Vec::with_capacity(r1.len() + r2.len());
+    let mut offset = 0;
+    let mut overlap_len = 0;
+    let mut mismatches = 0;
+    let mut weighted_mismatches = 0.0;
+
+    for pos in start1.min(start2)..=end1.max(end2) {
+        if pos == start2 && start2 >= start1 {
+            offset = seq.len() as isize;
+        } else if pos == start1 && start1 > start2 {
+            offset = -(seq.len() as isize);
+        }
+        let column1 = (start1..=end1)
+            .contains(&pos)
+            .then(|| &columns1[pos - start1]);
+        let column2 = (start2..=end2)
+            .contains(&pos)
+            .then(|| &columns2[pos - start2]);
+
+        match (column1, column2) {
+            (Some(column), None) | (None, Some(column)) => {
+                seq.extend_from_slice(&column.bases);
+                quality.extend_from_slice(&column.quals);
+            }
+            (Some(column1), Some(column2)) => {
+                overlap_len += 1;
+                if column1.bases == column2.bases {
+                    seq.extend_from_slice(&column1.bases);
+                    quality.extend(
+                        column1
+                            .quals
+                            .iter()
+                            .zip(&column2.quals)
+                            .map(|(&q1, &q2)| joined_quality(true, q1, q2)),
+                    );
+                } else {
+                    mismatches += 1;
+                    weighted_mismatches += mismatch_weight(
+                        r1_qual.and(column1.quals.first().copied()),
+                        r2_qual.and(column2.quals.first().copied()),
+                    );
+                    let (kept, other) =
+                        if mean_quality(&column1.quals) >= mean_quality(&column2.quals) {
+                            (column1, column2)
+                        } else {
+                            (column2, column1)
+                        };
+                    seq.extend_from_slice(&kept.bases);
+                    if kept.bases.len() == other.bases.len() {
+                        quality.extend(kept.bases.iter().zip(&other.bases).enumerate().map(
+                            |(i, (base, other_base))| {
+                                joined_quality(base == other_base, kept.quals[i], other.quals[i])
+                            },
+                        ));
+                    } else {
+                        quality.extend_from_slice(&kept.quals);
+                    }
+                }
+            }
+            (None, None) => {
+                seq.push(b'N');
+                quality.push(b'!');
+            }
+        }
+    }
+
+    Ok(EndJoiningResult {
+        seq,
+        quality: (r1_qual.is_some() && r2_qual.is_some()).then_some(quality),
+        overlap: OverlapResult {
+            offset,
+            overlap_len,
+            mismatches,
+            weighted_mismatches,
+        },
+    })
+}
+
+/// Mean Phred+33 quality of the bases of a reference column, 0 for a deleted position.
+fn mean_quality(quals: &[u8]) -> f64 {
+    if quals.is_empty() {
+        return 0.0;
+    }
+    quals.iter().map(|&q| q as f64).sum::<f64>() / quals.len() as f64
+}
+
+/// Phred+33 quality of an overlap base from the qualities of the two reads.
+/// Two agreeing bases are independent observations, their error probabilities multiply and the Phred qualities add (capped at `MAX_JOINED_QUALITY`).
+/// Of two conflicting bases, the higher quality one is kept, with the difference of the two qualities.
+fn joined_quality(agree: bool, q1: u8, q2: u8) -> u8 {
+    let phred1 = q1.saturating_sub(33);
+    let phred2 = q2.saturating_sub(33);
+    let phred = if agree {
+        phred1.saturating_add(phred2).min(MAX_JOINED_QUALITY)
+    } else {
+        phred1.abs_diff(phred2)
+    };
+    phred + 33
 }
 
 #[cfg(test)]
@@ -509,11 +849,49 @@ mod tests {
         let result = join_with_overlap(r1, None, r2, None, overlap.clone());
         assert_eq!(result.seq, b"AAAGGGGGGGTT");
 
-        let result = end_joining(input.clone(), &EndJoiningStrategy::UnknownOverlap);
+        let result = end_joining(
+            input.clone(),
+            &EndJoiningStrategy::UnknownOverlap(OverlapSearchParams::default()),
+        );
         assert!(result.is_ok());
         assert_eq!(result.unwrap().seq, b"GGGGGGGTTAAAGGGGGGG");
     }
 
+    #[test]
+    fn test_join_overlap_search_params() {
+        let r1 = b"GGGGGGGTT";
+        let r2 = b"AAAGGGGGGG";
+        let fasta1 = fasta::Record::with_attrs("r1", None, r1);
+        let fasta2 = fasta::Record::with_attrs("r2", None, r2);
+        let input = EndJoiningInput::Fasta((&fasta1, &fasta2));
+
+        // the overlap of 7 is shorter than the default minimum overlap
+        let result = end_joining(
+            input.clone(),
+            &EndJoiningStrategy::UnknownOverlap(OverlapSearchParams::default()),
+        )
+        .unwrap();
+        assert_eq!(*result.overlap().overlap_len(), 0);
+        assert_eq!(*result.overlap().offset(), r1.len() as isize);
+
+        let search_params = OverlapSearchParams {
+            min_overlap: 4,
+            error_rate: ERROR_RATE_FOR_ENDJOINING,
+        };
+        let result =
+            end_joining(input, &EndJoiningStrategy::UnknownOverlap(search_params)).unwrap();
+        assert_eq!(result.seq, b"AAAGGGGGGGTT");
+        assert_eq!(
+            *result.overlap(),
+            OverlapResult {
+                offset: -3,
+                overlap_len: 7,
+                mismatches: 0,
+                weighted_mismatches: 0.0
+            }
+        );
+    }
+
     #[test]
     fn test_join3() {
         let r1 = b"CCCGGGGGGGTTTTTCCC";
@@ -525,11 +903,183 @@ mod tests {
         let overlap = find_best_overlap(r1, r2, 10, ERROR_RATE_FOR_ENDJOINING);
         assert_eq!(overlap.offset, 5);
         assert_eq!(overlap.overlap_len, 11);
-        let result = end_joining(input.clone(), &EndJoiningStrategy::UnknownOverlap);
+        let result = end_joining(
+            input.clone(),
+            &EndJoiningStrategy::UnknownOverlap(OverlapSearchParams::default()),
+        );
         assert!(result.is_ok());
         assert_eq!(result.unwrap().seq, b"CCCGGGGGGGTTTTTCCC");
     }
 
+    #[test]
+    fn test_find_best_overlap_with_quality() {
+        // r2 starts at position 4 of r1, with one mismatch (r2[5]) in the overlap of 10
+        let r1 = b"AAAAACGTACGTCG";
+        let r2 = b"ACGTAGGTCGTTTT";
+        let r1_qual = vec![b'I'; r1.len()];
+        let mut r2_qual = vec![b'I'; r2.len()];
+
+        // a raw mismatch is above the error rate of 0.05 (0.5 mismatch allowed)
+        let overlap = find_best_overlap(r1, r2, 8, 0.05);
+        assert_eq!(overlap.overlap_len, 0);
+        let overlap =
+            find_best_overlap_with_quality(r1, Some(&r1_qual), r2, Some(&r2_qual), 8, 0.05, false);
+        assert_eq!(overlap.overlap_len, 0);
+
+        // a mismatch with a Q2 base counts (1 - 10^-4) * (1 - 10^-0.2) = 0.369
+        r2_qual[5] = b'#';
+        let overlap =
+            find_best_overlap_with_quality(r1, Some(&r1_qual), r2, Some(&r2_qual), 8, 0.05, false);
+        assert_eq!(overlap.offset, 4);
+        assert_eq!(overlap.overlap_len, 10);
+        assert_eq!(overlap.mismatches, 1);
+        assert!((overlap.weighted_mismatches - 0.369).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_overlap_at_offset() {
+        let r1 = b"ACGTACGT";
+        let r2 = b"TACCTCG";
+        let overlap = overlap_at_offset(r1, None, r2, None, 3, false).unwrap();
+        assert_eq!(overlap.overlap_len, 5);
+        assert_eq!(overlap.mismatches, 1);
+        assert_eq!(
+            overlap_at_offset(r1, None, r2, None, -2, false)
+                .unwrap()
+                .overlap_len,
+            5
+        );
+        assert!(overlap_at_offset(r1, None, r2, None, 8, false).is_none());
+        assert!(overlap_at_offset(r1, None, r2, None, -7, false).is_none());
+
+        // S (C or G) matches the G of r1 only with IUPAC matching
+        let r2 = b"TACSTCG";
+        assert_eq!(
+            overlap_at_offset(r1, None, r2, None, 3, false)
+                .unwrap()
+                .mismatches,
+            1
+        );
+        assert_eq!(
+            overlap_at_offset(r1, None, r2, None, 3, true)
+                .unwrap()
+                .mismatches,
+            0
+        );
+    }
+
+    #[test]
+    fn test_join_with_overlap_quality() {
+        // overlap of r1[4..8] and r2[0..4], the bases agree except T (Q40) in r1 and A (Q10) in r2 at the third position
+        let r1 = b"AACCGGTT";
+        let r1_qual = b"IIII55II";
+        let r2 = b"GGATAA";
+        let r2_qual = b"?I+III";
+        let overlap = OverlapResult::from_simple_overlap(r1.len(), r2.len(), 4);
+        let result = join_with_overlap(r1, Some(r1_qual), r2, Some(r2_qual), overlap);
+        assert_eq!(result.seq, b"AACCGGTTAA");
+        // Q20 + Q30 = Q50, Q20 + Q40 = Q60, Q40 - Q10 = Q30, Q40 + Q40 capped at Q60
+        assert_eq!(result.quality.unwrap(), b"IIIIS]?]II");
+
+        // conflicting bases of the same quality
+        assert_eq!(joined_quality(false, b'I', b'I'), b'!');
+        assert_eq!(joined_quality(true, b'!', b'5'), b'5');
+    }
+
+    fn test_locator(ref_start: usize, ref_end: usize, query: &str, reference: &str) -> Locator {
+        Locator::new(
+            ref_start,
+            ref_end,
+            100.0,
+            query.contains('-') || reference.contains('-'),
+            query.to_string(),
+            reference.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_join_by_reference() {
+        // overlap on the reference positions 5 to 8, with a mismatch at position 8
+        let r1 = fastq::Record::with_attrs("r1", None, b"AAAACGTA", b"IIIIIIII");
+        let r2 = fastq::Record::with_attrs("r2", None, b"CGTTGG", b"+IIIII");
+        let placement = ReferencePlacement::new(
+            test_locator(1, 8, "AAAACGTA", "AAAACGTT"),
+            test_locator(5, 10, "CGTTGG", "CGTTGG"),
+        )
+        .unwrap();
+        let result = end_joining(
+            EndJoiningInput::Fastq((&r1, &r2)),
+            &EndJoiningStrategy::ReferenceGuided(placement),
+        )
+        .unwrap();
+        assert_eq!(result.seq, b"AAAACGTAGG");
+        assert_eq!(result.quality.unwrap(), b"IIIIS]]!II");
+        assert_eq!(result.overlap.offset, 4);
+        assert_eq!(result.overlap.overlap_len, 4);
+        assert_eq!(result.overlap.mismatches, 1);
+
+        // an insertion in r1 before the overlap
+        let r1 = fasta::Record::with_attrs("r1", None, b"ACGTTACGT");
+        let r2 = fasta::Record::with_attrs("r2", None, b"ACGTCC");
+        let placement = ReferencePlacement::new(
+            test_locator(1, 8, "ACGTTACGT", "ACGT-ACGT"),
+            test_locator(5, 10, "ACGTCC", "ACGTCC"),
+        )
+        .unwrap();
+        let result = end_joining(
+            EndJoiningInput::Fasta((&r1, &r2)),
+            &EndJoiningStrategy::ReferenceGuided(placement),
+        )
+        .unwrap();
+        assert_eq!(result.seq, b"ACGTTACGTCC");
+        assert!(result.quality.is_none());
+        assert_eq!(result.overlap.offset, 5);
+        assert_eq!(result.overlap.overlap_len, 4);
+    }
+
+    #[test]
+    fn test_join_by_reference_gap() {
+        // r2 starts 3 reference positions after the end of r1
+        let r1 = fasta::Record::with_attrs("r1", None, b"AAAA");
+        let r2 = fasta::Record::with_attrs("r2", None, b"CCCC");
+        let placement = ReferencePlacement::new(
+            test_locator(101, 104, "AAAA", "AAAA"),
+            test_locator(108, 111, "CCCC", "CCCC"),
+        )
+        .unwrap();
+        let result = end_joining(
+            EndJoiningInput::Fasta((&r1, &r2)),
+            &EndJoiningStrategy::ReferenceGuided(placement),
+        )
+        .unwrap();
+        assert_eq!(result.seq, b"AAAANNNCCCC");
+        assert_eq!(result.overlap.offset, 7);
+        assert_eq!(result.overlap.overlap_len, 0);
+
+        // r2 upstream of r1
+        assert!(
+            ReferencePlacement::new(
+                test_locator(108, 111, "CCCC", "CCCC"),
+                test_locator(101, 104, "AAAA", "AAAA"),
+            )
+            .is_none()
+        );
+
+        // a locator not covering the read
+        let placement = ReferencePlacement::new(
+            test_locator(101, 103, "AAA", "AAA"),
+            test_locator(108, 111, "CCCC", "CCCC"),
+        )
+        .unwrap();
+        assert!(
+            end_joining(
+                EndJoiningInput::Fasta((&r1, &r2)),
+                &EndJoiningStrategy::ReferenceGuided(placement),
+            )
+            .is_err()
+        );
+    }
+
     #[test]
     fn test_join4() {
         let r1 =     b"CAATACATCACAACTGTTTAATAGTACTTGGATTAATGGTACTAGGAAAGGTACTGAAGGAAATGTTACAGAAAATATCATACTCCCATGCAGAATAAAACAAATTATAAACATGTGGCAGGAAGTAGGAAAAGCAATGTATGCCCCTCCCATCAAAGGAATGATTAGATGTTCATCAAATATTACAGGGCTGCTATTAACAAGGGATGGTGGTGAGAACAAAAACAAGAGCGAGCCCGAGGTCTTCAGACCTGGAGGAGGAGATATGAGGGACA";
@@ -538,7 +1088,10 @@ mod tests {
         let fasta1 = fasta::Record::with_attrs("r1", None, r1);
         let fasta2 = fasta::Record::with_attrs("r2", None, r2);
         let input = EndJoiningInput::Fasta((&fasta1, &fasta2));
-        let result = end_joining(input.clone(), &EndJoiningStrategy::UnknownOverlap);
+        let result = end_joining(
+            input.clone(),
+            &EndJoiningStrategy::UnknownOverlap(OverlapSearchParams::default()),
+        );
         assert!(result.is_ok());
         assert_eq!(result.unwrap().seq, joined);
     }
@@ -1,49 +1,210 @@
+use std::error::Error;
 use std::fs::{self, File};
-use std::io::{self, BufReader};
+use std::io::{self, BufReader, Read};
 use std::path::Path;
 
 use bio::io::fastq::{self, Record};
 use flate2::read::MultiGzDecoder;
 
+use crate::helper::tcs_helper::TcsError;
 use crate::helper::tcs_helper::{fastq_files::DataType, fastq_files::FastqFiles};
 
+/// Default number of read pairs held in memory at once when streaming paired fastq files.
+pub const FASTQ_CHUNK_SIZE: usize = 100_000;
+
+type FastqRecords = fastq::Records<BufReader<Box<dyn Read>>>;
+
+// One side (R1 or R2) of a paired fastq stream, with the number of records read and the problems encountered.
+struct FastqStream {
+    file: String,
+    gzipped: bool,
+    records: FastqRecords,
+    record_count: usize,
+    parse_errors: usize,
+    first_parse_error: Option<String>,
+    read_error: Option<TcsError>,
+}
+
+impl FastqStream {
+    fn new(path: &Path, gzipped: bool) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let stream: Box<dyn Read> = if gzipped {
+            Box::new(MultiGzDecoder::new(BufReader::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        };
+        Ok(FastqStream {
+            file: path.to_string_lossy().to_string(),
+            gzipped,
+            records: fastq::Reader::new(stream).records(),
+            record_count: 0,
+            parse_errors: 0,
+            first_parse_error: None,
+            read_error: None,
+        })
+    }
+
+    // Returns the next record slot: `Some(Ok(record))`, `Some(Err(()))` for a record that cannot be parsed,
+    // or `None` when the file ends or cannot be read any further.
+    fn next_record(&mut self) -> Option<Result<Record, ()>> {
+        if self.read_error.is_some() {
+            return None;
+        }
+        match self.records.next()? {
+            Ok(record) => {
+                self.record_count += 1;
+                Some(Ok(record))
+            }
+            Err(fastq::Error::ReadError(e)) => {
+                self.read_error = Some(
+                    if self.gzipped && e.kind() == io::ErrorKind::UnexpectedEof {
+                        TcsError::TruncatedGzip(self.file.clone(), self.record_count)
+                    } else {
+                        TcsError::FastqReadError(self.file.clone(), e.to_string())
+                    },
+                );
+                None
+            }
+            Err(e) => {
+                self.record_count += 1;
+                self.parse_errors += 1;
+                if self.first_parse_error.is_none() {
+                    self.first_parse_error = Some(e.to_string());
+                }
+                Some(Err(()))
+            }
+        }
+    }
+
+    fn issues(&self) -> Vec<TcsError> {
+        let mut issues = Vec::new();
+        if self.parse_errors > 0 {
+            issues.push(TcsError::FastqParseError(
+                self.file.clone(),
+                self.parse_errors,
+                self.first_parse_error.clone().unwrap_or_default(),
+            ));
+        }
+        if let Some(read_error) = &self.read_error {
+            issues.push(read_error.clone());
+        }
+        issues
+    }
+}
+
+/// Streaming reader of paired R1 R2 fastq files.
+/// Iterates over `(Record, Record)` pairs in the order of the files, without loading the files into memory.
+/// Use `read_chunk` to take the pairs in bounded chunks that can be processed in parallel.
+/// Pairs in which either record cannot be parsed, or whose R1 and R2 read names differ (see `read_name`), are skipped.
+/// Parse errors, truncated gzip files, read errors, read name mismatches and R1 R2 record count mismatches are not fatal,
+/// they are collected and returned by `issues` once the files are exhausted.
+pub struct PairedFastqReader {
+    r1: FastqStream,
+    r2: FastqStream,
+    finished: bool,
+    name_mismatches: usize,
+    first_name_mismatch: Option<(String, String)>,
+}
+
+impl PairedFastqReader {
+    /// Opens the R1 and R2 files of a `FastqFiles` struct, handling both Fastq and FastqGz data types.
+    pub fn from_files(files: &FastqFiles) -> std::io::Result<Self> {
+        let gzipped = files.data_type == DataType::FastqGz;
+        Ok(PairedFastqReader {
+            r1: FastqStream::new(&files.r1_file, gzipped)?,
+            r2: FastqStream::new(&files.r2_file, gzipped)?,
+            finished: false,
+            name_mismatches: 0,
+            first_name_mismatch: None,
+        })
+    }
+
+    /// Reads the next chunk of at most `chunk_size` pairs. Returns an empty vector when the files are exhausted.
+    pub fn read_chunk(&mut self, chunk_size: usize) -> Vec<(Record, Record)> {
+        self.by_ref().take(chunk_size).collect()
+    }
+
+    /// Problems found in the R1 and R2 files so far, complete once the reader is exhausted.
+    pub fn issues(&self) -> Vec<TcsError> {
+        let mut issues = self.r1.issues();
+        issues.extend(self.r2.issues());
+        if let Some((r1_name, r2_name)) = &self.first_name_mismatch {
+            issues.push(TcsError::ReadNameMismatch(
+                self.name_mismatches,
+                r1_name.clone(),
+                r2_name.clone(),
+            ));
+        }
+        if self.finished && self.r1.record_count != self.r2.record_count {
+            issues.push(TcsError::RecordCountMismatch(
+                self.r1.record_count,
+                self.r2.record_count,
+            ));
+        }
+        issues
+    }
+
+    // count the remaining records of the longer file, so that the record count mismatch is reported with the full counts.
+    fn drain(stream: &mut FastqStream) {
+        while stream.next_record().is_some() {}
+    }
+}
+
+impl Iterator for PairedFastqReader {
+    type Item = (Record, Record);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.finished {
+            match (self.r1.next_record(), self.r2.next_record()) {
+                (Some(Ok(rec1)), Some(Ok(rec2))) => {
+                    if read_name(&rec1) == read_name(&rec2) {
+                        return Some((rec1, rec2));
+                    }
+                    self.name_mismatches += 1;
+                    if self.first_name_mismatch.is_none() {
+                        self.first_name_mismatch =
+                            Some((rec1.id().to_string(), rec2.id().to_string()));
+                    }
+                }
+                (Some(_), Some(_)) => continue,
+                (Some(_), None) => {
+                    Self::drain(&mut self.r1);
+                    self.finished = true;
+                }
+                (None, Some(_)) => {
+                    Self::drain(&mut self.r2);
+                    self.finished = true;
+                }
+                (None, None) => self.finished = true,
+            }
+        }
+        None
+    }
+}
+
+/// Name of the read of a fastq record, shared by its R1 and R2 records.
+/// The record id (the header up to the first whitespace, so without the ` 1:N:0` comment of Casava 1.8 headers)
+/// without the `/1` or `/2` suffix of older Illumina headers.
+pub fn read_name(record: &Record) -> &str {
+    let id = record.id();
+    id.strip_suffix("/1")
+        .or_else(|| id.strip_suffix("/2"))
+        .unwrap_or(id)
+}
+
 /// Reads paried R1 R2 fastq files and returns a vector of tuples containing the records from both files.
-/// The function takes a `FastqFiles` struct as an argument, which contains the paths to the R1 and R2 files.
-/// The function uses the `bio` crate to read the fastq files and returns a vector of tuples containing the records from both files.
-/// The function also handles different data types (Fastq and FastqGz) using the `DataType` enum.
-/// The function returns a `Result` containing a vector of tuples of records or an `io::Error` if there was an error reading the files.
-/// The function uses flate2 to handle gzipped files.
+/// This loads all the pairs into memory, use `PairedFastqReader` to stream large files.
 /// *Arguments*
 /// - `files`: A `FastqFiles` struct containing the paths to the R1 and R2 files.
 /// *Returns*
-/// - `Result<Vec<(Record, Record)>, std::io::Error>`: A result containing a vector of tuples of records or an `io::Error` if there was an error reading the files.
-pub fn read_fastq_file(files: &FastqFiles) -> std::io::Result<Vec<(Record, Record)>> {
-    let r1_file = File::open(&files.r1_file)?;
-    let r2_file = File::open(&files.r2_file)?;
-
-    let (r1_stream, r2_stream): (Box<dyn std::io::Read>, Box<dyn std::io::Read>) =
-        match files.data_type {
-            DataType::Fastq => (
-                Box::new(BufReader::new(r1_file)),
-                Box::new(BufReader::new(r2_file)),
-            ),
-            DataType::FastqGz => (
-                Box::new(MultiGzDecoder::new(BufReader::new(r1_file))),
-                Box::new(MultiGzDecoder::new(BufReader::new(r2_file))),
-            ),
-        };
-    let r1_reader = fastq::Reader::new(BufReader::new(r1_stream));
-    let r2_reader = fastq::Reader::new(BufReader::new(r2_stream));
-
-    // Collect record pairs into Vec
-    let pairs: Vec<(Record, Record)> = r1_reader
-        .records()
-        .zip(r2_reader.records())
-        .filter_map(|(r1, r2)| match (r1.ok(), r2.ok()) {
-            (Some(rec1), Some(rec2)) => Some((rec1, rec2)),
-            _ => None,
-        })
-        .collect();
+/// - `Result<Vec<(Record, Record)>, Box<dyn Error>>`: A result containing a vector of tuples of records,
+///   or the first `TcsError` found in the files (parse error, truncated gzip, read name or record count mismatch).
+pub fn read_fastq_file(files: &FastqFiles) -> Result<Vec<(Record, Record)>, Box<dyn Error>> {
+    let mut reader = PairedFastqReader::from_files(files)?;
+    let pairs: Vec<(Record, Record)> = reader.by_ref().collect();
+    if let Some(issue) = reader.issues().into_iter().next() {
+        return Err(issue.into());
+    }
     Ok(pairs)
 }
 
@@ -77,6 +238,10 @@ pub fn find_directories(input: &str) -> io::Result<Vec<std::path::PathBuf>> {
 
 mod tests {
     use super::*;
+    use crate::helper::tcs_helper::validate_files;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
 
     #[test]
     fn test_find_directories() {
@@ -93,4 +258,143 @@ mod tests {
             assert!(dir.is_dir());
         }
     }
+
+    #[test]
+    fn test_paired_fastq_reader_chunks() {
+        let files = validate_files("tests/data/hivdr_control").unwrap();
+        let all_pairs = read_fastq_file(&files).unwrap();
+        assert!(!all_pairs.is_empty());
+
+        let mut reader = PairedFastqReader::from_files(&files).unwrap();
+        let mut chunked_pairs = Vec::new();
+        loop {
+            let chunk = reader.read_chunk(1000);
+            if chunk.is_empty() {
+                break;
+            }
+            assert!(chunk.len() <= 1000);
+            chunked_pairs.extend(chunk);
+        }
+
+        assert_eq!(all_pairs.len(), chunked_pairs.len());
+        for (pair, chunked_pair) in all_pairs.iter().zip(chunked_pairs.iter()) {
+            assert_eq!(pair.0.id(), chunked_pair.0.id());
+            assert_eq!(pair.1.seq(), chunked_pair.1.seq());
+        }
+    }
+
+    fn fastq_records(n: usize) -> String {
+        (0..n)
+            .map(|i| format!("@read{}\nACGTACGT\n+\nIIIIIIII\n", i))
+            .collect()
+    }
+
+    fn write_test_files(name: &str, r1: &[u8], r2: &[u8], data_type: DataType) -> FastqFiles {
+        let dir = std::env::temp_dir().join(format!("virust_tcs_io_{}", name));
+        fs::create_dir_all(&dir).unwrap();
+        let ext = match data_type {
+            DataType::Fastq => "fastq",
+            DataType::FastqGz => "fastq.gz",
+        };
+        let r1_file = dir.join(format!("r1.{}", ext));
+        let r2_file = dir.join(format!("r2.{}", ext));
+        fs::write(&r1_file, r1).unwrap();
+        fs::write(&r2_file, r2).unwrap();
+        FastqFiles {
+            r1_file,
+            r2_file,
+            data_type,
+        }
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_paired_fastq_reader_record_count_mismatch() {
+        let files = write_test_files(
+            "mismatch",
+            fastq_records(3).as_bytes(),
+            fastq_records(2).as_bytes(),
+            DataType::Fastq,
+        );
+        let mut reader = PairedFastqReader::from_files(&files).unwrap();
+        assert_eq!(reader.by_ref().count(), 2);
+        let issues = reader.issues();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(issues[0], TcsError::RecordCountMismatch(3, 2)));
+
+        assert!(read_fastq_file(&files).is_err());
+    }
+
+    #[test]
+    fn test_paired_fastq_reader_parse_error() {
+        // the last R1 record has no quality line
+        let r1 = format!("{}@read2\nACGTACGT\n+\n", fastq_records(2));
+        let files = write_test_files(
+            "parse_error",
+            r1.as_bytes(),
+            fastq_records(3).as_bytes(),
+            DataType::Fastq,
+        );
+        let mut reader = PairedFastqReader::from_files(&files).unwrap();
+        assert_eq!(reader.by_ref().count(), 2);
+        let issues = reader.issues();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(issues[0], TcsError::FastqParseError(_, 1, _)));
+    }
+
+    #[test]
+    fn test_paired_fastq_reader_read_name_mismatch() {
+        let r1 = "@read0/1\nACGT\n+\nIIII\n@read1 1:N:0:1\nACGT\n+\nIIII\n@read2\nACGT\n+\nIIII\n@read4\nACGT\n+\nIIII\n";
+        let r2 = "@read0/2\nACGT\n+\nIIII\n@read1 2:N:0:1\nACGT\n+\nIIII\n@read3\nACGT\n+\nIIII\n@read4\nACGT\n+\nIIII\n";
+        let files = write_test_files(
+            "read_name_mismatch",
+            r1.as_bytes(),
+            r2.as_bytes(),
+            DataType::Fastq,
+        );
+        let mut reader = PairedFastqReader::from_files(&files).unwrap();
+        let names = reader
+            .by_ref()
+            .map(|(r1, _)| read_name(&r1).to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["read0", "read1", "read4"]);
+        let issues = reader.issues();
+        assert_eq!(issues.len(), 1);
+        assert!(matches!(
+            &issues[0],
+            TcsError::ReadNameMismatch(1, r1, r2) if r1 == "read2" && r2 == "read3"
+        ));
+
+        assert!(read_fastq_file(&files).is_err());
+    }
+
+    #[test]
+    fn test_paired_fastq_reader_truncated_gzip() {
+        let r1 = gzip(fastq_records(1000).as_bytes());
+        let r2 = gzip(fastq_records(1000).as_bytes());
+        let files = write_test_files(
+            "truncated_gzip",
+            &r1[..r1.len() / 2],
+            &r2,
+            DataType::FastqGz,
+        );
+        let mut reader = PairedFastqReader::from_files(&files).unwrap();
+        assert!(reader.by_ref().count() < 1000);
+        let issues = reader.issues();
+        assert!(
+            issues
+                .iter()
+                .any(|issue| matches!(issue, TcsError::TruncatedGzip(..)))
+        );
+        assert!(
+            issues
+                .iter()
+                .any(|issue| matches!(issue, TcsError::RecordCountMismatch(_, 1000)))
+        );
+    }
 }
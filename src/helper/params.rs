@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::fmt::Display;
 use std::ops::Range;
+use std::path::Path;
 
 use bio::alphabets;
 use once_cell::sync::Lazy;
@@ -10,8 +11,12 @@ use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize};
 use thiserror::Error;
 
+use crate::helper::consensus::{ConsensusStrategy, UnequalLengthConsensus};
+use crate::helper::end_joining::OverlapSearchParams;
 use crate::helper::json::FromJsonString;
+use crate::helper::tcs_helper::{FamilyHeterogeneityAction, QcReference};
 use crate::helper::umi::UMI;
+use crate::helper::umis::{UMIClustering, UMICutOffModel};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Params {
@@ -23,6 +28,9 @@ pub struct Params {
     #[serde(alias = "Email", alias = "EMAIL")]
     pub email: Option<String>,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference_dir: Option<String>,
+
     pub primer_pairs: Vec<RegionParams>,
 }
 
@@ -45,12 +53,34 @@ pub struct RegionParams {
 
     #[serde(deserialize_with = "string_or_number_to_f32")]
     pub majority: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub consensus_strategy: Option<ConsensusStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primer_mismatch_tolerance: Option<PrimerMismatchTolerance>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primer_matching: Option<PrimerMatchingMethod>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umi_clustering: Option<UMIClustering>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub umi_cut_off_model: Option<UMICutOffModel>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub family_heterogeneity: Option<FamilyHeterogeneityAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_ambiguous_bases: Option<CountOrFraction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iupac_min_share: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unequal_length_consensus: Option<UnequalLengthConsensus>,
     pub end_join: bool,
 
     #[serde(deserialize_with = "string_or_number_to_u32")]
     pub end_join_option: u32,
     #[serde(default, deserialize_with = "string_or_number_to_u32")]
     pub overlap: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_overlap: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overlap_error_rate: Option<f64>,
 
     #[serde(alias = "TCS_qc", alias = "TCS_QC")]
     pub tcs_qc: bool,
@@ -83,6 +113,7 @@ impl Display for Params {
         } else {
             write!(f, "  email: null,\n")?;
         }
+        writeln!(f, "  reference_dir: {:?},", self.reference_dir)?;
         write!(f, "  primer_pairs: [\n")?;
         for region in &self.primer_pairs {
             write!(f, "    {},\n", region)?;
@@ -109,9 +140,31 @@ pub struct ValidatedRegionParams {
     pub forward_matching: ForwardMatching,
     pub cdna_matching: CDNAMatching,
     pub majority: f32,
+    #[serde(default)]
+    pub consensus_strategy: Option<ConsensusStrategy>,
+    #[serde(default = "default_primer_mismatch_tolerance")]
+    pub primer_mismatch_tolerance: PrimerMismatchTolerance,
+    #[serde(default)]
+    pub primer_matching: PrimerMatchingMethod,
+    #[serde(default)]
+    pub umi_clustering: UMIClustering,
+    #[serde(default)]
+    pub umi_cut_off_model: UMICutOffModel,
+    #[serde(default)]
+    pub family_heterogeneity: Option<FamilyHeterogeneityAction>,
+    #[serde(default = "default_max_ambiguous_bases")]
+    pub max_ambiguous_bases: CountOrFraction,
+    #[serde(default)]
+    pub iupac_min_share: Option<f64>,
+    #[serde(default)]
+    pub unequal_length_consensus: UnequalLengthConsensus,
     pub end_join: bool,
     pub end_join_option: u32,
     pub overlap: u32,
+    #[serde(default)]
+    pub overlap_search: OverlapSearchParams,
+    #[serde(default)]
+    pub reference: QcReference,
     pub tcs_qc: bool,
     pub qc_config: Option<QcConfig>,
     pub trim: bool,
@@ -120,7 +173,7 @@ pub struct ValidatedRegionParams {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct QcConfig {
-    pub reference: String,
+    pub reference: QcReference,
     pub start: Option<Range<u32>>,
     pub end: Option<Range<u32>>,
     pub indel: bool,
@@ -128,7 +181,7 @@ pub struct QcConfig {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct TrimConfig {
-    pub reference: String,
+    pub reference: QcReference,
     pub start: u32,
     pub end: u32,
 }
@@ -147,6 +200,56 @@ pub struct CDNAMatching {
     pub bio_cdna: String,
 }
 
+/// A maximum number of events (e.g. primer mismatches or ambiguous bases) in a sequence,
+/// either an absolute count or a fraction of the sequence length.
+/// - `primer_mismatch_tolerance`: mismatches (IUPAC aware) between the biological part of a primer and the read,
+///   the same tolerance for the forward (R1) and the cDNA (R2) primers of a region. Up to 2 mismatches by default,
+///   the tolerance of the original TCS pipeline.
+/// - `max_ambiguous_bases`: ambiguous bases (N) in each of the R1 and R2 consensus of a TCS, a TCS with more in R1 or R2
+///   is dropped. No ambiguous bases by default, the behavior of the original TCS pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub enum CountOrFraction {
+    Count(u32),
+    Fraction(f64),
+}
+
+/// The primer mismatch tolerance of a region, written `{"Count": 2}` or `{"Fraction": 0.1}` in the param file.
+pub type PrimerMismatchTolerance = CountOrFraction;
+
+pub const DEFAULT_PRIMER_MISMATCH_TOLERANCE: PrimerMismatchTolerance = CountOrFraction::Count(2);
+pub const DEFAULT_MAX_AMBIGUOUS_BASES: CountOrFraction = CountOrFraction::Count(0);
+
+impl CountOrFraction {
+    /// The maximum number allowed in a sequence of `length`.
+    /// A fraction is rounded down, e.g. a fraction of 0.1 allows 2 for a primer of 25 bases.
+    pub fn max_count(&self, length: usize) -> usize {
+        match self {
+            CountOrFraction::Count(count) => *count as usize,
+            CountOrFraction::Fraction(fraction) => (fraction * length as f64).floor() as usize,
+        }
+    }
+}
+
+fn default_primer_mismatch_tolerance() -> PrimerMismatchTolerance {
+    DEFAULT_PRIMER_MISMATCH_TOLERANCE
+}
+
+fn default_max_ambiguous_bases() -> CountOrFraction {
+    DEFAULT_MAX_AMBIGUOUS_BASES
+}
+
+/// How the primers of a region are found in R1 and R2.
+/// Both methods accept up to the primer mismatch tolerance of the region, counting an inserted or deleted base as one mismatch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum PrimerMatchingMethod {
+    /// The primer is compared base by base to the window right after the leading Ns (R1) or the UMI block (R2).
+    #[default]
+    FixedWindow,
+    /// Reads failing the fixed window are aligned to the primer, which tolerates small indels in the primer
+    /// and in the leading N stagger, and the read is trimmed at the aligned end of the primer.
+    Alignment,
+}
+
 #[derive(Error, Debug)]
 pub enum ParamsValidationError {
     #[error("Platform Error rate out of supported range (0..0.1) {0}")]
@@ -157,8 +260,24 @@ pub enum ParamsValidationError {
     EmptySequence,
     #[error("Invalid nucleotide word, must use IUPAC alphabets: {0}")]
     InValidNucleotideWord(String),
-    #[error("Invalid End Join Option, must be between 1 and 4: {0}")]
+    #[error("Invalid majority cut-off, must be between 0 and 1.0: {0}")]
+    InvalidMajorityCutoff(f64),
+    #[error("Invalid {0}, a fraction must be between 0 and 1.0: {1}")]
+    InvalidFraction(&'static str, f64),
+    #[error("Invalid IUPAC minimum share, must be above 0 and at most 0.5: {0}")]
+    InvalidIupacMinShare(f64),
+    #[error("Invalid minimum overlap for end-joining, must be at least 1: {0}")]
+    InvalidMinOverlap(usize),
+    #[error("Invalid error rate for end-joining, must be between 0 and 0.5: {0}")]
+    InvalidOverlapErrorRate(f64),
+    #[error("Invalid End Join Option, must be between 1 and 5: {0}")]
     InvalidEndJoinOption(u32),
+    #[error(
+        "Unknown reference genome {0}, must be HXB2, SIVmm239, a FASTA file path or the name of a FASTA file in the reference directory"
+    )]
+    UnknownReferenceGenome(String),
+    #[error("Invalid reference genome FASTA file: {0}")]
+    InvalidReferenceGenomeFasta(String),
     #[error("Invalid reference genome cooridinates, start {0}, end {1} not valid")]
     InvalidReferenceGenomeCoordinates(u32, u32),
     #[error(
@@ -184,9 +303,32 @@ impl Display for RegionParams {
         write!(f, "  forward: {},\n", self.forward)?;
         write!(f, "  cdna: {},\n", self.cdna)?;
         write!(f, "  majority: {},\n", self.majority)?;
+        writeln!(f, "  consensus_strategy: {:?},", self.consensus_strategy)?;
+        writeln!(
+            f,
+            "  primer_mismatch_tolerance: {:?},",
+            self.primer_mismatch_tolerance
+        )?;
+        writeln!(f, "  primer_matching: {:?},", self.primer_matching)?;
+        writeln!(f, "  umi_clustering: {:?},", self.umi_clustering)?;
+        writeln!(f, "  umi_cut_off_model: {:?},", self.umi_cut_off_model)?;
+        writeln!(
+            f,
+            "  family_heterogeneity: {:?},",
+            self.family_heterogeneity
+        )?;
+        writeln!(f, "  max_ambiguous_bases: {:?},", self.max_ambiguous_bases)?;
+        writeln!(f, "  iupac_min_share: {:?},", self.iupac_min_share)?;
+        writeln!(
+            f,
+            "  unequal_length_consensus: {:?},",
+            self.unequal_length_consensus
+        )?;
         write!(f, "  end_join: {},\n", self.end_join)?;
         write!(f, "  end_join_option: {},\n", self.end_join_option)?;
         write!(f, "  overlap: {},\n", self.overlap)?;
+        writeln!(f, "  min_overlap: {:?},", self.min_overlap)?;
+        writeln!(f, "  overlap_error_rate: {:?},", self.overlap_error_rate)?;
         write!(f, "  tcs_qc: {},\n", self.tcs_qc)?;
         write!(f, "  ref_genome: {},\n", self.ref_genome)?;
         write!(f, "  ref_start: {},\n", self.ref_start)?;
@@ -210,6 +352,7 @@ impl Params {
             platform_error_rate: 0.0,
             platform_format: 0,
             email: None,
+            reference_dir: None,
             primer_pairs: Vec::new(),
         }
     }
@@ -217,6 +360,7 @@ impl Params {
     /// Validate the parameters in the `Params` struct.
     /// This function checks the validity of the platform error rate, primer sequences,
     /// end join options, reference genome coordinates, and other fields.
+    /// The reference genome of the QC and trimming is resolved by `validate_reference_genome`, with the `reference_dir` of the params.
     /// If any validation fails, it returns a `ParamsValidationError`.
     /// # Returns
     /// * `Result<ValidatedParams, Box<dyn StdError>>` - A result containing validated parameters or an error.
@@ -239,26 +383,48 @@ impl Params {
         for primer_pairs in self.primer_pairs.iter() {
             let forward_matching = validate_forward_primer(&primer_pairs.forward)?;
             let cdna_matching = validate_cdna_primer(&primer_pairs.cdna)?;
-            if (1..=4).contains(&primer_pairs.end_join_option) == false {
+            if (1..=5).contains(&primer_pairs.end_join_option) == false {
                 return Err(ParamsValidationError::InvalidEndJoinOption(
                     primer_pairs.end_join_option as u32,
                 )
                 .into());
             }
 
-            let mut ref_genome = String::new();
+            let consensus_strategy = validate_consensus_strategy(
+                primer_pairs.majority,
+                primer_pairs.consensus_strategy,
+            )?;
+
+            let primer_mismatch_tolerance = validate_count_or_fraction(
+                "primer mismatch tolerance",
+                primer_pairs.primer_mismatch_tolerance,
+                DEFAULT_PRIMER_MISMATCH_TOLERANCE,
+            )?;
+
+            let max_ambiguous_bases = validate_count_or_fraction(
+                "maximum ambiguous bases",
+                primer_pairs.max_ambiguous_bases,
+                DEFAULT_MAX_AMBIGUOUS_BASES,
+            )?;
+
+            let iupac_min_share = validate_iupac_min_share(primer_pairs.iupac_min_share)?;
+
+            let overlap_search =
+                validate_overlap_search(primer_pairs.min_overlap, primer_pairs.overlap_error_rate)?;
+
+            // end-join option 5 places R1 and R2 on the reference, with or without QC
+            let reference_guided_join = primer_pairs.end_join && primer_pairs.end_join_option == 5;
+            let ref_genome = if primer_pairs.tcs_qc || primer_pairs.trim || reference_guided_join {
+                validate_reference_genome(&primer_pairs.ref_genome, self.reference_dir.as_deref())?
+            } else {
+                QcReference::default()
+            };
             let mut ref_start = None;
             let mut ref_end = None;
-            let mut trim_ref = String::new();
             let mut trim_ref_start = None;
             let mut trim_ref_end = None;
 
             if primer_pairs.tcs_qc {
-                ref_genome = if ["HXB2", "SIVmm239"].contains(&primer_pairs.ref_genome.as_str()) {
-                    primer_pairs.ref_genome.clone()
-                } else {
-                    "HXB2".to_string()
-                };
                 ref_start =
                     process_qc_ref_number(primer_pairs.ref_start, primer_pairs.ref_start_lower);
                 ref_end = process_qc_ref_number(primer_pairs.ref_end, primer_pairs.ref_end_lower);
@@ -280,12 +446,6 @@ impl Params {
             }
 
             if primer_pairs.trim {
-                trim_ref = if ["HXB2", "SIVmm239"].contains(&primer_pairs.ref_genome.as_str()) {
-                    primer_pairs.ref_genome.clone()
-                } else {
-                    "HXB2".to_string()
-                };
-
                 trim_ref_start = primer_pairs.trim_ref_start;
                 trim_ref_end = primer_pairs.trim_ref_end;
 
@@ -319,13 +479,24 @@ impl Params {
                 forward_matching,
                 cdna_matching,
                 majority: primer_pairs.majority,
+                consensus_strategy,
+                primer_mismatch_tolerance,
+                primer_matching: primer_pairs.primer_matching.unwrap_or_default(),
+                umi_clustering: primer_pairs.umi_clustering.unwrap_or_default(),
+                umi_cut_off_model: primer_pairs.umi_cut_off_model.unwrap_or_default(),
+                family_heterogeneity: primer_pairs.family_heterogeneity,
+                max_ambiguous_bases,
+                iupac_min_share,
+                unequal_length_consensus: primer_pairs.unequal_length_consensus.unwrap_or_default(),
                 end_join: primer_pairs.end_join,
                 end_join_option: primer_pairs.end_join_option,
                 overlap: primer_pairs.overlap,
+                overlap_search,
+                reference: ref_genome.clone(),
                 tcs_qc: primer_pairs.tcs_qc,
                 qc_config: if primer_pairs.tcs_qc {
                     Some(QcConfig {
-                        reference: ref_genome,
+                        reference: ref_genome.clone(),
                         start: ref_start,
                         end: ref_end,
                         indel: primer_pairs.indel,
@@ -336,7 +507,7 @@ impl Params {
                 trim: primer_pairs.trim,
                 trim_config: if primer_pairs.trim {
                     Some(TrimConfig {
-                        reference: trim_ref,
+                        reference: ref_genome,
                         start: trim_ref_start.unwrap(),
                         end: trim_ref_end.unwrap(),
                     })
@@ -374,6 +545,133 @@ impl Params {
     }
 }
 
+impl ValidatedRegionParams {
+    /// The consensus strategy of the region, or `default` (the weighted strategy with the command line steepness and midpoint)
+    /// if the region does not set one.
+    pub fn consensus_strategy_or(&self, default: ConsensusStrategy) -> ConsensusStrategy {
+        self.consensus_strategy.unwrap_or(default)
+    }
+}
+
+/// Resolves the consensus strategy of a region from its params.
+/// An explicit `consensus_strategy` takes priority, the platform error rate of a `Bayesian` strategy must be within 0..0.1. Otherwise a `majority` cut-off above 0.5 uses the supermajority strategy,
+/// a cut-off of 0.5 (simple majority in the param generator) uses the simple majority strategy, as `"SimpleMajority"` does,
+/// and a cut-off of 0 leaves the strategy to the default strategy of the CLI.
+/// An explicit `{"Supermajority": 0.5}` is kept as is: it calls a base found in more than half of the reads,
+/// while the simple majority calls the most frequent base.
+fn validate_consensus_strategy(
+    majority: f32,
+    consensus_strategy: Option<ConsensusStrategy>,
+) -> Result<Option<ConsensusStrategy>, ParamsValidationError> {
+    if !(0.0..=1.0).contains(&majority) {
+        return Err(ParamsValidationError::InvalidMajorityCutoff(
+            majority as f64,
+        ));
+    }
+    match consensus_strategy {
+        Some(ConsensusStrategy::Supermajority(cutoff)) if !(0.5..=1.0).contains(&cutoff) => {
+            Err(ParamsValidationError::InvalidMajorityCutoff(cutoff))
+        }
+        Some(ConsensusStrategy::Bayesian(platform_error_rate))
+            if !(0.0..=0.1).contains(&platform_error_rate) =>
+        {
+            Err(ParamsValidationError::InvalidPlatformErrorRate(
+                platform_error_rate as f32,
+            ))
+        }
+        Some(strategy) => Ok(Some(strategy)),
+        None if majority > 0.5 => Ok(Some(ConsensusStrategy::Supermajority(majority as f64))),
+        None if majority == 0.5 => Ok(Some(ConsensusStrategy::SimpleMajority)),
+        None => Ok(None),
+    }
+}
+
+/// Resolves a count or fraction of a region (`name` in the error), the `default` if the region does not set one.
+/// A fraction of the sequence length must be between 0 and 1.
+fn validate_count_or_fraction(
+    name: &'static str,
+    value: Option<CountOrFraction>,
+    default: CountOrFraction,
+) -> Result<CountOrFraction, ParamsValidationError> {
+    match value {
+        Some(CountOrFraction::Fraction(fraction)) if !(0.0..=1.0).contains(&fraction) => {
+            Err(ParamsValidationError::InvalidFraction(name, fraction))
+        }
+        Some(value) => Ok(value),
+        None => Ok(default),
+    }
+}
+
+/// The IUPAC minimum share of a region must be above 0 and at most 0.5, so that two bases can each pass it.
+fn validate_iupac_min_share(
+    iupac_min_share: Option<f64>,
+) -> Result<Option<f64>, ParamsValidationError> {
+    match iupac_min_share {
+        Some(share) if !(share > 0.0 && share <= 0.5) => {
+            Err(ParamsValidationError::InvalidIupacMinShare(share))
+        }
+        _ => Ok(iupac_min_share),
+    }
+}
+
+/// Resolves the overlap search of the end-joining of a region, `MIN_OVERLAP` and `ERROR_RATE_FOR_ENDJOINING` if the region does not set them.
+/// The minimum overlap must be at least 1 and the error rate between 0 and 0.5.
+fn validate_overlap_search(
+    min_overlap: Option<usize>,
+    overlap_error_rate: Option<f64>,
+) -> Result<OverlapSearchParams, ParamsValidationError> {
+    let default = OverlapSearchParams::default();
+    let min_overlap = min_overlap.unwrap_or(default.min_overlap);
+    if min_overlap == 0 {
+        return Err(ParamsValidationError::InvalidMinOverlap(min_overlap));
+    }
+    let error_rate = overlap_error_rate.unwrap_or(default.error_rate);
+    if !(0.0..=0.5).contains(&error_rate) {
+        return Err(ParamsValidationError::InvalidOverlapErrorRate(error_rate));
+    }
+    Ok(OverlapSearchParams {
+        min_overlap,
+        error_rate,
+    })
+}
+
+/// Extensions of the FASTA files of the references in the reference directory.
+const REFERENCE_FASTA_EXTENSIONS: [&str; 4] = ["fasta", "fa", "fas", "fna"];
+
+/// Resolves the reference genome of the QC and trimming of a region, in this order:
+/// - `HXB2` or `SIVmm239`, the references built in the locator.
+/// - A path to a FASTA file.
+/// - The name of a FASTA file (`<name>.fasta`, `.fa`, `.fas` or `.fna`) in the reference directory, if given.
+///
+/// The first record of the FASTA file of a custom reference is read to check it. Any other name is an error.
+fn validate_reference_genome(
+    ref_genome: &str,
+    reference_dir: Option<&str>,
+) -> Result<QcReference, ParamsValidationError> {
+    if let Some(reference) = QcReference::from_string(ref_genome) {
+        return Ok(reference);
+    }
+    let path = Path::new(ref_genome);
+    let fasta_path = if !ref_genome.is_empty() && path.is_file() {
+        Some(path.to_path_buf())
+    } else {
+        reference_dir.and_then(|dir| {
+            REFERENCE_FASTA_EXTENSIONS
+                .iter()
+                .map(|extension| Path::new(dir).join(format!("{}.{}", ref_genome, extension)))
+                .find(|fasta_path| fasta_path.is_file())
+        })
+    };
+    let fasta_path = fasta_path
+        .ok_or_else(|| ParamsValidationError::UnknownReferenceGenome(ref_genome.to_string()))?;
+
+    let reference = QcReference::from_fasta_path(&fasta_path);
+    reference
+        .sequence()
+        .map_err(|e| ParamsValidationError::InvalidReferenceGenomeFasta(e.to_string()))?;
+    Ok(reference)
+}
+
 pub fn dr_presets_names() -> Vec<&'static str> {
     let mut all_version_names = PRESETS.keys().cloned().collect::<Vec<_>>();
     all_version_names.sort();
@@ -549,6 +847,7 @@ fn process_qc_ref_number(n1: u32, n2: Option<u32>) -> Option<Range<u32>> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::helper::consensus::ConsensusParams;
 
     static JSON_STR: &str = r#"
         {
@@ -698,6 +997,196 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_validate_consensus_strategy() {
+        let json = std::fs::read_to_string("tests/data/test_params.json").unwrap();
+        let mut params: Params = serde_json::from_str(&json).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].consensus_strategy,
+            Some(ConsensusStrategy::SimpleMajority)
+        );
+
+        params.primer_pairs[0].majority = 0.0;
+        let validated_params = params.validate().unwrap();
+        assert_eq!(validated_params.primer_pairs[0].consensus_strategy, None);
+
+        params.primer_pairs[0].majority = 0.7;
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].consensus_strategy,
+            Some(ConsensusStrategy::Supermajority(0.7f32 as f64))
+        );
+
+        params.primer_pairs[0].consensus_strategy =
+            serde_json::from_str(r#"{"Weighted": {"k": 0.3, "q0": 25.0}}"#).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0]
+                .consensus_strategy_or(ConsensusStrategy::SimpleMajority),
+            ConsensusStrategy::Weighted(ConsensusParams::new(0.3, 25.0))
+        );
+
+        params.primer_pairs[0].consensus_strategy = Some(ConsensusStrategy::Supermajority(0.3));
+        assert!(params.validate().is_err());
+
+        params.primer_pairs[0].consensus_strategy =
+            serde_json::from_str(r#"{"Bayesian": 0.02}"#).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].consensus_strategy,
+            Some(ConsensusStrategy::Bayesian(0.02))
+        );
+
+        params.primer_pairs[0].consensus_strategy = Some(ConsensusStrategy::Bayesian(0.5));
+        assert!(params.validate().is_err());
+
+        params.primer_pairs[0].consensus_strategy = None;
+        params.primer_pairs[0].majority = 1.5;
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_reference_genome() {
+        let json = std::fs::read_to_string("tests/data/test_params.json").unwrap();
+        let mut params: Params = serde_json::from_str(&json).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0]
+                .qc_config
+                .as_ref()
+                .unwrap()
+                .reference,
+            QcReference::HXB2
+        );
+
+        params.primer_pairs[0].ref_genome = "HIV2_ROD".to_string();
+        assert!(params.validate().is_err());
+
+        params.primer_pairs[0].ref_genome = "tests/data/references/HXB2_PR.fasta".to_string();
+        let validated_params = params.validate().unwrap();
+        let reference = &validated_params.primer_pairs[0]
+            .qc_config
+            .as_ref()
+            .unwrap()
+            .reference;
+        assert_eq!(reference.to_string(), "HXB2_PR");
+        assert_eq!(reference.sequence().unwrap().len(), 451);
+
+        params.primer_pairs[0].ref_genome = "HXB2_PR".to_string();
+        assert!(params.validate().is_err());
+        params.reference_dir = Some("tests/data/references".to_string());
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0]
+                .qc_config
+                .as_ref()
+                .unwrap()
+                .reference,
+            QcReference::from_fasta_path(Path::new("tests/data/references/HXB2_PR.fasta"))
+        );
+
+        // end-join option 5 needs the reference without QC or trimming
+        params.reference_dir = None;
+        params.primer_pairs[0].tcs_qc = false;
+        params.primer_pairs[0].trim = false;
+        params.primer_pairs[0].end_join = true;
+        params.primer_pairs[0].end_join_option = 5;
+        params.primer_pairs[0].ref_genome = "HIV2_ROD".to_string();
+        assert!(params.validate().is_err());
+
+        params.primer_pairs[0].ref_genome = "SIVmm239".to_string();
+        let validated_params = params.validate().unwrap();
+        assert!(validated_params.primer_pairs[0].qc_config.is_none());
+        assert_eq!(
+            validated_params.primer_pairs[0].reference,
+            QcReference::SIVmm239
+        );
+    }
+
+    #[test]
+    fn test_validate_primer_mismatch_tolerance() {
+        let json = std::fs::read_to_string("tests/data/test_params.json").unwrap();
+        let mut params: Params = serde_json::from_str(&json).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].primer_mismatch_tolerance,
+            CountOrFraction::Count(2)
+        );
+
+        params.primer_pairs[0].primer_mismatch_tolerance =
+            serde_json::from_str(r#"{"Fraction": 0.1}"#).unwrap();
+        let validated_params = params.validate().unwrap();
+        let tolerance = validated_params.primer_pairs[0].primer_mismatch_tolerance;
+        assert_eq!(tolerance, CountOrFraction::Fraction(0.1));
+        assert_eq!(tolerance.max_count(25), 2);
+        assert_eq!(tolerance.max_count(50), 5);
+
+        params.primer_pairs[0].primer_mismatch_tolerance = Some(CountOrFraction::Fraction(1.5));
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_max_ambiguous_bases() {
+        let json = std::fs::read_to_string("tests/data/test_params.json").unwrap();
+        let mut params: Params = serde_json::from_str(&json).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].max_ambiguous_bases,
+            CountOrFraction::Count(0)
+        );
+
+        params.primer_pairs[0].max_ambiguous_bases =
+            serde_json::from_str(r#"{"Fraction": 0.01}"#).unwrap();
+        let validated_params = params.validate().unwrap();
+        let max_ambiguous_bases = validated_params.primer_pairs[0].max_ambiguous_bases;
+        assert_eq!(max_ambiguous_bases.max_count(250), 2);
+        assert_eq!(max_ambiguous_bases.max_count(99), 0);
+
+        params.primer_pairs[0].max_ambiguous_bases = Some(CountOrFraction::Fraction(-0.1));
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_iupac_min_share() {
+        let json = std::fs::read_to_string("tests/data/test_params.json").unwrap();
+        let mut params: Params = serde_json::from_str(&json).unwrap();
+        params.primer_pairs[0].iupac_min_share = Some(0.2);
+        let validated_params = params.validate().unwrap();
+        assert_eq!(validated_params.primer_pairs[0].iupac_min_share, Some(0.2));
+
+        params.primer_pairs[0].iupac_min_share = Some(0.6);
+        assert!(params.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_overlap_search() {
+        let json = std::fs::read_to_string("tests/data/test_params.json").unwrap();
+        let mut params: Params = serde_json::from_str(&json).unwrap();
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].overlap_search,
+            OverlapSearchParams::default()
+        );
+
+        params.primer_pairs[0].min_overlap = Some(20);
+        params.primer_pairs[0].overlap_error_rate = Some(0.05);
+        let validated_params = params.validate().unwrap();
+        assert_eq!(
+            validated_params.primer_pairs[0].overlap_search,
+            OverlapSearchParams {
+                min_overlap: 20,
+                error_rate: 0.05
+            }
+        );
+
+        params.primer_pairs[0].min_overlap = Some(0);
+        assert!(params.validate().is_err());
+        params.primer_pairs[0].min_overlap = None;
+        params.primer_pairs[0].overlap_error_rate = Some(0.8);
+        assert!(params.validate().is_err());
+    }
+
     #[test]
     fn test_validate_params_invalid() {
         let params: Params = serde_json::from_str(JSON_STR).unwrap();
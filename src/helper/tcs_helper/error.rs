@@ -33,6 +33,16 @@ pub enum TcsError {
         "Invalid read length: Platform Format: {0}, should be equal or less to Read 1 Length: {1} and Read 2: {2}"
     )]
     InvalidReadLength(usize, usize, usize),
+    #[error("R1 and R2 record counts do not match: R1 has {0} records, R2 has {1} records")]
+    RecordCountMismatch(usize, usize),
+    #[error("R1 and R2 read names differ in {0} pair(s), first: R1: {1}, R2: {2}")]
+    ReadNameMismatch(usize, String, String),
+    #[error("Truncated gzip file: {0}, {1} records read before the end of the file")]
+    TruncatedGzip(String, usize),
+    #[error("Failed to parse {1} record(s) in {0}, first error: {2}")]
+    FastqParseError(String, usize, String),
+    #[error("Failed to read {0}: {1}")]
+    FastqReadError(String, String),
     #[error("Failed to access the param file from the given path: {0}")]
     ParamFileAccessError(String),
     #[error("Unexpected error: {0}")]
@@ -0,0 +1,226 @@
+use bio::io::fastq::Record;
+use getset::{Getters, Setters};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+// Heterogeneity analysis of UMI families
+// A UMI family is expected to hold the reads of a single template. With a UMI collision (two templates tagged with the same UMI)
+// or a PCR recombinant, the family holds two templates, and its consensus is a chimera or is dropped for ambiguous bases.
+// The minor allele fraction of each position of R1 and R2 is calculated, and a family is bimodal
+// if its reads fall into two haplotypes over the heterogeneous positions.
+
+/// What to do with the bimodal UMI families of a region.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FamilyHeterogeneityAction {
+    /// Report the heterogeneity of the families, the consensus of a bimodal family is built from all of its reads.
+    Report,
+    /// Split a bimodal family into its two haplotypes, a haplotype with more reads than the UMI cut-off makes a consensus.
+    Split,
+    /// Drop the bimodal families.
+    Drop,
+}
+
+/// A position is heterogeneous if the minor allele has at least this fraction of the reads of the family...
+pub const HETEROGENEOUS_MINOR_ALLELE_FRACTION: f64 = 0.2;
+/// ...and at least this number of reads.
+pub const HETEROGENEOUS_MINOR_ALLELE_COUNT: usize = 2;
+/// A bimodal family has at least this number of heterogeneous positions (a single position is often an early PCR error),
+pub const BIMODAL_MIN_HETEROGENEOUS_POSITIONS: usize = 2;
+/// and its two major haplotypes cover at least this fraction of the reads.
+pub const BIMODAL_MIN_HAPLOTYPE_COVERAGE: f64 = 0.8;
+
+/// Heterogeneity of a UMI family, kept with each TCS (and in the TCS report), so only the counts of positions are stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Getters)]
+pub struct FamilyHeterogeneity {
+    /// number of R1 positions with a minor allele
+    #[serde(default)]
+    #[getset(get = "pub")]
+    r1_minor_allele_positions: usize,
+    /// number of R2 positions with a minor allele
+    #[serde(default)]
+    #[getset(get = "pub")]
+    r2_minor_allele_positions: usize,
+    #[getset(get = "pub")]
+    heterogeneous_positions: usize,
+    #[getset(get = "pub")]
+    bimodal: bool,
+    /// number of reads of the two major haplotypes of a bimodal family
+    #[getset(get = "pub")]
+    haplotype_sizes: Option<(usize, usize)>,
+}
+
+/// Indices of the reads of the two major haplotypes of a bimodal family, the most abundant first.
+pub type HaplotypeReads = (Vec<usize>, Vec<usize>);
+
+/// Counts of the heterogeneity analysis of the UMI families of a region.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Getters, Setters)]
+pub struct FamilyHeterogeneitySummary {
+    #[getset(get = "pub", set = "pub")]
+    analyzed_families: usize,
+    #[getset(get = "pub", set = "pub")]
+    bimodal_families: usize,
+    /// bimodal families with the consensus of at least one haplotype built
+    #[getset(get = "pub", set = "pub")]
+    split_families: usize,
+    /// bimodal families dropped, including the split families with both haplotypes at or below the UMI cut-off
+    #[getset(get = "pub", set = "pub")]
+    dropped_families: usize,
+}
+
+/// Analyzes the heterogeneity of a UMI family from its R1 and R2 reads (R1 and R2 of a pair at the same index).
+/// The reads are compared position by position, so the R1 reads must all have the same length, and so must the R2 reads:
+/// an indel in some reads would shift all the later positions and make them heterogeneous.
+/// # Returns
+/// * `Some((FamilyHeterogeneity, Option<HaplotypeReads>))` - The heterogeneity of the family,
+///   and for a bimodal family, the indices of the reads of its two major haplotypes.
+/// * `None` - The R1 or the R2 reads have different lengths, the family is not analyzed.
+pub fn analyze_family_heterogeneity(
+    r1_reads: &[Record],
+    r2_reads: &[Record],
+) -> Option<(FamilyHeterogeneity, Option<HaplotypeReads>)> {
+    if !r1_reads.iter().map(|r| r.seq().len()).all_equal()
+        || !r2_reads.iter().map(|r| r.seq().len()).all_equal()
+    {
+        return None;
+    }
+    let (r1_minor_allele_fractions, r1_heterogeneous) = minor_allele_fractions(r1_reads);
+    let (r2_minor_allele_fractions, r2_heterogeneous) = minor_allele_fractions(r2_reads);
+    let heterogeneous_positions = r1_heterogeneous.len() + r2_heterogeneous.len();
+
+    let mut heterogeneity = FamilyHeterogeneity {
+        r1_minor_allele_positions: r1_minor_allele_fractions.len(),
+        r2_minor_allele_positions: r2_minor_allele_fractions.len(),
+        heterogeneous_positions,
+        bimodal: false,
+        haplotype_sizes: None,
+    };
+    if heterogeneous_positions < BIMODAL_MIN_HETEROGENEOUS_POSITIONS {
+        return Some((heterogeneity, None));
+    }
+
+    // haplotype of each read pair over the heterogeneous positions of R1 and R2
+    let haplotypes = r1_reads
+        .iter()
+        .zip(r2_reads)
+        .map(|(r1, r2)| {
+            r1_heterogeneous
+                .iter()
+                .map(|&i| r1.seq()[i])
+                .chain(r2_heterogeneous.iter().map(|&i| r2.seq()[i]))
+                .collect::<Vec<u8>>()
+        })
+        .collect::<Vec<_>>();
+    let major_haplotypes = haplotypes
+        .iter()
+        .counts()
+        .into_iter()
+        .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+        .take(2)
+        .collect::<Vec<_>>();
+    let [(first, first_count), (second, second_count)] = major_haplotypes[..] else {
+        return Some((heterogeneity, None));
+    };
+
+    let family_size = haplotypes.len() as f64;
+    if second_count < HETEROGENEOUS_MINOR_ALLELE_COUNT
+        || (second_count as f64) < HETEROGENEOUS_MINOR_ALLELE_FRACTION * family_size
+        || ((first_count + second_count) as f64) < BIMODAL_MIN_HAPLOTYPE_COVERAGE * family_size
+    {
+        return Some((heterogeneity, None));
+    }
+
+    let reads_of = |haplotype: &Vec<u8>| {
+        haplotypes
+            .iter()
+            .positions(|h| h == haplotype)
+            .collect::<Vec<_>>()
+    };
+    let haplotype_reads = (reads_of(first), reads_of(second));
+    heterogeneity.bimodal = true;
+    heterogeneity.haplotype_sizes = Some((first_count, second_count));
+    Some((heterogeneity, Some(haplotype_reads)))
+}
+
+/// Minor allele fraction (second most frequent base over the number of reads) of each position of the reads of the same length,
+/// listed for the positions with a minor allele, and the heterogeneous positions.
+fn minor_allele_fractions(reads: &[Record]) -> (Vec<(usize, f64)>, Vec<usize>) {
+    let length = reads.first().map_or(0, |r| r.seq().len());
+    let mut fractions = Vec::new();
+    let mut heterogeneous = Vec::new();
+    for position in 0..length {
+        let minor_count = reads
+            .iter()
+            .map(|r| r.seq()[position])
+            .counts()
+            .into_values()
+            .sorted_by(|a, b| b.cmp(a))
+            .nth(1)
+            .unwrap_or(0);
+        if minor_count == 0 {
+            continue;
+        }
+        let fraction = minor_count as f64 / reads.len() as f64;
+        fractions.push((position, fraction));
+        if minor_count >= HETEROGENEOUS_MINOR_ALLELE_COUNT
+            && fraction >= HETEROGENEOUS_MINOR_ALLELE_FRACTION
+        {
+            heterogeneous.push(position);
+        }
+    }
+    (fractions, heterogeneous)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(seqs: &[&str]) -> Vec<Record> {
+        seqs.iter()
+            .map(|seq| Record::with_attrs("read", None, seq.as_bytes(), &vec![b'I'; seq.len()]))
+            .collect()
+    }
+
+    #[test]
+    fn test_analyze_family_heterogeneity() {
+        // one read with a sequencing error at position 3
+        let r1 = records(&["ACGTACGT", "ACGTACGT", "ACGAACGT", "ACGTACGT", "ACGTACGT"]);
+        let r2 = records(&["TTGGCCAA"; 5]);
+        assert_eq!(minor_allele_fractions(&r1), (vec![(3, 0.2)], Vec::new()));
+        let (heterogeneity, haplotypes) = analyze_family_heterogeneity(&r1, &r2).unwrap();
+        assert_eq!(*heterogeneity.r1_minor_allele_positions(), 1);
+        assert_eq!(*heterogeneity.r2_minor_allele_positions(), 0);
+        assert_eq!(*heterogeneity.heterogeneous_positions(), 0);
+        assert!(!heterogeneity.bimodal());
+        assert!(haplotypes.is_none());
+
+        // two templates differing at position 1 of R1 and position 6 of R2
+        let r1 = records(&[
+            "ACGTACGT", "ACGTACGT", "ATGTACGT", "ACGTACGT", "ATGTACGT", "ATGTACGA",
+        ]);
+        let r2 = records(&[
+            "TTGGCCAA", "TTGGCCAA", "TTGGCCGA", "TTGGCCAA", "TTGGCCGA", "TTGGCCGA",
+        ]);
+        let (heterogeneity, haplotypes) = analyze_family_heterogeneity(&r1, &r2).unwrap();
+        assert_eq!(*heterogeneity.heterogeneous_positions(), 2);
+        assert!(heterogeneity.bimodal());
+        assert_eq!(*heterogeneity.haplotype_sizes(), Some((3, 3)));
+        assert_eq!(haplotypes, Some((vec![0, 1, 3], vec![2, 4, 5])));
+    }
+
+    #[test]
+    fn test_analyze_family_heterogeneity_indel() {
+        // one template, 3 of the 8 reads with a deletion in the AAAA homopolymer of R1
+        let r1 = records(&[
+            "ACGTAAAACGTTGCAGT",
+            "ACGTAAACGTTGCAGT",
+            "ACGTAAAACGTTGCAGT",
+            "ACGTAAACGTTGCAGT",
+            "ACGTAAAACGTTGCAGT",
+            "ACGTAAAACGTTGCAGT",
+            "ACGTAAACGTTGCAGT",
+            "ACGTAAAACGTTGCAGT",
+        ]);
+        let r2 = records(&["TTGGCCAA"; 8]);
+        assert!(analyze_family_heterogeneity(&r1, &r2).is_none());
+    }
+}
@@ -8,12 +8,13 @@ use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use crate::helper::params::{CDNAMatching, ForwardMatching, ValidatedParams};
+use crate::helper::io::read_name;
+use crate::helper::params::{CDNAMatching, ForwardMatching, PrimerMatchingMethod, ValidatedParams};
 use crate::helper::tcs_helper::*;
-use crate::helper::umi::UMI;
+use crate::helper::umi::{UMI, UMIType};
 
 // MARK: FilteredPair
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FilteredPair {
     pub region: String,
     pub umi: UMI,
@@ -37,6 +38,10 @@ pub enum FilterPairInvalidReason {
     R2MatchR1Mismatch(String),
     R1R2MatchDifferentRegions(String),
     NoMatch(String),
+    // R1 and R2 match several regions with the same number of primer mismatches, the tied regions are listed.
+    AmbiguousRegion(String),
+    // R1 and R2 match the primers of the listed regions, but the spacer bases of the patterned UMI in R2 do not match the cDNA primer.
+    UMIPatternMismatch(String),
 }
 
 // use std::fmt::{self, Display};
@@ -111,67 +116,114 @@ pub fn filter_r1_r2_pairs(
     // and return them at the end if no valid pair is found.
     // This is particularly important when some regions share the same forward or cDNA primer,
     // Early return would prevent checking all regions.
+    // For the same reason, every region matched by both R1 and R2 is kept as a candidate with its total primer mismatches,
+    // so that the pair is assigned to the best matching region instead of the first one in the params.
     let mut region_no_matches: HashMap<String, FilterPairInvalidReason> = HashMap::new();
+    let mut candidates: Vec<(usize, FilteredPair)> = Vec::new();
 
     for region_params in &params.primer_pairs {
         let region = &region_params.region;
         let forward_matching = &region_params.forward_matching;
         let cdna_matching = &region_params.cdna_matching;
+        let primer_mismatch_tolerance = &region_params.primer_mismatch_tolerance;
 
-        // Check if R1 matches the forward matching config
-        let r1_match = match r1_matching(r1_trunc, forward_matching) {
-            Ok(Some(record)) => Some(record),
-            Ok(None) => None, // No match, continue to next region
-            Err(e) => return Err(e),
-        };
+        // Check if R1 matches the forward matching config, None if R1 does not match
+        let r1_match = r1_matching(
+            r1_trunc,
+            forward_matching,
+            primer_mismatch_tolerance.max_count(forward_matching.bio_forward.len()),
+            region_params.primer_matching,
+        )?;
 
-        let r2_match = match r2_matching(r2_trunc, cdna_matching) {
-            Ok((Some(umi), Some(record))) => Some((umi, record)),
-            Ok((None, None)) => None,
-            Err(e) => return Err(e),
-            _ => None,
-        };
+        let r2_match = r2_matching(
+            r2_trunc,
+            cdna_matching,
+            primer_mismatch_tolerance.max_count(cdna_matching.bio_cdna.len()),
+            region_params.primer_matching,
+        )?;
 
-        if r1_match.is_some() && r2_match.is_some() {
-            let (umi, r2_record) = r2_match.unwrap();
-            let r1_record = r1_match.unwrap();
-
-            // Create the filtered pair
-            let filtered_pair = FilteredPair {
-                region: region.clone(),
-                umi,
-                r1: r1_record,
-                r2: reverse_complement(&r2_record), // MARK: reverse compl R2
-            };
-
-            return Ok(PairedRecordFilterResult::Valid(filtered_pair));
-        } else if r1_match.is_some() && r2_match.is_none() {
-            // R1 matches but R2 does not
-
-            region_no_matches.insert(
-                region.clone(),
-                FilterPairInvalidReason::R1MatchR2Mismatch(region.to_string()),
-            );
-        } else if r1_match.is_none() && r2_match.is_some() {
-            // R2 matches but R1 does not
-            region_no_matches.insert(
-                region.clone(),
-                FilterPairInvalidReason::R2MatchR1Mismatch(region.to_string()),
-            );
-        } else {
-            // Neither R1 nor R2 matches
-            region_no_matches.insert(
-                region.clone(),
-                FilterPairInvalidReason::NoMatch("No match".to_string()),
-            );
+        match (r1_match, r2_match) {
+            (
+                Some((r1_record, r1_mismatches)),
+                R2Matching::Matched(umi, r2_record, r2_mismatches),
+            ) => {
+                // Create the filtered pair
+                let filtered_pair = FilteredPair {
+                    region: region.clone(),
+                    umi,
+                    r1: r1_record,
+                    r2: reverse_complement(&r2_record), // MARK: reverse compl R2
+                };
+
+                candidates.push((r1_mismatches + r2_mismatches, filtered_pair));
+            }
+            (Some(_), R2Matching::UMIPatternMismatch) => {
+                // Both primers match, but the UMI of R2 is corrupted
+                region_no_matches.insert(
+                    region.clone(),
+                    FilterPairInvalidReason::UMIPatternMismatch(region.to_string()),
+                );
+            }
+            (Some(_), R2Matching::NoMatch) => {
+                // R1 matches but R2 does not
+                region_no_matches.insert(
+                    region.clone(),
+                    FilterPairInvalidReason::R1MatchR2Mismatch(region.to_string()),
+                );
+            }
+            (None, R2Matching::Matched(..) | R2Matching::UMIPatternMismatch) => {
+                // R2 matches but R1 does not
+                region_no_matches.insert(
+                    region.clone(),
+                    FilterPairInvalidReason::R2MatchR1Mismatch(region.to_string()),
+                );
+            }
+            (None, R2Matching::NoMatch) => {
+                // Neither R1 nor R2 matches
+                region_no_matches.insert(
+                    region.clone(),
+                    FilterPairInvalidReason::NoMatch("No match".to_string()),
+                );
+            }
         }
     }
 
+    if !candidates.is_empty() {
+        return Ok(resolve_candidate_regions(candidates));
+    }
+
     let no_match_reason = consolidate_no_match(&region_no_matches)?;
 
     Ok(PairedRecordFilterResult::Invalid(no_match_reason))
 }
 
+// MARK: resolve_candidate_regions
+// Chooses the region with the fewest primer mismatches (R1 + R2) among the regions matched by both R1 and R2.
+// A tie between regions is reported as AmbiguousRegion, with the tied regions sorted by name.
+fn resolve_candidate_regions(candidates: Vec<(usize, FilteredPair)>) -> PairedRecordFilterResult {
+    let best_mismatches = candidates
+        .iter()
+        .map(|(mismatches, _)| *mismatches)
+        .min()
+        .unwrap_or(0);
+    let mut best_candidates = candidates
+        .into_iter()
+        .filter(|(mismatches, _)| *mismatches == best_mismatches)
+        .map(|(_, filtered_pair)| filtered_pair)
+        .collect::<Vec<_>>();
+
+    if best_candidates.len() == 1 {
+        return PairedRecordFilterResult::Valid(best_candidates.pop().unwrap());
+    }
+
+    let mut regions = best_candidates
+        .iter()
+        .map(|filtered_pair| filtered_pair.region.clone())
+        .collect::<Vec<_>>();
+    regions.sort();
+    PairedRecordFilterResult::Invalid(FilterPairInvalidReason::AmbiguousRegion(regions.join(", ")))
+}
+
 // MARK: validate_paired_fastq_record
 
 pub fn validate_paired_fastq_record(
@@ -182,14 +234,20 @@ pub fn validate_paired_fastq_record(
         return Err(TcsError::EmptyFastqRecord.into());
     }
 
-    let r1_header = r1_record.id().split_whitespace().next().ok_or_else(|| {
-        Box::new(TcsError::InvalidR1Header(r1_record.id().to_string()))
-            as Box<dyn Error + Send + Sync>
-    })?;
-    let r2_header = r2_record.id().split_whitespace().next().ok_or_else(|| {
-        Box::new(TcsError::InvalidR2Header(r2_record.id().to_string()))
-            as Box<dyn Error + Send + Sync>
-    })?;
+    let r1_header = read_name(r1_record)
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| {
+            Box::new(TcsError::InvalidR1Header(r1_record.id().to_string()))
+                as Box<dyn Error + Send + Sync>
+        })?;
+    let r2_header = read_name(r2_record)
+        .split_whitespace()
+        .next()
+        .ok_or_else(|| {
+            Box::new(TcsError::InvalidR2Header(r2_record.id().to_string()))
+                as Box<dyn Error + Send + Sync>
+        })?;
 
     if r1_header != r2_header {
         return Err(
@@ -248,10 +306,16 @@ fn general_filter(r1_record: &Record, r2_record: &Record) -> GeneralFilterResult
 }
 
 // MARK: r1_matching and r2_matching
+// A primer matches if the number of mismatches (IUPAC aware) with the read is at most `max_mismatches`,
+// resolved from the primer mismatch tolerance of the region.
+// With the alignment method, a read failing the fixed window is aligned to the primer (see align_primer_end),
+// and the read is trimmed at the aligned end of the primer.
 fn r1_matching(
     r1_record: &Record,
     forward_matching: &ForwardMatching,
-) -> Result<Option<Record>, Box<dyn Error + Send + Sync>> {
+    max_mismatches: usize,
+    method: PrimerMatchingMethod,
+) -> Result<Option<(Record, usize)>, Box<dyn Error + Send + Sync>> {
     let r1_seq = from_utf8(r1_record.seq()).ok().unwrap() as &str;
     let bio_forward = &forward_matching.bio_forward;
     let leading_ns = forward_matching.leading_n_number as usize;
@@ -263,17 +327,47 @@ fn r1_matching(
 
     let diff = diff_by_iupac(primer_region_r1, bio_forward).len();
 
-    if diff < 3 {
-        return Ok(Some(r1_record.get_range(trim_start_number..r1_seq.len())?));
+    let primer_end = if diff <= max_mismatches {
+        Some((trim_start_number, diff))
     } else {
-        return Ok(None);
+        match method {
+            PrimerMatchingMethod::FixedWindow => None,
+            // an indel in the leading N stagger shifts the start of the primer
+            PrimerMatchingMethod::Alignment => align_primer_end(
+                r1_seq,
+                bio_forward,
+                leading_ns,
+                max_mismatches,
+                max_mismatches,
+            ),
+        }
+    };
+
+    match primer_end {
+        Some((primer_end, mismatches)) => Ok(Some((
+            r1_record.get_range(primer_end..r1_seq.len())?,
+            mismatches,
+        ))),
+        None => Ok(None),
     }
 }
 
+// Outcome of r2_matching
+#[derive(Debug, PartialEq)]
+enum R2Matching {
+    // UMI, trimmed R2 and number of primer mismatches
+    Matched(UMI, Record, usize),
+    // the cDNA primer matches, but a spacer base of the patterned UMI does not match its IUPAC code in the cDNA primer
+    UMIPatternMismatch,
+    NoMatch,
+}
+
 fn r2_matching(
     r2_record: &Record,
     cdna_matching: &CDNAMatching,
-) -> Result<(Option<UMI>, Option<Record>), Box<dyn Error + Send + Sync>> {
+    max_mismatches: usize,
+    method: PrimerMatchingMethod,
+) -> Result<R2Matching, Box<dyn Error + Send + Sync>> {
     let r2_seq = from_utf8(r2_record.seq()).ok().unwrap() as &str;
     let bio_cdna = &cdna_matching.bio_cdna;
     let umi_size = cdna_matching.umi.umi_block.len() as usize;
@@ -311,15 +405,68 @@ fn r2_matching(
     // match ambuiguity codes in primer region
     let diff = diff_by_iupac(primer_region_r2, bio_cdna).len();
 
-    if diff < 3 {
-        // UMI identification logic can be added here
-        Ok((
-            Some(r2_umi),
-            Some(r2_record.get_range(trim_start_number..r2_seq.len())?),
-        ))
+    let primer_end = if diff <= max_mismatches {
+        Some((trim_start_number, diff))
     } else {
-        Ok((None, None))
+        match method {
+            PrimerMatchingMethod::FixedWindow => None,
+            // the UMI block has a fixed length, the alignment window starts right after it
+            PrimerMatchingMethod::Alignment => {
+                align_primer_end(r2_seq, bio_cdna, umi_size, 0, max_mismatches)
+            }
+        }
+    };
+
+    match primer_end {
+        // the spacer bases (outside of the information index) of a patterned UMI must match the cDNA primer,
+        // the Ns of the UMI block match any base.
+        Some(_)
+            if cdna_matching.umi.umi_type == UMIType::UMIWithPattern
+                && !diff_by_iupac(r2_umi_block, &cdna_matching.umi.umi_block).is_empty() =>
+        {
+            Ok(R2Matching::UMIPatternMismatch)
+        }
+        Some((primer_end, mismatches)) => Ok(R2Matching::Matched(
+            r2_umi,
+            r2_record.get_range(primer_end..r2_seq.len())?,
+            mismatches,
+        )),
+        None => Ok(R2Matching::NoMatch),
+    }
+}
+
+// Aligns the primer to the read, with the primer expected at `primer_start`, and returns the end of the primer in the read
+// and the number of edits, if the alignment has at most `max_mismatches` edits.
+// The primer is aligned to a window of the read from `max_start_shift` bases before `primer_start`
+// to `max_mismatches` bases after the expected end of the primer (clipped to the read length).
+// A read with a small indel keeps one half of the primer in place, either the first half at `primer_start`
+// or the second half shifted by at most `max_mismatches` bases.
+// Reads without such a half (mostly reads of the other regions) are not aligned, which keeps the alignment method fast.
+fn align_primer_end(
+    seq: &str,
+    primer: &str,
+    primer_start: usize,
+    max_start_shift: usize,
+    max_mismatches: usize,
+) -> Option<(usize, usize)> {
+    let half = primer.len() / 2;
+    let half_matches = |start: usize, primer_half: &str| {
+        seq.get(start..start + primer_half.len())
+            .is_some_and(|read_half| diff_by_iupac(read_half, primer_half).len() <= max_mismatches)
+    };
+    let suffix_start = primer_start + half;
+    if !half_matches(primer_start, &primer[..half])
+        && !(suffix_start.saturating_sub(max_mismatches)..=suffix_start + max_mismatches)
+            .any(|start| half_matches(start, &primer[half..]))
+    {
+        return None;
     }
+
+    let window_start = primer_start.saturating_sub(max_start_shift);
+    let window_end = (primer_start + primer.len() + max_mismatches).min(seq.len());
+    let window = seq.as_bytes().get(window_start..window_end)?;
+    let alignment = align_primer_iupac(window, primer.as_bytes())?;
+    (alignment.edits <= max_mismatches).then_some((window_start + alignment.end, alignment.edits))
 }
 
 // MARK: consolidate_no_match
@@ -344,6 +491,19 @@ fn consolidate_no_match(
         return Ok(FilterPairInvalidReason::NoMatch("No match".to_string()));
     }
 
+    // both primers of these regions match, the UMI pattern mismatch is reported over the other reasons.
+    let mut umi_pattern_regions = region_no_matches
+        .iter()
+        .filter(|(_, reason)| matches!(reason, FilterPairInvalidReason::UMIPatternMismatch(_)))
+        .map(|(region, _)| region.clone())
+        .collect::<Vec<_>>();
+    if !umi_pattern_regions.is_empty() {
+        umi_pattern_regions.sort();
+        return Ok(FilterPairInvalidReason::UMIPatternMismatch(
+            umi_pattern_regions.join(", "),
+        ));
+    }
+
     // code for R1R2MatchDifferentRegions
     let mut r1_regions = Vec::new();
     let mut r2_regions = Vec::new();
@@ -381,10 +541,14 @@ fn consolidate_no_match(
 
 mod tests {
     use super::*;
+    use crate::helper::consensus::UnequalLengthConsensus;
+    use crate::helper::end_joining::OverlapSearchParams;
     use crate::helper::params::{
+        CountOrFraction, DEFAULT_MAX_AMBIGUOUS_BASES, DEFAULT_PRIMER_MISMATCH_TOLERANCE,
         ForwardMatching, ValidatedRegionParams, validate_cdna_primer, validate_forward_primer,
     };
-    use crate::helper::umi::{UMI, UMIType};
+    use crate::helper::tcs_helper::QcReference;
+    use crate::helper::umis::{UMIClustering, UMICutOffModel};
     use bio::io::fastq::Record;
 
     #[test]
@@ -393,6 +557,10 @@ mod tests {
         let r2_record = Record::with_attrs("myseq1 2:0:0", None, b"TGCA", b"IIII");
 
         assert!(validate_paired_fastq_record(&r1_record, &r2_record).is_ok());
+
+        let r1_record = Record::with_attrs("myseq1/1", None, b"ACGT", b"IIII");
+        let r2_record = Record::with_attrs("myseq1/2", None, b"TGCA", b"IIII");
+        assert!(validate_paired_fastq_record(&r1_record, &r2_record).is_ok());
     }
 
     #[test]
@@ -476,11 +644,17 @@ mod tests {
             leading_n_number: 4,
         };
 
-        let result = r1_matching(&r1_record, &forward_matching);
+        let result = r1_matching(
+            &r1_record,
+            &forward_matching,
+            2,
+            PrimerMatchingMethod::FixedWindow,
+        );
 
         assert!(result.is_ok());
-        if let Ok(Some(record)) = result {
+        if let Ok(Some((record, mismatches))) = result {
             assert_eq!(record.seq(), b"AAAAAAAAAAAAAAAAAAAAAA");
+            assert_eq!(mismatches, 0);
         } else {
             panic!("Expected a valid R1 match");
         }
@@ -492,11 +666,17 @@ mod tests {
             b"IIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIII",
         );
 
-        let result = r1_matching(&r1_record, &forward_matching);
+        let result = r1_matching(
+            &r1_record,
+            &forward_matching,
+            2,
+            PrimerMatchingMethod::FixedWindow,
+        );
 
         assert!(result.is_ok());
-        if let Ok(Some(record)) = result {
+        if let Ok(Some((record, mismatches))) = result {
             assert_eq!(record.seq(), b"AAAAAAAAAAAAAAAAAAAAAA");
+            assert_eq!(mismatches, 2);
         } else {
             panic!("Expected a valid R1 match");
         }
@@ -508,10 +688,76 @@ mod tests {
             b"IIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIIII",
         );
 
-        let result = r1_matching(&r1_record, &forward_matching);
+        let result = r1_matching(
+            &r1_record,
+            &forward_matching,
+            2,
+            PrimerMatchingMethod::FixedWindow,
+        );
 
         assert!(result.is_ok());
         assert!(result.unwrap().is_none(), "Expected no match for R1");
+
+        // 4 mismatches in a primer of 10 bases, matched with a tolerance of 4 or 40% of the primer length
+        let tolerance = CountOrFraction::Count(4);
+        let result = r1_matching(
+            &r1_record,
+            &forward_matching,
+            tolerance.max_count(10),
+            PrimerMatchingMethod::FixedWindow,
+        );
+        assert!(result.unwrap().is_some(), "Expected a valid R1 match");
+        let tolerance = CountOrFraction::Fraction(0.4);
+        let result = r1_matching(
+            &r1_record,
+            &forward_matching,
+            tolerance.max_count(10),
+            PrimerMatchingMethod::FixedWindow,
+        );
+        assert!(result.unwrap().is_some(), "Expected a valid R1 match");
+        let tolerance = CountOrFraction::Fraction(0.35);
+        let result = r1_matching(
+            &r1_record,
+            &forward_matching,
+            tolerance.max_count(10),
+            PrimerMatchingMethod::FixedWindow,
+        );
+        assert!(result.unwrap().is_none(), "Expected no match for R1");
+    }
+
+    #[test]
+    fn test_r1_matching_alignment() {
+        let forward_matching = ForwardMatching {
+            forward: "NNNNACGTAGCTAGC".to_string(),
+            bio_forward: "ACGTAGCTAG".to_string(),
+            leading_n_number: 4,
+        };
+
+        // one base (C) deleted in the primer (one edit), and one base inserted in the leading Ns (primer intact)
+        for (seq, edits) in [
+            (b"TCGAACGTAGTAGAAAAAAAAAAAAAAAAAAAAAA".as_slice(), 1),
+            (b"TCGATACGTAGCTAGAAAAAAAAAAAAAAAAAAAAAA".as_slice(), 0),
+        ] {
+            let r1_record = Record::with_attrs("test r1", None, seq, &vec![b'I'; seq.len()]);
+
+            let result = r1_matching(
+                &r1_record,
+                &forward_matching,
+                2,
+                PrimerMatchingMethod::FixedWindow,
+            );
+            assert!(result.unwrap().is_none(), "Expected no match for R1");
+
+            let result = r1_matching(
+                &r1_record,
+                &forward_matching,
+                2,
+                PrimerMatchingMethod::Alignment,
+            );
+            let (record, mismatches) = result.unwrap().expect("Expected a valid R1 match");
+            assert_eq!(record.seq(), b"AAAAAAAAAAAAAAAAAAAAAA");
+            assert_eq!(mismatches, edits);
+        }
     }
 
     #[test]
@@ -525,29 +771,74 @@ mod tests {
             None,
             b"TACTGTTTTACCAGTCCATTTTGCTCTATTGACGTTACAATGTGCTTGTCTCATATTTCCTATTTTTCCTATTGTAACAAATGCTCTCCCTGGTCCCCTCTGGATACGGATACTTTTTCTTGTATTGTTGTTGGGTCTTGTACAATTAATTTCTACAGATGTGTTCAGCTGTACTATTATGGTTTTAGCATTGTCCGTGAAATTGACAGATCTAATTACTACCTCTTCTTCTGCTAGACTGCCATTTAACAGCAGTTGAGTTGATACTACTGGCCTAATTCCATGTGTACATTGTACTGT",
             b"CCCCCGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGFGGGGGGGGGGEFCGGGFGGGFFGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGG9BEFGDGGGGGGGGGGGGGGGGGGFGGGGGFGGGGGGGGFFEFGGGGGGGGGFFFGGGGGGGFGFAAFFCGGGGGGGGGCFFGGGGGGGGGGEDGFGGGFGGGGGDFFFFGGGGCFFGGF8DGGGGFGGGGGFF<DBFFGFEEFFGGGFFFFFCEFEEFFFFFFFFFFEEF9@DECEEFEEEECE?EEFFFECEF4*");
-        let result = r2_matching(&r2_record, &cdna_matching);
+        let result = r2_matching(
+            &r2_record,
+            &cdna_matching,
+            2,
+            PrimerMatchingMethod::FixedWindow,
+        );
         assert!(result.is_ok());
         assert_eq!(
             result.unwrap(),
-            (
-                Some(UMI {
+            R2Matching::Matched(
+                UMI {
                     umi_type: UMIType::UMI,
                     umi_block: "TACTGTTTTAC".to_string(),
                     information_index: vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10],
                     umi_information_block: "TACTGTTTTAC".to_string(),
-                }),
-                Some(Record::with_attrs(
+                },
+                Record::with_attrs(
                     "test r2",
                     None,
                     b"TTGTCTCATATTTCCTATTTTTCCTATTGTAACAAATGCTCTCCCTGGTCCCCTCTGGATACGGATACTTTTTCTTGTATTGTTGTTGGGTCTTGTACAATTAATTTCTACAGATGTGTTCAGCTGTACTATTATGGTTTTAGCATTGTCCGTGAAATTGACAGATCTAATTACTACCTCTTCTTCTGCTAGACTGCCATTTAACAGCAGTTGAGTTGATACTACTGGCCTAATTCCATGTGTACATTGTACTGT",
                     b"GGGGGEFCGGGFGGGFFGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGG9BEFGDGGGGGGGGGGGGGGGGGGFGGGGGFGGGGGGGGFFEFGGGGGGGGGFFFGGGGGGGFGFAAFFCGGGGGGGGGCFFGGGGGGGGGGEDGFGGGFGGGGGDFFFFGGGGCFFGGF8DGGGGFGGGGGFF<DBFFGFEEFFGGGFFFFFCEFEEFFFFFFFFFFEEF9@DECEEFEEEECE?EEFFFECEF4*",
-                )),
+                ),
+                0,
             ),
         );
     }
 
     #[test]
-    fn test_filter_r1_r2_pairs() {
+    fn test_r2_matching_patterned_umi() {
+        let cdna_primer = "GTGACTGGAGTTCAGACGTGTGCTCTTCCGATCTNNNRYNNNRYNNNRYNNNCAGTCCATTTTGCTYTAYTRABVTTACAATRTGC";
+        let cdna_matching = validate_cdna_primer(cdna_primer).unwrap();
+        assert_eq!(cdna_matching.umi.umi_type, UMIType::UMIWithPattern);
+        let primer_and_insert = "CAGTCCATTTTGCTCTATTGACGTTACAATGTGCTTGTCTCATATTTCCTATTTTTCC";
+
+        // spacers AC, GT and AC match RY
+        let seq = format!("ACGACTTGGTCATACGGG{}", primer_and_insert);
+        let r2_record = Record::with_attrs("test r2", None, seq.as_bytes(), &vec![b'I'; seq.len()]);
+        match r2_matching(
+            &r2_record,
+            &cdna_matching,
+            2,
+            PrimerMatchingMethod::FixedWindow,
+        )
+        .unwrap()
+        {
+            R2Matching::Matched(umi, _, mismatches) => {
+                assert_eq!(umi.umi_information_block, "ACGTTGCATGGG");
+                assert_eq!(mismatches, 0);
+            }
+            other => panic!("Expected a valid R2 match, got: {:?}", other),
+        }
+
+        // the first spacer CC does not match RY
+        let seq = format!("ACGCCTTGGTCATACGGG{}", primer_and_insert);
+        let r2_record = Record::with_attrs("test r2", None, seq.as_bytes(), &vec![b'I'; seq.len()]);
+        assert_eq!(
+            r2_matching(
+                &r2_record,
+                &cdna_matching,
+                2,
+                PrimerMatchingMethod::FixedWindow,
+            )
+            .unwrap(),
+            R2Matching::UMIPatternMismatch
+        );
+    }
+
+    fn test_record_pair() -> (Record, Record) {
         let r2_record = Record::with_attrs(
             "M01825:522:000000000-C7M6N:1:1101:13543:1027 2:N:0:GCCTTAA",
             None,
@@ -561,29 +852,50 @@ mod tests {
             b"#8ACCGGGFGG9FEFGGGGGGGEGGGGGFGGGGGGGGGGGGGGGGGGGGGGGGGGGFGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGFGGGGGGGGGGGGGGGGFGGGGGGGGGGGGGGGGGGGGGGGGGGGGGFFGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGGDGGGGGGGFGGGGGGGGGGGGGGGGFFGGGGGGGGGGGDGGCGGGGGGFGGFGGGGGFGGF=CFFFFFCFFFFEEAAFFEEF;D6EFE8;",
         );
 
+        (r1_record, r2_record)
+    }
+
+    fn test_region_params(region: &str, forward_primer: &str) -> ValidatedRegionParams {
         let cdna_primer =
             "GTGACTGGAGTTCAGACGTGTGCTCTTCCGATCTNNNNNNNNNNNCAGTCCATTTTGCTYTAYTRABVTTACAATRTGC";
         let cdna_matching = validate_cdna_primer(cdna_primer).unwrap();
-
-        let forward_primer =
-            "GCCTCCCTCGCGCCATCAGAGATGTGTATAAGAGACAGNNNNTTATGGGATCAAAGCCTAAAGCCATGTGTA";
         let forward_matching = validate_forward_primer(forward_primer).unwrap();
 
-        let region_params = ValidatedRegionParams {
+        ValidatedRegionParams {
             platform_error_rate: 0.01,
             platform_format: 300,
-            region: "test_region".to_string(),
+            region: region.to_string(),
             forward_matching,
             cdna_matching,
             majority: 0.6,
+            consensus_strategy: None,
+            primer_mismatch_tolerance: DEFAULT_PRIMER_MISMATCH_TOLERANCE,
+            primer_matching: PrimerMatchingMethod::FixedWindow,
+            umi_clustering: UMIClustering::Exact,
+            umi_cut_off_model: UMICutOffModel::Polynomial,
+            family_heterogeneity: None,
+            max_ambiguous_bases: DEFAULT_MAX_AMBIGUOUS_BASES,
+            iupac_min_share: None,
+            unequal_length_consensus: UnequalLengthConsensus::Reject,
             end_join: false,
             end_join_option: 1,
             overlap: 0,
+            overlap_search: OverlapSearchParams::default(),
+            reference: QcReference::default(),
             tcs_qc: false,
             qc_config: None,
             trim: false,
             trim_config: None,
-        };
+        }
+    }
+
+    const TEST_FORWARD_PRIMER: &str =
+        "GCCTCCCTCGCGCCATCAGAGATGTGTATAAGAGACAGNNNNTTATGGGATCAAAGCCTAAAGCCATGTGTA";
+
+    #[test]
+    fn test_filter_r1_r2_pairs() {
+        let (r1_record, r2_record) = test_record_pair();
+        let region_params = test_region_params("test_region", TEST_FORWARD_PRIMER);
 
         let validated_params = ValidatedParams {
             primer_pairs: vec![region_params],
@@ -604,6 +916,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_filter_r1_r2_pairs_best_region() {
+        let (r1_record, r2_record) = test_record_pair();
+        // one mismatch with the R1 primer of the read at the last base of the forward primer
+        let one_mismatch_primer =
+            "GCCTCCCTCGCGCCATCAGAGATGTGTATAAGAGACAGNNNNTTATGGGATCAAAGCCTAAAGCCATGTGTC";
+
+        // the first region in the params matches with one mismatch, the second one matches exactly
+        let validated_params = ValidatedParams {
+            primer_pairs: vec![
+                test_region_params("one_mismatch", one_mismatch_primer),
+                test_region_params("exact", TEST_FORWARD_PRIMER),
+            ],
+        };
+        match filter_r1_r2_pairs(&r1_record, &r2_record, &validated_params).unwrap() {
+            PairedRecordFilterResult::Valid(filtered_pair) => {
+                assert_eq!(filtered_pair.region, "exact");
+            }
+            PairedRecordFilterResult::Invalid(msg) => panic!("Expected valid pair, got: {:?}", msg),
+        }
+
+        // two regions with the same primers tie
+        let validated_params = ValidatedParams {
+            primer_pairs: vec![
+                test_region_params("one_mismatch", one_mismatch_primer),
+                test_region_params("exact_b", TEST_FORWARD_PRIMER),
+                test_region_params("exact_a", TEST_FORWARD_PRIMER),
+            ],
+        };
+        assert_eq!(
+            filter_r1_r2_pairs(&r1_record, &r2_record, &validated_params).unwrap(),
+            PairedRecordFilterResult::Invalid(FilterPairInvalidReason::AmbiguousRegion(
+                "exact_a, exact_b".to_string()
+            ))
+        );
+    }
+
     #[test]
     fn test_consolidate_no_match() {
         let mut region_no_matches1 = HashMap::new();
@@ -660,6 +1009,12 @@ mod tests {
             ),
         );
 
+        let mut region_no_matches5 = region_no_matches4.clone();
+        region_no_matches5.insert(
+            "PR".to_string(),
+            FilterPairInvalidReason::UMIPatternMismatch("PR".to_string()),
+        );
+
         let result = consolidate_no_match(&region_no_matches1);
         assert!(result.is_ok());
         assert_eq!(
@@ -689,5 +1044,11 @@ mod tests {
                 "R1 regions: RT, R2 regions: V1V3".to_string()
             )
         );
+
+        let result = consolidate_no_match(&region_no_matches5);
+        assert_eq!(
+            result.unwrap(),
+            FilterPairInvalidReason::UMIPatternMismatch("PR".to_string())
+        );
     }
 }
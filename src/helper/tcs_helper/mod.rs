@@ -1,7 +1,11 @@
 pub mod error;
+pub mod family_heterogeneity;
 pub mod fastq_files;
 pub mod filter_r1_r2;
+pub mod tcs_batch_result;
+pub mod tcs_checkpoint;
 pub mod tcs_consensus;
+pub mod tcs_dr_result;
 pub mod tcs_output;
 pub mod tcs_qc;
 pub mod tcs_report;
@@ -9,12 +13,16 @@ pub mod tcs_summary;
 pub mod utils;
 
 pub use error::TcsError;
+pub use family_heterogeneity::*;
 pub use fastq_files::{FastqFiles, validate_files};
 pub use filter_r1_r2::{
     FilterPairInvalidReason, FilteredPair, PairedRecordFilterResult, filter_r1_r2_pairs,
 };
 
+pub use tcs_batch_result::{TcsBatchResult, TcsLibraryResult, TcsLibraryStatus};
+pub use tcs_checkpoint::*;
 pub use tcs_consensus::*;
+pub use tcs_dr_result::{DrBatchResult, DrLibraryResult, DrStage};
 pub use tcs_output::TcsOutput;
 pub use tcs_output::*;
 pub use tcs_qc::{QcAlgorithm, QcReference, TcsQcInput};
@@ -0,0 +1,196 @@
+use std::fmt::Display;
+
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+
+use crate::helper::tcs_helper::TcsReport;
+
+// Batch level result of the TCS pipeline run over every library subdirectory of a batch directory.
+// One TcsLibraryResult is recorded per library, and the Display implementation prints the batch summary table.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TcsLibraryStatus {
+    Success,
+    Warning,
+    Error,
+}
+
+impl Display for TcsLibraryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TcsLibraryStatus::Success => write!(f, "success"),
+            TcsLibraryStatus::Warning => write!(f, "warning"),
+            TcsLibraryStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct TcsLibraryResult {
+    #[getset(get = "pub", set = "pub")]
+    library: String,
+    #[getset(get = "pub", set = "pub")]
+    status: TcsLibraryStatus,
+    #[getset(get = "pub", set = "pub")]
+    total_reads: usize,
+    #[getset(get = "pub", set = "pub")]
+    warning_number: usize,
+    #[getset(get = "pub", set = "pub")]
+    errors: Vec<String>,
+}
+
+impl TcsLibraryResult {
+    /// Builds the result of one library from the outcome of the `tcs` function.
+    /// A fatal error or a report with errors is an `Error`, a report with warnings is a `Warning`.
+    pub fn from_tcs_outcome(library: &str, outcome: Result<TcsReport, String>) -> Self {
+        match outcome {
+            Ok(report) => {
+                let status = if !report.is_successful() {
+                    TcsLibraryStatus::Error
+                } else if !report.warnings().is_empty() {
+                    TcsLibraryStatus::Warning
+                } else {
+                    TcsLibraryStatus::Success
+                };
+                TcsLibraryResult {
+                    library: library.to_string(),
+                    status,
+                    total_reads: *report.total_reads(),
+                    warning_number: report.warnings().len(),
+                    errors: report.errors().clone(),
+                }
+            }
+            Err(e) => TcsLibraryResult {
+                library: library.to_string(),
+                status: TcsLibraryStatus::Error,
+                total_reads: 0,
+                warning_number: 0,
+                errors: vec![e],
+            },
+        }
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.status != TcsLibraryStatus::Error
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct TcsBatchResult {
+    #[getset(get = "pub", set = "pub")]
+    input_directory: String,
+    #[getset(get = "pub", set = "pub")]
+    library_results: Vec<TcsLibraryResult>,
+}
+
+impl TcsBatchResult {
+    pub fn new(input_directory: &str) -> Self {
+        TcsBatchResult {
+            input_directory: input_directory.to_string(),
+            library_results: Vec::new(),
+        }
+    }
+
+    pub fn add_library_result(&mut self, library_result: TcsLibraryResult) {
+        self.library_results.push(library_result);
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.library_results.iter().all(|r| r.is_successful())
+    }
+}
+
+impl Display for TcsBatchResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let library_names = self
+            .library_results
+            .iter()
+            .map(|r| {
+                std::path::Path::new(&r.library)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or(r.library.clone())
+            })
+            .collect::<Vec<_>>();
+        let width = library_names
+            .iter()
+            .map(|name| name.len())
+            .max()
+            .unwrap_or(0)
+            .max("library".len());
+
+        writeln!(
+            f,
+            "{:<width$}  {:<8}  {:>12}  {:>8}  error",
+            "library", "status", "total_reads", "warnings"
+        )?;
+        for (name, result) in library_names.iter().zip(&self.library_results) {
+            writeln!(
+                f,
+                "{:<width$}  {:<8}  {:>12}  {:>8}  {}",
+                name,
+                result.status.to_string(),
+                result.total_reads,
+                result.warning_number,
+                result.errors.join("; ")
+            )?;
+        }
+        let n_success = self
+            .library_results
+            .iter()
+            .filter(|r| r.status == TcsLibraryStatus::Success)
+            .count();
+        let n_warning = self
+            .library_results
+            .iter()
+            .filter(|r| r.status == TcsLibraryStatus::Warning)
+            .count();
+        write!(
+            f,
+            "{} libraries: {} success, {} warning, {} error",
+            self.library_results.len(),
+            n_success,
+            n_warning,
+            self.library_results.len() - n_success - n_warning
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tcs_batch_result_table() {
+        let mut batch = TcsBatchResult::new("batch");
+        batch.add_library_result(TcsLibraryResult::from_tcs_outcome(
+            "batch/lib1",
+            Ok(TcsReport::new()),
+        ));
+        let mut report_with_error = TcsReport::new();
+        report_with_error.add_error("No R1 or R2 files found in the input directory".to_string());
+        batch.add_library_result(TcsLibraryResult::from_tcs_outcome(
+            "batch/lib2",
+            Ok(report_with_error),
+        ));
+        batch.add_library_result(TcsLibraryResult::from_tcs_outcome(
+            "batch/lib3",
+            Err("Input directory does not exist".to_string()),
+        ));
+
+        assert!(!batch.is_successful());
+        assert_eq!(
+            *batch.library_results()[0].status(),
+            TcsLibraryStatus::Success
+        );
+        assert_eq!(
+            *batch.library_results()[1].status(),
+            TcsLibraryStatus::Error
+        );
+
+        let table = batch.to_string();
+        assert!(table.starts_with("library"));
+        assert!(table.contains("lib2     error"));
+        assert!(table.ends_with("3 libraries: 1 success, 0 warning, 2 error"));
+    }
+}
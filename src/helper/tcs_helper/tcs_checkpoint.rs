@@ -0,0 +1,607 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use bio::io::fastq::{self, Record};
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::helper::consensus::ConsensusMethod;
+use crate::helper::params::Params;
+use crate::helper::tcs_helper::{
+    AdvancedSettings, FastqFiles, FilterPairInvalidReason, FilteredPair, TcsConsensus,
+};
+use crate::helper::umi::{UMI, UMIType};
+use crate::helper::umis::{UMIFamilies, UMISummary};
+
+// Stage based checkpoints of the TCS pipeline
+// The result of each stage is serialized into the CHECKPOINT_DIRECTORY of the run (output) directory:
+// the de-multiplexed pairs of the run, then the UMI families, consensus, end-joined and QC'd TCS of each region.
+// The de-multiplexed pairs are written as gzipped FASTQ files per region, with a JSON index of the stage.
+// With `--resume`, a stage with a checkpoint is loaded instead of being computed again.
+// The checkpoints are stamped with a fingerprint of the params, the advanced settings and the raw fastq files,
+// and are discarded when the fingerprint of the new run is different.
+// The checkpoint directory is removed once the run completes.
+
+/// Name of the checkpoint directory in the run (output) directory.
+pub const CHECKPOINT_DIRECTORY: &str = ".tcs_checkpoint";
+
+const FINGERPRINT_FILE: &str = "fingerprint.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CheckpointStage {
+    Demultiplexed,
+    UmiFamilies,
+    Consensus,
+    EndJoined,
+    Qc,
+}
+
+impl Display for CheckpointStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointStage::Demultiplexed => write!(f, "demultiplexed"),
+            CheckpointStage::UmiFamilies => write!(f, "umi_families"),
+            CheckpointStage::Consensus => write!(f, "consensus"),
+            CheckpointStage::EndJoined => write!(f, "end_joined"),
+            CheckpointStage::Qc => write!(f, "qc"),
+        }
+    }
+}
+
+/// Identity of a raw fastq file, a file replaced or modified after the checkpoints were written has a different size or modification time.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InputFileFingerprint {
+    path: String,
+    size: u64,
+    modified: Option<u128>,
+}
+
+impl InputFileFingerprint {
+    pub fn from_path(path: &Path) -> std::io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos());
+        Ok(InputFileFingerprint {
+            path: fs::canonicalize(path)?.to_string_lossy().to_string(),
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Everything the results of a run depend on. Checkpoints are only reused by a run with the same fingerprint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointFingerprint {
+    tcs_version: String,
+    params: String,
+    steepness: f32,
+    midpoint: u8,
+    #[serde(default)]
+    consensus_method: ConsensusMethod,
+    r1_file: InputFileFingerprint,
+    r2_file: InputFileFingerprint,
+}
+
+impl CheckpointFingerprint {
+    pub fn new(
+        params: &Params,
+        advanced_settings: &AdvancedSettings,
+        fastq_files: &FastqFiles,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(CheckpointFingerprint {
+            tcs_version: env!("CARGO_PKG_VERSION").to_string(),
+            params: serde_json::to_string(params)?,
+            steepness: *advanced_settings.steepness(),
+            midpoint: *advanced_settings.midpoint(),
+            consensus_method: *advanced_settings.consensus_method(),
+            r1_file: InputFileFingerprint::from_path(&fastq_files.r1_file)?,
+            r2_file: InputFileFingerprint::from_path(&fastq_files.r2_file)?,
+        })
+    }
+}
+
+/// State of the checkpoints found when a run starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointStatus {
+    /// The run was not resumed, existing checkpoints (if any) were discarded.
+    NotResumed,
+    /// The run was resumed, but no checkpoints were found.
+    NotFound,
+    /// The run was resumed, but the checkpoints were written with different params or input files, and were discarded.
+    Invalidated,
+    /// The run was resumed from the existing checkpoints.
+    Resumed,
+}
+
+impl Display for CheckpointStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckpointStatus::NotResumed => write!(f, "not resumed, starting from scratch"),
+            CheckpointStatus::NotFound => write!(f, "no checkpoints found, starting from scratch"),
+            CheckpointStatus::Invalidated => write!(
+                f,
+                "params or input files changed, checkpoints discarded, starting from scratch"
+            ),
+            CheckpointStatus::Resumed => write!(f, "resuming from the existing checkpoints"),
+        }
+    }
+}
+
+/// Checkpoint of the de-multiplexing stage, with the results needed to rebuild the report of the stage.
+#[derive(Debug, Clone)]
+pub struct DemultiplexedCheckpoint {
+    pub total_reads: usize,
+    pub groups: HashMap<String, Vec<FilteredPair>>,
+    pub fails: Vec<FilterPairInvalidReason>,
+    pub errors: Vec<String>,
+    pub read_issues: Vec<String>,
+}
+
+/// JSON index of the de-multiplexing checkpoint, the pairs of the regions are in gzipped FASTQ files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DemultiplexedIndex {
+    total_reads: usize,
+    regions: HashMap<String, DemultiplexedRegion>,
+    fails: Vec<FilterPairInvalidReason>,
+    errors: Vec<String>,
+    read_issues: Vec<String>,
+}
+
+/// Pairs of a region in the de-multiplexing checkpoint.
+/// The UMI type and information index come from the cDNA primer of the region and are the same for all its pairs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DemultiplexedRegion {
+    pair_number: usize,
+    umi_type: UMIType,
+    information_index: Vec<u32>,
+}
+
+/// Checkpoint of the UMI family stage of a region, the error is the UMI distribution error of the region.
+pub type UmiFamiliesCheckpoint = Result<(UMIFamilies, UMISummary), String>;
+
+/// Checkpoint of the end-joining and the QC stages of a region, with the error of the stage if any,
+/// and the errors of the individual TCS skipped by the stage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TcsStageCheckpoint {
+    pub tcs_consensus: Vec<TcsConsensus>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub tcs_errors: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TcsCheckpoint {
+    directory: PathBuf,
+    resume: bool,
+}
+
+impl TcsCheckpoint {
+    /// Opens the checkpoint directory of a run.
+    /// The existing checkpoints are only kept if the run is resumed with the same fingerprint,
+    /// otherwise they are removed and the fingerprint of the new run is written.
+    pub fn open(
+        run_directory: &Path,
+        fingerprint: &CheckpointFingerprint,
+        resume: bool,
+    ) -> Result<(Self, CheckpointStatus), Box<dyn Error>> {
+        let directory = run_directory.join(CHECKPOINT_DIRECTORY);
+        let fingerprint_path = directory.join(FINGERPRINT_FILE);
+
+        let status = if !resume {
+            CheckpointStatus::NotResumed
+        } else if !fingerprint_path.exists() {
+            CheckpointStatus::NotFound
+        } else {
+            match read_json::<CheckpointFingerprint>(&fingerprint_path) {
+                Some(previous) if previous == *fingerprint => CheckpointStatus::Resumed,
+                _ => CheckpointStatus::Invalidated,
+            }
+        };
+
+        if status != CheckpointStatus::Resumed {
+            if directory.exists() {
+                fs::remove_dir_all(&directory)?;
+            }
+            fs::create_dir_all(&directory)?;
+            write_json(&fingerprint_path, fingerprint)?;
+        }
+
+        Ok((
+            TcsCheckpoint {
+                directory,
+                resume: status == CheckpointStatus::Resumed,
+            },
+            status,
+        ))
+    }
+
+    /// Loads the checkpoint of a stage (of a region), `None` if the run is not resumed or the stage has not completed.
+    pub fn load<T: DeserializeOwned>(
+        &self,
+        stage: CheckpointStage,
+        region: Option<&str>,
+    ) -> Option<T> {
+        if !self.resume {
+            return None;
+        }
+        read_json(&self.stage_path(stage, region))
+    }
+
+    /// Saves the checkpoint of a stage (of a region).
+    /// The file is written under a temporary name first, so that a crash while writing never leaves a partial checkpoint.
+    pub fn save<T: Serialize>(
+        &self,
+        stage: CheckpointStage,
+        region: Option<&str>,
+        value: &T,
+    ) -> Result<(), Box<dyn Error>> {
+        let path = self.stage_path(stage, region);
+        let temp_path = path.with_extension("json.tmp");
+        write_json(&temp_path, value)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    /// Loads the checkpoint of the de-multiplexing stage, `None` if the run is not resumed or the stage has not completed.
+    pub fn load_demultiplexed(&self) -> Option<DemultiplexedCheckpoint> {
+        if !self.resume {
+            return None;
+        }
+        let index: DemultiplexedIndex =
+            read_json(&self.stage_path(CheckpointStage::Demultiplexed, None))?;
+
+        let mut groups = HashMap::new();
+        for (region, region_index) in index.regions {
+            let (r1_path, r2_path) = self.demultiplexed_paths(&region);
+            let pairs = read_pairs(&r1_path, &r2_path, &region, &region_index).ok()?;
+            if pairs.len() != region_index.pair_number {
+                return None;
+            }
+            groups.insert(region, pairs);
+        }
+
+        Some(DemultiplexedCheckpoint {
+            total_reads: index.total_reads,
+            groups,
+            fails: index.fails,
+            errors: index.errors,
+            read_issues: index.read_issues,
+        })
+    }
+
+    /// Saves the checkpoint of the de-multiplexing stage.
+    /// The R1 and R2 records of each region are written to gzipped FASTQ files, the UMI block of a pair heading the description of its R1 record.
+    /// The JSON index is written last, so that the stage is only loaded once all its files are complete.
+    pub fn save_demultiplexed(
+        &self,
+        demultiplexed: &DemultiplexedCheckpoint,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut regions = HashMap::new();
+        for (region, pairs) in &demultiplexed.groups {
+            let Some(first_pair) = pairs.first() else {
+                continue;
+            };
+            let (r1_path, r2_path) = self.demultiplexed_paths(region);
+            write_pairs(&r1_path, &r2_path, pairs)?;
+            regions.insert(
+                region.clone(),
+                DemultiplexedRegion {
+                    pair_number: pairs.len(),
+                    umi_type: first_pair.umi.umi_type.clone(),
+                    information_index: first_pair.umi.information_index.clone(),
+                },
+            );
+        }
+
+        let index = DemultiplexedIndex {
+            total_reads: demultiplexed.total_reads,
+            regions,
+            fails: demultiplexed.fails.clone(),
+            errors: demultiplexed.errors.clone(),
+            read_issues: demultiplexed.read_issues.clone(),
+        };
+        self.save(CheckpointStage::Demultiplexed, None, &index)
+    }
+
+    /// Removes the checkpoint directory of a run directory, once the run has completed.
+    pub fn remove(run_directory: &Path) -> std::io::Result<()> {
+        let directory = run_directory.join(CHECKPOINT_DIRECTORY);
+        if directory.exists() {
+            fs::remove_dir_all(directory)?;
+        }
+        Ok(())
+    }
+
+    fn stage_path(&self, stage: CheckpointStage, region: Option<&str>) -> PathBuf {
+        match region {
+            Some(region) => self.directory.join(format!("{}_{}.json", region, stage)),
+            None => self.directory.join(format!("{}.json", stage)),
+        }
+    }
+
+    fn demultiplexed_paths(&self, region: &str) -> (PathBuf, PathBuf) {
+        let stage = CheckpointStage::Demultiplexed;
+        (
+            self.directory
+                .join(format!("{}_{}_r1.fastq.gz", region, stage)),
+            self.directory
+                .join(format!("{}_{}_r2.fastq.gz", region, stage)),
+        )
+    }
+}
+
+/// Writes the R1 and R2 records of the pairs to gzipped FASTQ files, under temporary names first.
+fn write_pairs(
+    r1_path: &Path,
+    r2_path: &Path,
+    pairs: &[FilteredPair],
+) -> Result<(), Box<dyn Error>> {
+    let r1_temp_path = r1_path.with_extension("gz.tmp");
+    let r2_temp_path = r2_path.with_extension("gz.tmp");
+    let mut r1_encoder = GzEncoder::new(
+        BufWriter::new(File::create(&r1_temp_path)?),
+        Compression::fast(),
+    );
+    let mut r2_encoder = GzEncoder::new(
+        BufWriter::new(File::create(&r2_temp_path)?),
+        Compression::fast(),
+    );
+    {
+        let mut r1_writer = fastq::Writer::new(&mut r1_encoder);
+        let mut r2_writer = fastq::Writer::new(&mut r2_encoder);
+        for pair in pairs {
+            let desc = match pair.r1.desc() {
+                Some(desc) => format!("{} {}", pair.umi.umi_block, desc),
+                None => pair.umi.umi_block.clone(),
+            };
+            r1_writer.write(pair.r1.id(), Some(&desc), pair.r1.seq(), pair.r1.qual())?;
+            r2_writer.write_record(&pair.r2)?;
+        }
+        r1_writer.flush()?;
+        r2_writer.flush()?;
+    }
+    r1_encoder.finish()?.flush()?;
+    r2_encoder.finish()?.flush()?;
+    fs::rename(&r1_temp_path, r1_path)?;
+    fs::rename(&r2_temp_path, r2_path)?;
+    Ok(())
+}
+
+/// Reads the pairs of a region back from the gzipped FASTQ files written by `write_pairs`.
+fn read_pairs(
+    r1_path: &Path,
+    r2_path: &Path,
+    region: &str,
+    region_index: &DemultiplexedRegion,
+) -> Result<Vec<FilteredPair>, Box<dyn Error>> {
+    let open = |path: &Path| -> std::io::Result<_> {
+        Ok(fastq::Reader::new(MultiGzDecoder::new(BufReader::new(
+            File::open(path)?,
+        ))))
+    };
+    let r1_records = open(r1_path)?.records();
+    let r2_records = open(r2_path)?.records();
+
+    let mut pairs = Vec::with_capacity(region_index.pair_number);
+    for (r1, r2) in r1_records.zip(r2_records) {
+        let (r1, r2) = (r1?, r2?);
+        let desc = r1.desc().ok_or("UMI block missing from the R1 record")?;
+        let (umi_block, desc) = match desc.split_once(' ') {
+            Some((umi_block, desc)) => (umi_block.to_string(), Some(desc)),
+            None => (desc.to_string(), None),
+        };
+        let umi_information_block = region_index
+            .information_index
+            .iter()
+            .map(|&i| umi_block.chars().nth(i as usize))
+            .collect::<Option<String>>()
+            .ok_or("UMI block shorter than its information index")?;
+        pairs.push(FilteredPair {
+            region: region.to_string(),
+            umi: UMI {
+                umi_type: region_index.umi_type.clone(),
+                umi_block,
+                information_index: region_index.information_index.clone(),
+                umi_information_block,
+            },
+            r1: Record::with_attrs(r1.id(), desc, r1.seq(), r1.qual()),
+            r2,
+        });
+    }
+    Ok(pairs)
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let file = File::open(path).ok()?;
+    serde_json::from_reader(BufReader::new(file)).ok()
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(&mut writer, value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::tcs_helper::fastq_files::DataType;
+
+    fn test_run(name: &str) -> (PathBuf, FastqFiles) {
+        let dir = std::env::temp_dir().join(format!("virust_tcs_checkpoint_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let r1_file = dir.join("r1.fastq");
+        let r2_file = dir.join("r2.fastq");
+        fs::write(&r1_file, "@read1\nACGT\n+\nIIII\n").unwrap();
+        fs::write(&r2_file, "@read1\nACGT\n+\nIIII\n").unwrap();
+        (
+            dir,
+            FastqFiles {
+                r1_file,
+                r2_file,
+                data_type: DataType::Fastq,
+            },
+        )
+    }
+
+    fn test_params(platform_error_rate: f32) -> Params {
+        let mut params = Params::from_preset("v1").unwrap();
+        params.platform_error_rate = platform_error_rate;
+        params
+    }
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let (dir, fastq_files) = test_run("resume");
+        let settings = AdvancedSettings::default();
+        let fingerprint =
+            CheckpointFingerprint::new(&test_params(0.02), &settings, &fastq_files).unwrap();
+
+        let (checkpoint, status) = TcsCheckpoint::open(&dir, &fingerprint, true).unwrap();
+        assert_eq!(status, CheckpointStatus::NotFound);
+        let stage = TcsStageCheckpoint {
+            tcs_consensus: vec![TcsConsensus::new()],
+            error: Some("end-joining error".to_string()),
+            tcs_errors: Vec::new(),
+        };
+        checkpoint
+            .save(CheckpointStage::EndJoined, Some("PR"), &stage)
+            .unwrap();
+        // checkpoints are not loaded by the run that writes them
+        assert!(
+            checkpoint
+                .load::<TcsStageCheckpoint>(CheckpointStage::EndJoined, Some("PR"))
+                .is_none()
+        );
+
+        let (checkpoint, status) = TcsCheckpoint::open(&dir, &fingerprint, true).unwrap();
+        assert_eq!(status, CheckpointStatus::Resumed);
+        let loaded = checkpoint
+            .load::<TcsStageCheckpoint>(CheckpointStage::EndJoined, Some("PR"))
+            .unwrap();
+        assert_eq!(loaded.tcs_consensus.len(), 1);
+        assert_eq!(loaded.error.as_deref(), Some("end-joining error"));
+        assert!(
+            checkpoint
+                .load::<TcsStageCheckpoint>(CheckpointStage::Qc, Some("PR"))
+                .is_none()
+        );
+
+        TcsCheckpoint::remove(&dir).unwrap();
+        assert!(!dir.join(CHECKPOINT_DIRECTORY).exists());
+    }
+
+    #[test]
+    fn test_demultiplexed_checkpoint() {
+        let (dir, fastq_files) = test_run("demultiplexed");
+        let settings = AdvancedSettings::default();
+        let fingerprint =
+            CheckpointFingerprint::new(&test_params(0.02), &settings, &fastq_files).unwrap();
+        let (checkpoint, _) = TcsCheckpoint::open(&dir, &fingerprint, false).unwrap();
+
+        let pair = |umi_block: &str, desc: Option<&str>| FilteredPair {
+            region: "PR".to_string(),
+            umi: UMI {
+                umi_type: UMIType::UMIWithPattern,
+                umi_block: umi_block.to_string(),
+                information_index: vec![0, 1, 2, 5, 6],
+                umi_information_block: format!("{}{}", &umi_block[0..3], &umi_block[5..7]),
+            },
+            r1: Record::with_attrs("read", desc, b"ACGTACGT", b"IIII!III"),
+            r2: Record::with_attrs("read", Some("2:N:0:1"), b"TTGGCC", b"IIIIII"),
+        };
+        let demultiplexed = DemultiplexedCheckpoint {
+            total_reads: 3,
+            groups: HashMap::from([(
+                "PR".to_string(),
+                vec![pair("ACGTCGA", Some("1:N:0:1")), pair("TTTACCC", None)],
+            )]),
+            fails: vec![FilterPairInvalidReason::NoMatch("read".to_string())],
+            errors: Vec::new(),
+            read_issues: Vec::new(),
+        };
+        checkpoint.save_demultiplexed(&demultiplexed).unwrap();
+        let checkpoint_directory = dir.join(CHECKPOINT_DIRECTORY);
+        assert!(
+            checkpoint_directory
+                .join("PR_demultiplexed_r1.fastq.gz")
+                .exists()
+        );
+
+        let (checkpoint, status) = TcsCheckpoint::open(&dir, &fingerprint, true).unwrap();
+        assert_eq!(status, CheckpointStatus::Resumed);
+        let loaded = checkpoint.load_demultiplexed().unwrap();
+        assert_eq!(loaded.total_reads, 3);
+        assert_eq!(loaded.fails, demultiplexed.fails);
+        assert_eq!(loaded.groups, demultiplexed.groups);
+        let loaded_pair = &loaded.groups["PR"][0];
+        assert_eq!(loaded_pair.umi.umi_block, "ACGTCGA");
+        assert_eq!(loaded_pair.umi.umi_information_block, "ACGGA");
+        assert_eq!(loaded_pair.r1.desc(), Some("1:N:0:1"));
+        assert_eq!(loaded.groups["PR"][1].r1.desc(), None);
+
+        // an incomplete region file is not loaded
+        fs::write(
+            checkpoint_directory.join("PR_demultiplexed_r2.fastq.gz"),
+            b"",
+        )
+        .unwrap();
+        assert!(checkpoint.load_demultiplexed().is_none());
+
+        TcsCheckpoint::remove(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_invalidation() {
+        let (dir, fastq_files) = test_run("invalidation");
+        let settings = AdvancedSettings::default();
+        let fingerprint =
+            CheckpointFingerprint::new(&test_params(0.02), &settings, &fastq_files).unwrap();
+        let (checkpoint, _) = TcsCheckpoint::open(&dir, &fingerprint, false).unwrap();
+        checkpoint
+            .save(CheckpointStage::Demultiplexed, None, &1usize)
+            .unwrap();
+
+        // different params
+        let changed_params =
+            CheckpointFingerprint::new(&test_params(0.01), &settings, &fastq_files).unwrap();
+        let (checkpoint, status) = TcsCheckpoint::open(&dir, &changed_params, true).unwrap();
+        assert_eq!(status, CheckpointStatus::Invalidated);
+        assert!(
+            checkpoint
+                .load::<usize>(CheckpointStage::Demultiplexed, None)
+                .is_none()
+        );
+        checkpoint
+            .save(CheckpointStage::Demultiplexed, None, &1usize)
+            .unwrap();
+
+        // different input file
+        fs::write(&fastq_files.r2_file, "@read1\nACGTA\n+\nIIIII\n").unwrap();
+        let changed_input =
+            CheckpointFingerprint::new(&test_params(0.01), &settings, &fastq_files).unwrap();
+        assert_ne!(changed_input, changed_params);
+        let (_, status) = TcsCheckpoint::open(&dir, &changed_input, true).unwrap();
+        assert_eq!(status, CheckpointStatus::Invalidated);
+        assert!(
+            !dir.join(CHECKPOINT_DIRECTORY)
+                .join("demultiplexed.json")
+                .exists()
+        );
+
+        TcsCheckpoint::remove(&dir).unwrap();
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
 use std::fmt::Display;
 use std::ops::Range;
@@ -11,12 +11,15 @@ use serde::{Deserialize, Serialize};
 use virust_locator::prelude::*;
 
 use crate::helper::consensus::{
-    self, ConsensusInput, ConsensusParams, ConsensusStrategy, consensus,
+    self, ConsensusInput, ConsensusParams, ConsensusStrategy, UnequalLengthConsensus, consensus,
+    consensus_iupac,
 };
 use crate::helper::end_joining::*;
-use crate::helper::params::{QcConfig, TrimConfig};
+use crate::helper::params::{CountOrFraction, QcConfig, TrimConfig};
 use crate::helper::tcs_helper::*;
-use crate::helper::umis::{UMIDistError, UMIInformationBlocks, UMISummary};
+use crate::helper::umis::{
+    UMIClustering, UMICutOffModel, UMIDistError, UMIFamilies, UMIInformationBlocks, UMISummary,
+};
 
 #[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
 pub struct TcsConsensus {
@@ -34,6 +37,29 @@ pub struct TcsConsensus {
     qc: TcsConsensusQcResult,
     #[getset(get = "pub", set = "pub")]
     trimmed: Option<Record>,
+    // heterogeneity of the UMI family, if analyzed
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    heterogeneity: Option<FamilyHeterogeneity>,
+    // overlap of the R1 and R2 consensus used for the end-joining, if joined
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    overlap: Option<OverlapResult>,
+    // how the overlap was found with the end-joining option 4, if joined with it
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    overlap_choice: Option<TcsOverlapChoice>,
+}
+
+/// How the overlap of the R1 and R2 consensus of a TCS was found with the end-joining option 4 (see `join_consensus_fastq_vec`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TcsOverlapChoice {
+    /// The best overlap of the R1 and R2 consensus of the TCS.
+    TcsOverlap,
+    /// No acceptable overlap for the TCS, the overlap of the consensus of consensus is applied at the same offset.
+    PopulationOverlap,
+    /// No acceptable overlap for the TCS nor for the consensus of consensus, R1 and R2 are concatenated.
+    NoOverlap,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
@@ -101,6 +127,121 @@ pub struct TcsConsensusBuildingOutput {
     errors: Vec<String>,
     #[getset(get = "pub")]
     umi_summary: UMISummary,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    family_heterogeneity_summary: Option<FamilyHeterogeneitySummary>,
+    // consensus built but dropped for more ambiguous bases than allowed
+    #[serde(default)]
+    #[getset(get = "pub")]
+    ambiguous_dropped_number: usize,
+    // families of reads of different lengths whose consensus was built through alignment
+    #[serde(default)]
+    #[getset(get = "pub")]
+    aligned_families: usize,
+}
+
+/// Distribution of the overlaps of the R1 and R2 consensus of the joined TCS of a region.
+/// Many TCS without an overlap or with a short insert point to a library with failed overlaps or short inserts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, Getters)]
+pub struct OverlapSummary {
+    #[getset(get = "pub")]
+    joined_tcs: usize,
+    // joined TCS without an overlap, R1 and R2 concatenated
+    #[getset(get = "pub")]
+    no_overlap_tcs: usize,
+    // joined TCS with R2 hanging off the left end of R1 (negative offset), an insert shorter than the reads
+    #[getset(get = "pub")]
+    short_insert_tcs: usize,
+    #[getset(get = "pub")]
+    min_overlap_len: Option<usize>,
+    // mean of the two middle overlap lengths for an even number of overlaps
+    #[getset(get = "pub")]
+    median_overlap_len: Option<f64>,
+    #[getset(get = "pub")]
+    max_overlap_len: Option<usize>,
+    // mismatches over the overlapping bases of all the joined TCS
+    #[getset(get = "pub")]
+    mismatch_rate: f64,
+    // number of joined TCS by overlap length, 0 for no overlap
+    #[getset(get = "pub")]
+    overlap_len_distribution: BTreeMap<usize, usize>,
+    // joined TCS without an overlap of their own, joined with the population overlap (end-joining option 4)
+    #[serde(default)]
+    #[getset(get = "pub")]
+    population_overlap_tcs: usize,
+}
+
+/// Median of sorted values, the mean of the two middle values for an even number of values.
+fn median(sorted_values: &[usize]) -> Option<f64> {
+    let middle = sorted_values.len() / 2;
+    match sorted_values.len() {
+        0 => None,
+        len if len % 2 == 0 => {
+            Some((sorted_values[middle - 1] + sorted_values[middle]) as f64 / 2.0)
+        }
+        _ => Some(sorted_values[middle] as f64),
+    }
+}
+
+impl OverlapSummary {
+    /// Summarizes the overlaps of the joined TCS, `None` if no TCS is joined.
+    /// Also `None` with the end-joining options 1 and 2, whose overlap is set by the params instead of found on the TCS.
+    pub fn from_tcs_consensus(
+        tcs_consensus: &[TcsConsensus],
+        end_joining_option: u32,
+    ) -> Option<Self> {
+        if matches!(end_joining_option, 1 | 2) {
+            return None;
+        }
+        let overlaps = tcs_consensus
+            .iter()
+            .filter_map(|consensus| consensus.overlap.as_ref())
+            .collect::<Vec<_>>();
+        if overlaps.is_empty() {
+            return None;
+        }
+
+        let overlap_lens = overlaps
+            .iter()
+            .map(|overlap| *overlap.overlap_len())
+            .filter(|&overlap_len| overlap_len > 0)
+            .sorted()
+            .collect::<Vec<_>>();
+        let overlapping_bases = overlap_lens.iter().sum::<usize>();
+        let mismatches = overlaps
+            .iter()
+            .map(|overlap| *overlap.mismatches())
+            .sum::<usize>();
+
+        Some(OverlapSummary {
+            joined_tcs: overlaps.len(),
+            no_overlap_tcs: overlaps.len() - overlap_lens.len(),
+            short_insert_tcs: overlaps
+                .iter()
+                .filter(|overlap| *overlap.offset() < 0)
+                .count(),
+            min_overlap_len: overlap_lens.first().copied(),
+            median_overlap_len: median(&overlap_lens),
+            max_overlap_len: overlap_lens.last().copied(),
+            mismatch_rate: if overlapping_bases > 0 {
+                mismatches as f64 / overlapping_bases as f64
+            } else {
+                0.0
+            },
+            overlap_len_distribution: overlaps
+                .iter()
+                .map(|overlap| *overlap.overlap_len())
+                .counts()
+                .into_iter()
+                .collect(),
+            population_overlap_tcs: tcs_consensus
+                .iter()
+                .filter(|consensus| {
+                    consensus.overlap_choice == Some(TcsOverlapChoice::PopulationOverlap)
+                })
+                .count(),
+        })
+    }
 }
 
 impl TcsConsensus {
@@ -114,102 +255,317 @@ impl TcsConsensus {
             joined_consensus: None,
             qc: TcsConsensusQcResult::default(),
             trimmed: None,
+            heterogeneity: None,
+            overlap: None,
+            overlap_choice: None,
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn build_from_filtered_pairs(
-    pairs: &Vec<FilteredPair>,
+    pairs: &[FilteredPair],
     strategy: consensus::ConsensusStrategy,
     error_cutoff: f32,
+    umi_clustering: UMIClustering,
+    umi_cut_off_model: UMICutOffModel,
+    heterogeneity_action: Option<FamilyHeterogeneityAction>,
+    max_ambiguous_bases: CountOrFraction,
+    unequal_length: UnequalLengthConsensus,
 ) -> Result<TcsConsensusBuildingOutput, UMIDistError> {
+    let (umi_families, umi_summary) =
+        find_umi_families(pairs, error_cutoff, umi_clustering, umi_cut_off_model)?;
+    Ok(build_from_umi_families(
+        pairs,
+        &umi_families,
+        umi_summary,
+        strategy,
+        heterogeneity_action,
+        max_ambiguous_bases,
+        unequal_length,
+    ))
+}
+
+/// Finds the UMI families of the filtered pairs of a region, the families with a size above the UMI cut-off
+/// calculated from the error cut-off (platform error rate).
+/// With `UMIClustering::Directional`, UMIs with a sequencing error are merged into their parent UMI before the cut-off.
+/// With `UMICutOffModel::Mixture`, the cut-off is fitted from the family size distribution instead of the error cut-off.
+pub fn find_umi_families(
+    pairs: &[FilteredPair],
+    error_cutoff: f32,
+    umi_clustering: UMIClustering,
+    umi_cut_off_model: UMICutOffModel,
+) -> Result<(UMIFamilies, UMISummary), UMIDistError> {
+    let umis = UMIInformationBlocks {
+        umi_information_blocks: pairs
+            .iter()
+            .map(|pair| pair.umi.umi_information_block.clone())
+            .collect(),
+    };
+    umis.find_umi_family_by_error_cutoff(error_cutoff, umi_clustering, umi_cut_off_model)
+}
+
+// consensus of a UMI family (none for a dropped family, two for a split family)
+struct FamilyConsensus {
+    tcs_consensus: Vec<TcsConsensus>,
+    // heterogeneity analyzed (reads of the same length)
+    analyzed: bool,
+    bimodal: bool,
+    // reads of different lengths, aligned for the consensus
+    aligned: bool,
+}
+
+type FamilyConsensusResult = Result<FamilyConsensus, Box<dyn Error + Send + Sync>>;
+
+/// Builds the R1 and R2 consensus of each UMI family in parallel.
+/// The UMI families are found from the same filtered pairs with `find_umi_families`,
+/// the pairs of the UMIs merged into a family are part of the family.
+/// With a heterogeneity action, the heterogeneity of each family is analyzed (see `analyze_family_heterogeneity`),
+/// and the bimodal families are reported, split into their two haplotypes or dropped.
+/// A family with R1 or R2 reads of different lengths is analyzed on its reads projected onto a common frame with `align_fastq_records`
+/// when its consensus is built through alignment, otherwise it is not analyzed and its consensus is built from all of its reads.
+/// The consensus of the haplotypes of a split family have the UMI information block suffixed with `-1` and `-2`,
+/// a split family without a haplotype above the UMI cut-off is counted as dropped.
+/// Consensus with more ambiguous bases (N) in R1 or R2 than `max_ambiguous_bases` are dropped and counted in the output,
+/// the tolerated ambiguous bases are kept with the lowest quality (`!`).
+/// With `UnequalLengthConsensus::Align`, the consensus of a family with reads of different lengths is built with `consensus_aligned`,
+/// otherwise the family fails with `ConsensusError::InvalidSequenceLength`.
+/// Errors of individual UMI families (consensus never built) are collected in the output.
+pub fn build_from_umi_families(
+    pairs: &[FilteredPair],
+    umi_families: &UMIFamilies,
+    umi_summary: UMISummary,
+    strategy: consensus::ConsensusStrategy,
+    heterogeneity_action: Option<FamilyHeterogeneityAction>,
+    max_ambiguous_bases: CountOrFraction,
+    unequal_length: UnequalLengthConsensus,
+) -> TcsConsensusBuildingOutput {
     let mut umi_records = HashMap::new();
-    let mut umi_information_blocks = Vec::new();
     for pair in pairs {
-        let umi_information_block = pair.umi.umi_information_block.clone();
-        umi_information_blocks.push(umi_information_block.clone());
         umi_records
-            .entry(umi_information_block)
+            .entry(pair.umi.umi_information_block.clone())
             .or_insert_with(|| Vec::new())
             .push((&pair.r1, &pair.r2));
     }
+    let umi_cut_off = *umi_summary.umi_cut_off();
 
-    let umis = UMIInformationBlocks {
-        umi_information_blocks,
-    };
-
-    let (umi_families, umi_summary) = umis.find_umi_family_by_error_cutoff(error_cutoff)?;
-
-    let tcs_consensus_results: Vec<Result<TcsConsensus, Box<dyn Error + Send + Sync>>> =
-        umi_families
-            .families
-            .par_iter()
-            .map(|umi_family| {
-                let umi_information_block = umi_family.umi_information_block.clone();
-                let filtered_pairs = umi_records.get(&umi_information_block).ok_or_else(|| {
+    let tcs_consensus_results: Vec<FamilyConsensusResult> = umi_families
+        .families
+        .par_iter()
+        .map(|umi_family| {
+            let umi_information_block = umi_family.umi_information_block.clone();
+            let mut filtered_pairs = Vec::new();
+            for umi in std::iter::once(&umi_information_block).chain(&umi_family.merged_umis) {
+                filtered_pairs.extend(umi_records.get(umi).ok_or_else(|| {
                     TcsError::UnexpectedError(format!(
                         "No filtered pairs found for UMI information block: {}",
-                        umi_information_block
+                        umi
                     ))
-                })?;
-
-                let r1_vec = filtered_pairs
-                    .iter()
-                    .map(|(r1, _)| (*r1).clone())
-                    .collect::<Vec<_>>();
-                let r2_vec = filtered_pairs
-                    .iter()
-                    .map(|(_, r2)| (*r2).clone())
-                    .collect::<Vec<_>>();
-
-                let r1_consensus =
-                    consensus::consensus(strategy, consensus::ConsensusInput::Fastq(&r1_vec))?;
-                let r2_consensus =
-                    consensus::consensus(strategy, consensus::ConsensusInput::Fastq(&r2_vec))?;
-
-                let r1_consensus_record = Record::with_attrs(
-                    &format!("{}_{}_r1", umi_information_block, umi_family.frequency),
-                    None,
-                    &r1_consensus.seq,
-                    &r1_consensus.qual.unwrap(),
-                );
-                let r2_consensus_record = Record::with_attrs(
-                    &format!("{}_{}_r2", umi_information_block, umi_family.frequency),
-                    None,
-                    &r2_consensus.seq,
-                    &r2_consensus.qual.unwrap(),
-                );
+                })?);
+            }
+
+            let r1_vec = filtered_pairs
+                .iter()
+                .map(|(r1, _)| (*r1).clone())
+                .collect::<Vec<_>>();
+            let r2_vec = filtered_pairs
+                .iter()
+                .map(|(_, r2)| (*r2).clone())
+                .collect::<Vec<_>>();
+            let aligned = unequal_length == UnequalLengthConsensus::Align
+                && (!same_length(&r1_vec) || !same_length(&r2_vec));
 
-                let mut tcs_consensus = TcsConsensus::new();
-                tcs_consensus.set_umi_information_block(umi_information_block);
-                tcs_consensus.set_umi_family_size(umi_family.frequency);
-                tcs_consensus.r1_consensus = r1_consensus_record;
-                tcs_consensus.r2_consensus = r2_consensus_record;
-                tcs_consensus.set_umi_family_size(umi_family.frequency);
+            let analysis = match heterogeneity_action {
+                Some(action) if aligned => {
+                    let r1_aligned = consensus::align_fastq_records(strategy, &r1_vec)?;
+                    let r2_aligned = consensus::align_fastq_records(strategy, &r2_vec)?;
+                    analyze_family_heterogeneity(&r1_aligned, &r2_aligned)
+                        .map(|analysis| (action, analysis))
+                }
+                Some(action) => analyze_family_heterogeneity(&r1_vec, &r2_vec)
+                    .map(|analysis| (action, analysis)),
+                None => None,
+            };
+            let Some((action, (heterogeneity, haplotype_reads))) = analysis else {
+                let tcs_consensus = build_family_consensus(
+                    umi_information_block,
+                    umi_family.frequency,
+                    &r1_vec,
+                    &r2_vec,
+                    strategy,
+                    unequal_length,
+                )?;
+                return Ok(FamilyConsensus {
+                    tcs_consensus: vec![tcs_consensus],
+                    analyzed: false,
+                    bimodal: false,
+                    aligned,
+                });
+            };
 
-                Ok(tcs_consensus)
+            let bimodal = *heterogeneity.bimodal();
+            let family_consensus = match (action, haplotype_reads) {
+                (FamilyHeterogeneityAction::Drop, Some(_)) => Vec::new(),
+                (FamilyHeterogeneityAction::Split, Some((first, second))) => {
+                    let mut haplotype_consensus = Vec::new();
+                    for (i, reads) in [first, second].iter().enumerate() {
+                        if reads.len() <= umi_cut_off {
+                            continue;
+                        }
+                        let mut tcs_consensus = build_family_consensus(
+                            format!("{}-{}", umi_information_block, i + 1),
+                            reads.len(),
+                            &reads.iter().map(|&j| r1_vec[j].clone()).collect::<Vec<_>>(),
+                            &reads.iter().map(|&j| r2_vec[j].clone()).collect::<Vec<_>>(),
+                            strategy,
+                            unequal_length,
+                        )?;
+                        tcs_consensus.set_heterogeneity(Some(heterogeneity.clone()));
+                        haplotype_consensus.push(tcs_consensus);
+                    }
+                    haplotype_consensus
+                }
+                _ => {
+                    let mut tcs_consensus = build_family_consensus(
+                        umi_information_block,
+                        umi_family.frequency,
+                        &r1_vec,
+                        &r2_vec,
+                        strategy,
+                        unequal_length,
+                    )?;
+                    tcs_consensus.set_heterogeneity(Some(heterogeneity));
+                    vec![tcs_consensus]
+                }
+            };
+            Ok(FamilyConsensus {
+                tcs_consensus: family_consensus,
+                analyzed: true,
+                bimodal,
+                aligned,
             })
-            .collect();
+        })
+        .collect();
 
     let mut tcs_consensus = Vec::new();
     let mut errors = Vec::new();
+    let mut heterogeneity_summary = FamilyHeterogeneitySummary::default();
+    let mut ambiguous_dropped_number = 0;
+    let mut aligned_families = 0;
     for result in tcs_consensus_results {
         match result {
-            Ok(consensus) => {
-                if !consensus.r1_consensus.seq().contains(&b'N')
-                    && !consensus.r2_consensus.seq().contains(&b'N')
-                {
-                    tcs_consensus.push(consensus);
+            Ok(FamilyConsensus {
+                tcs_consensus: family_consensus,
+                analyzed,
+                bimodal,
+                aligned,
+            }) => {
+                if aligned {
+                    aligned_families += 1;
+                }
+                if analyzed {
+                    heterogeneity_summary
+                        .set_analyzed_families(heterogeneity_summary.analyzed_families() + 1);
+                }
+                if bimodal {
+                    heterogeneity_summary
+                        .set_bimodal_families(heterogeneity_summary.bimodal_families() + 1);
+                    // a split family with both haplotypes at or below the UMI cut-off is dropped
+                    match heterogeneity_action {
+                        Some(FamilyHeterogeneityAction::Split) if !family_consensus.is_empty() => {
+                            heterogeneity_summary
+                                .set_split_families(heterogeneity_summary.split_families() + 1)
+                        }
+                        Some(
+                            FamilyHeterogeneityAction::Split | FamilyHeterogeneityAction::Drop,
+                        ) => heterogeneity_summary
+                            .set_dropped_families(heterogeneity_summary.dropped_families() + 1),
+                        _ => &mut heterogeneity_summary,
+                    };
+                }
+                for mut consensus in family_consensus {
+                    if within_max_ambiguous_bases(&consensus.r1_consensus, max_ambiguous_bases)
+                        && within_max_ambiguous_bases(&consensus.r2_consensus, max_ambiguous_bases)
+                    {
+                        consensus.r1_consensus = mask_ambiguous_quality(&consensus.r1_consensus);
+                        consensus.r2_consensus = mask_ambiguous_quality(&consensus.r2_consensus);
+                        tcs_consensus.push(consensus);
+                    } else {
+                        ambiguous_dropped_number += 1;
+                    }
                 }
             }
             Err(e) => errors.push(e.to_string()),
         }
     }
-    Ok(TcsConsensusBuildingOutput {
+    TcsConsensusBuildingOutput {
         tcs_consensus,
         errors,
         umi_summary,
-    })
+        family_heterogeneity_summary: heterogeneity_action.map(|_| heterogeneity_summary),
+        ambiguous_dropped_number,
+        aligned_families,
+    }
+}
+
+/// Whether all the records have the same length.
+fn same_length(records: &[Record]) -> bool {
+    records.iter().map(|r| r.seq().len()).all_equal()
+}
+
+/// Whether the number of ambiguous bases (N) of a consensus is within the maximum allowed for its length.
+fn within_max_ambiguous_bases(record: &Record, max_ambiguous_bases: CountOrFraction) -> bool {
+    let ambiguous_bases = record.seq().iter().filter(|&&base| base == b'N').count();
+    ambiguous_bases <= max_ambiguous_bases.max_count(record.seq().len())
+}
+
+/// Sets the quality of the ambiguous bases (N) of a consensus to the lowest quality (`!`, Phred 0).
+fn mask_ambiguous_quality(record: &Record) -> Record {
+    let qual = record
+        .seq()
+        .iter()
+        .zip(record.qual())
+        .map(|(&base, &q)| if base == b'N' { b'!' } else { q })
+        .collect::<Vec<u8>>();
+    Record::with_attrs(record.id(), record.desc(), record.seq(), &qual)
+}
+
+/// Builds the R1 and R2 consensus of the reads of a UMI family (or of a haplotype of a split family).
+fn build_family_consensus(
+    umi_information_block: String,
+    family_size: usize,
+    r1_vec: &[Record],
+    r2_vec: &[Record],
+    strategy: consensus::ConsensusStrategy,
+    unequal_length: UnequalLengthConsensus,
+) -> Result<TcsConsensus, Box<dyn Error + Send + Sync>> {
+    let consensus_fn = match unequal_length {
+        UnequalLengthConsensus::Reject => consensus::consensus,
+        UnequalLengthConsensus::Align => consensus::consensus_aligned,
+    };
+    let r1_consensus = consensus_fn(strategy, consensus::ConsensusInput::Fastq(r1_vec))?;
+    let r2_consensus = consensus_fn(strategy, consensus::ConsensusInput::Fastq(r2_vec))?;
+
+    let r1_consensus_record = Record::with_attrs(
+        &format!("{}_{}_r1", umi_information_block, family_size),
+        None,
+        &r1_consensus.seq,
+        &r1_consensus.qual.unwrap(),
+    );
+    let r2_consensus_record = Record::with_attrs(
+        &format!("{}_{}_r2", umi_information_block, family_size),
+        None,
+        &r2_consensus.seq,
+        &r2_consensus.qual.unwrap(),
+    );
+
+    let mut tcs_consensus = TcsConsensus::new();
+    tcs_consensus.set_umi_information_block(umi_information_block);
+    tcs_consensus.set_umi_family_size(family_size);
+    tcs_consensus.r1_consensus = r1_consensus_record;
+    tcs_consensus.r2_consensus = r2_consensus_record;
+
+    Ok(tcs_consensus)
 }
 
 /// Joins the R1 and R2 consensus FASTQ records into a single joined consensus record.
@@ -220,41 +576,221 @@ pub fn build_from_filtered_pairs(
 /// - `end_joining_option`: An integer that determines the joining strategy:
 ///   - `1`: Simple end joining.
 ///   - `2`: Overlap end joining with a specified overlap length.
-///   - `3`: Unknown overlap, which is not recommended due to potential issues in libraries with many off-target reads.
+///   - `3`: One overlap for all the TCS, found on the consensus of consensus of R1 and R2. Not recommended for libraries
+///     with many off-target reads or variable indel lengths.
+///   - `4`: Overlap of each TCS, found by `find_best_overlap_with_quality` on its own R1 and R2 consensus. A TCS without an
+///     acceptable overlap falls back to the overlap of the consensus of consensus (as option 3) applied at the same offset,
+///     and R1 and R2 are concatenated if there is no population overlap either. The choice is recorded in the `overlap_choice` of each TCS.
+///   - `5`: Reference-guided joining, R1 and R2 consensus are located separately on the `reference` and joined by reference coordinate,
+///     with the gap between non-overlapping reads filled with `N`. TCS with R1 or R2 not located, or R2 upstream of R1, are not joined
+///     and reported as errors of individual TCS.
 /// - `overlap_len`: The length of the overlap to use for the overlap end joining strategy.
+/// - `overlap_search`: The minimum overlap and the error rate of the overlap search of options 3 and 4.
+/// - `iupac_min_share`: With options 3 and 4, the consensus of consensus has IUPAC codes at the mixed positions
+///   where each base is in at least this share of the TCS (see `consensus_iupac`), instead of `N`,
+///   and the overlap search matches an IUPAC code with any of its bases. Without it, the bases of an overlap must be identical.
+/// - `reference`: The reference genome of option 5, usually the QC reference of the region.
 /// In the orginal Ruby version of TCS, we had an option of 3 for Unknown Overlap but use a consensus strategy to determine the overlap.
 /// We often have issues with this approach, particularly in libraries with many off-target reads.
-/// We decide to have either with a known overlap length or create end-joining for individual TCS consensus records.
-/// The param files generated from previous versions will still be compatible with this function. All option 3 will be converted to EndJoiningStrategy::UnknownOverlap.
+/// Option 4 finds the overlap of each TCS instead, and only uses the population overlap for the TCS without their own.
 ///
-/// Returns a `Result` indicating success or an error message if joining fails.
+/// Returns the errors of the individual TCS that could not be joined, or an error if the joining of the region fails
+/// (no population overlap for option 3, the locator fails for option 5), or if the option is not between 1 and 5.
 /// This function uses parallel processing to join the consensus records efficiently.
-/// If any errors occur during the joining process, they are collected and returned as a single error message.
-/// If the joining is successful, the joined consensus record is set in the `joined_consensus` field of each `TcsConsensus` record.
+/// A TCS that could not be joined is skipped (no `joined_consensus`), the other TCS are still joined.
+/// If the joining is successful, the joined consensus record is set in the `joined_consensus` field of each `TcsConsensus` record,
+/// and the overlap used in its `overlap` field.
 pub fn join_consensus_fastq_vec(
     tcs_consensus: &mut Vec<TcsConsensus>,
     end_joining_option: u32,
     overlap_len: usize,
-) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let strategy = match end_joining_option {
-        1 => EndJoiningStrategy::Simple,
-        2 => EndJoiningStrategy::SimpleOverlap(overlap_len),
-        3 => EndJoiningStrategy::Overlap(find_consensus_overlap(
-            tcs_consensus
-                .iter()
-                .map(|c| c.r1_consensus.clone())
-                .collect(),
-            tcs_consensus
+    overlap_search: OverlapSearchParams,
+    iupac_min_share: Option<f64>,
+    reference: &QcReference,
+) -> Result<Vec<String>, Box<dyn Error + Send + Sync>> {
+    let tcs_number = tcs_consensus.len();
+    let strategies = match end_joining_option {
+        1 => vec![Some(EndJoiningStrategy::Simple); tcs_number],
+        2 => vec![Some(EndJoiningStrategy::SimpleOverlap(overlap_len)); tcs_number],
+        3 => {
+            let population_overlap =
+                find_population_overlap(tcs_consensus, overlap_search, iupac_min_share)?;
+            vec![Some(EndJoiningStrategy::Overlap(population_overlap)); tcs_number]
+        }
+        4 => {
+            let (strategies, choices) =
+                find_tcs_overlaps(tcs_consensus, overlap_search, iupac_min_share);
+            let errors = join_with_strategies(tcs_consensus, strategies);
+            for (consensus, choice) in tcs_consensus.iter_mut().zip(choices) {
+                if consensus.overlap.is_some() {
+                    consensus.set_overlap_choice(Some(choice));
+                }
+            }
+            return Ok(errors);
+        }
+        5 => {
+            let strategies = locate_consensus_pairs(tcs_consensus, reference)?;
+            let mut errors = tcs_consensus
                 .iter()
-                .map(|c| c.r2_consensus.clone())
-                .collect(),
-        )?),
-        _ => EndJoiningStrategy::UnknownOverlap,
+                .zip(&strategies)
+                .filter(|(_, strategy)| strategy.is_none())
+                .map(|(consensus, _)| {
+                    format!(
+                        "R1 or R2 consensus of UMI {} not located on the reference, or R2 upstream of R1",
+                        consensus.umi_information_block
+                    )
+                })
+                .collect::<Vec<_>>();
+            errors.extend(join_with_strategies(tcs_consensus, strategies));
+            return Ok(errors);
+        }
+        _ => {
+            return Err(format!(
+                "Unsupported end-joining option {}, must be between 1 and 5",
+                end_joining_option
+            )
+            .into());
+        }
+    };
+
+    Ok(join_with_strategies(tcs_consensus, strategies))
+}
+
+/// Overlap of the consensus of consensus of the R1 and R2 consensus of all the TCS (see `find_consensus_overlap`).
+fn find_population_overlap(
+    tcs_consensus: &[TcsConsensus],
+    overlap_search: OverlapSearchParams,
+    iupac_min_share: Option<f64>,
+) -> Result<OverlapResult, Box<dyn Error + Send + Sync>> {
+    find_consensus_overlap(
+        tcs_consensus
+            .iter()
+            .map(|c| c.r1_consensus.clone())
+            .collect(),
+        tcs_consensus
+            .iter()
+            .map(|c| c.r2_consensus.clone())
+            .collect(),
+        overlap_search,
+        iupac_min_share,
+    )
+}
+
+/// Finds the overlap of each TCS for the end-joining option 4, with the choice made for each TCS.
+/// The population overlap is only searched if a TCS has no acceptable overlap of its own. It is applied at its offset to the
+/// R1 and R2 of the TCS, which may have other lengths than the consensus of consensus. If it cannot be found (e.g. the consensus
+/// of consensus of reads of different lengths fails) or has no overlap, the TCS is joined without an overlap.
+fn find_tcs_overlaps(
+    tcs_consensus: &[TcsConsensus],
+    overlap_search: OverlapSearchParams,
+    iupac_min_share: Option<f64>,
+) -> (Vec<Option<EndJoiningStrategy>>, Vec<TcsOverlapChoice>) {
+    let iupac = iupac_min_share.is_some();
+    let tcs_overlaps = tcs_consensus
+        .par_iter()
+        .map(|consensus| {
+            find_best_overlap_with_quality(
+                consensus.r1_consensus.seq(),
+                Some(consensus.r1_consensus.qual()),
+                consensus.r2_consensus.seq(),
+                Some(consensus.r2_consensus.qual()),
+                overlap_search.min_overlap,
+                overlap_search.error_rate,
+                iupac,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let population_overlap = if tcs_overlaps
+        .iter()
+        .any(|overlap| overlap.overlap_len() == &0)
+    {
+        find_population_overlap(tcs_consensus, overlap_search, iupac_min_share)
+            .ok()
+            .filter(|overlap| *overlap.overlap_len() > 0)
+    } else {
+        None
     };
 
-    let errors = tcs_consensus
+    tcs_consensus
+        .iter()
+        .zip(tcs_overlaps)
+        .map(|(consensus, tcs_overlap)| {
+            if *tcs_overlap.overlap_len() > 0 {
+                return (
+                    Some(EndJoiningStrategy::Overlap(tcs_overlap)),
+                    TcsOverlapChoice::TcsOverlap,
+                );
+            }
+            let fallback = population_overlap.as_ref().and_then(|population_overlap| {
+                overlap_at_offset(
+                    consensus.r1_consensus.seq(),
+                    Some(consensus.r1_consensus.qual()),
+                    consensus.r2_consensus.seq(),
+                    Some(consensus.r2_consensus.qual()),
+                    *population_overlap.offset(),
+                    iupac,
+                )
+            });
+            match fallback {
+                Some(overlap) => (
+                    Some(EndJoiningStrategy::Overlap(overlap)),
+                    TcsOverlapChoice::PopulationOverlap,
+                ),
+                None => (
+                    Some(EndJoiningStrategy::Simple),
+                    TcsOverlapChoice::NoOverlap,
+                ),
+            }
+        })
+        .unzip()
+}
+
+/// Locates the R1 and R2 consensus of each TCS on the reference for the reference-guided end joining.
+/// Returns the `EndJoiningStrategy::ReferenceGuided` of each TCS, `None` if R1 or R2 is not located or R2 is upstream of R1.
+fn locate_consensus_pairs(
+    tcs_consensus: &[TcsConsensus],
+    reference: &QcReference,
+) -> Result<Vec<Option<EndJoiningStrategy>>, Box<dyn Error + Send + Sync>> {
+    let unique_reads = tcs_consensus
+        .iter()
+        .flat_map(|c| [c.r1_consensus.seq(), c.r2_consensus.seq()])
+        .unique()
+        .collect::<Vec<_>>();
+    if unique_reads.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let tcs_qc_input = TcsQcInput::with_attrs(unique_reads, reference.clone(), QC_ALGORITHM)
+        .ok_or("Failed to create TcsQcInput")?;
+    let located = tcs_qc_input.run_locator()?.results_map().to_owned();
+
+    Ok(tcs_consensus
+        .iter()
+        .map(|consensus| {
+            let r1 = located.get(consensus.r1_consensus.seq())?.clone()?;
+            let r2 = located.get(consensus.r2_consensus.seq())?.clone()?;
+            ReferencePlacement::new(r1, r2).map(EndJoiningStrategy::ReferenceGuided)
+        })
+        .collect())
+}
+
+/// Joins each TCS with its own strategy, a TCS without a strategy is not joined.
+/// Returns the errors of the TCS that could not be joined, the other TCS are joined.
+fn join_with_strategies(
+    tcs_consensus: &mut Vec<TcsConsensus>,
+    strategies: Vec<Option<EndJoiningStrategy>>,
+) -> Vec<String> {
+    tcs_consensus
         .par_iter_mut()
-        .filter_map(|consensus| {
+        .zip(strategies)
+        .filter_map(|(consensus, strategy)| {
+            consensus.overlap_choice = None;
+            let Some(strategy) = strategy else {
+                consensus.joined_consensus = None;
+                consensus.overlap = None;
+                return None;
+            };
             let end_joining_input =
                 EndJoiningInput::Fastq((&consensus.r1_consensus, &consensus.r2_consensus));
             let joined_consensus = end_joining(end_joining_input, &strategy);
@@ -271,10 +807,12 @@ pub fn join_consensus_fastq_vec(
                         &joined.quality().as_ref().unwrap(),
                     );
                     consensus.set_joined_consensus(Some(joined_record));
+                    consensus.set_overlap(Some(joined.overlap().clone()));
                     None
                 }
                 Err(e) => {
                     consensus.joined_consensus = None;
+                    consensus.overlap = None;
                     Some(format!(
                         "Error joining consensus for UMI {}: {}",
                         consensus.umi_information_block, e
@@ -282,12 +820,7 @@ pub fn join_consensus_fastq_vec(
                 }
             }
         })
-        .collect::<Vec<_>>();
-    if !errors.is_empty() {
-        return Err(errors.join(", ").into());
-    }
-
-    Ok(())
+        .collect()
 }
 
 const QC_ALGORITHM: QcAlgorithm = QcAlgorithm::SemiGlobal;
@@ -428,7 +961,7 @@ fn get_qc_results(qc_config: &QcConfig, locator: &Locator) -> TcsConsensusQcResu
     }
 
     TcsConsensusQcResult::NotPassed(QcNotPassedReport {
-        qc_reference: qc_config.reference.clone(),
+        qc_reference: qc_config.reference.to_string(),
         qc_coordinates1: qc_config.start.clone(),
         qc_coordinates2: qc_config.end.clone(),
         qc_indels: qc_config.indel,
@@ -460,19 +993,32 @@ fn process_indel_logic(param_indel_bool: bool, locator_indel: bool) -> bool {
 fn find_consensus_overlap(
     r1_consensus: Vec<Record>,
     r2_consensus: Vec<Record>,
+    overlap_search: OverlapSearchParams,
+    iupac_min_share: Option<f64>,
 ) -> Result<OverlapResult, Box<dyn Error + Send + Sync>> {
     let consensus_params = ConsensusParams::default();
     let strategy = ConsensusStrategy::Weighted(consensus_params);
     let r1_consensus_input = ConsensusInput::Fastq(&r1_consensus);
     let r2_consensus_input = ConsensusInput::Fastq(&r2_consensus);
-    let r1_consensus_of_consensus = consensus(strategy, r1_consensus_input)?;
-    let r2_consensus_of_consensus = consensus(strategy, r2_consensus_input)?;
+    let (r1_consensus_of_consensus, r2_consensus_of_consensus) = match iupac_min_share {
+        Some(share) => (
+            consensus_iupac(strategy, r1_consensus_input, share)?,
+            consensus_iupac(strategy, r2_consensus_input, share)?,
+        ),
+        None => (
+            consensus(strategy, r1_consensus_input)?,
+            consensus(strategy, r2_consensus_input)?,
+        ),
+    };
 
-    Ok(find_best_overlap(
+    Ok(find_best_overlap_with_quality(
         &r1_consensus_of_consensus.seq,
+        r1_consensus_of_consensus.qual.as_deref(),
         &r2_consensus_of_consensus.seq,
-        MIN_OVERLAP,
-        ERROR_RATE_FOR_ENDJOINING,
+        r2_consensus_of_consensus.qual.as_deref(),
+        overlap_search.min_overlap,
+        overlap_search.error_rate,
+        iupac_min_share.is_some(),
     ))
 }
 
@@ -480,46 +1026,47 @@ fn find_consensus_overlap(
 mod tests {
 
     use super::*;
+    use crate::helper::params::DEFAULT_MAX_AMBIGUOUS_BASES;
 
     #[test]
     fn test_get_qc_results() {
         let qc_config1 = QcConfig {
-            reference: "HXB2".to_string(),
+            reference: QcReference::HXB2,
             start: Some(6585..6686),
             end: Some(7208..7209),
             indel: true,
         };
 
         let qc_config2 = QcConfig {
-            reference: "HXB2".to_string(),
+            reference: QcReference::HXB2,
             start: None,
             end: None,
             indel: true,
         };
 
         let qc_config3 = QcConfig {
-            reference: "HXB2".to_string(),
+            reference: QcReference::HXB2,
             start: Some(6585..6686),
             end: Some(7208..7209),
             indel: false,
         };
 
         let qc_config4 = QcConfig {
-            reference: "HXB2".to_string(),
+            reference: QcReference::HXB2,
             start: Some(6580..6670),
             end: Some(7208..7209),
             indel: true,
         };
 
         let qc_config5 = QcConfig {
-            reference: "HXB2".to_string(),
+            reference: QcReference::HXB2,
             start: Some(6580..6670),
             end: None,
             indel: true,
         };
 
         let qc_config6 = QcConfig {
-            reference: "HXB2".to_string(),
+            reference: QcReference::HXB2,
             start: None,
             end: Some(7208..7209),
             indel: true,
@@ -557,4 +1104,330 @@ mod tests {
         assert_eq!(result5, TcsConsensusQcResult::Passed);
         assert_eq!(result6, TcsConsensusQcResult::Passed);
     }
+
+    #[test]
+    fn test_max_ambiguous_bases() {
+        let record = Record::with_attrs("umi_5_r1", None, b"ACGNACGTNA", b"IIIIIIIIII");
+        assert!(!within_max_ambiguous_bases(
+            &record,
+            DEFAULT_MAX_AMBIGUOUS_BASES
+        ));
+        assert!(within_max_ambiguous_bases(
+            &record,
+            CountOrFraction::Count(2)
+        ));
+        assert!(within_max_ambiguous_bases(
+            &record,
+            CountOrFraction::Fraction(0.2)
+        ));
+        assert!(!within_max_ambiguous_bases(
+            &record,
+            CountOrFraction::Fraction(0.1)
+        ));
+
+        let masked = mask_ambiguous_quality(&record);
+        assert_eq!(masked.seq(), record.seq());
+        assert_eq!(masked.qual(), b"III!IIII!I");
+    }
+
+    // output of a single UMI family "AAAA" of the R1 and R2 reads of each pair, all bases of quality `I`
+    fn build_single_family(
+        pairs: &[(&str, &str)],
+        umi_cut_off: usize,
+        heterogeneity_action: FamilyHeterogeneityAction,
+        unequal_length: UnequalLengthConsensus,
+    ) -> TcsConsensusBuildingOutput {
+        use crate::helper::umi::{UMI, UMIType};
+        use crate::helper::umis::UMIFamily;
+
+        let filtered_pairs = pairs
+            .iter()
+            .map(|(r1, r2)| FilteredPair {
+                region: "RT".to_string(),
+                umi: UMI {
+                    umi_type: UMIType::UMI,
+                    umi_block: "AAAA".to_string(),
+                    information_index: vec![0, 1, 2, 3],
+                    umi_information_block: "AAAA".to_string(),
+                },
+                r1: Record::with_attrs("r1", None, r1.as_bytes(), &vec![b'I'; r1.len()]),
+                r2: Record::with_attrs("r2", None, r2.as_bytes(), &vec![b'I'; r2.len()]),
+            })
+            .collect::<Vec<_>>();
+        let umi_families = UMIFamilies {
+            families: vec![UMIFamily {
+                umi_information_block: "AAAA".to_string(),
+                frequency: pairs.len(),
+                merged_umis: Vec::new(),
+            }],
+        };
+        let umi_summary: UMISummary = serde_json::from_value(serde_json::json!({
+            "umi_cut_off": umi_cut_off,
+            "umi_freq": {"AAAA": pairs.len()},
+            "umi_freq_distribution": {pairs.len().to_string(): 1},
+        }))
+        .unwrap();
+        build_from_umi_families(
+            &filtered_pairs,
+            &umi_families,
+            umi_summary,
+            ConsensusStrategy::Weighted(ConsensusParams::default()),
+            Some(heterogeneity_action),
+            DEFAULT_MAX_AMBIGUOUS_BASES,
+            unequal_length,
+        )
+    }
+
+    #[test]
+    fn test_split_family_below_umi_cut_off() {
+        // a family of two templates with 3 reads each, differing at position 1 of R1 and position 6 of R2
+        let pairs = [
+            ("ACGTACGT", "TTGGCCAA"),
+            ("ACGTACGT", "TTGGCCAA"),
+            ("ATGTACGT", "TTGGCCGA"),
+            ("ACGTACGT", "TTGGCCAA"),
+            ("ATGTACGT", "TTGGCCGA"),
+            ("ATGTACGT", "TTGGCCGA"),
+        ];
+        let build = |umi_cut_off: usize| {
+            build_single_family(
+                &pairs,
+                umi_cut_off,
+                FamilyHeterogeneityAction::Split,
+                UnequalLengthConsensus::default(),
+            )
+        };
+
+        let output = build(2);
+        let summary = output.family_heterogeneity_summary().clone().unwrap();
+        assert_eq!(output.tcs_consensus().len(), 2);
+        assert_eq!(*summary.split_families(), 1);
+        assert_eq!(*summary.dropped_families(), 0);
+
+        // both haplotypes at the UMI cut-off, no consensus built
+        let output = build(3);
+        let summary = output.family_heterogeneity_summary().clone().unwrap();
+        assert!(output.tcs_consensus().is_empty());
+        assert_eq!(*summary.bimodal_families(), 1);
+        assert_eq!(*summary.split_families(), 0);
+        assert_eq!(*summary.dropped_families(), 1);
+    }
+
+    // one template, 3 of the 8 reads with a deletion in the AAAA homopolymer of R1
+    const INDEL_FAMILY: [(&str, &str); 8] = [
+        ("ACGTAAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+        ("ACGTAAAACGTTGCAGTCCA", "TTGGCCAAGT"),
+    ];
+
+    #[test]
+    fn test_indel_family_not_split() {
+        // the reads of different lengths are not analyzed, the family fails the consensus as without the analysis
+        for action in [
+            FamilyHeterogeneityAction::Report,
+            FamilyHeterogeneityAction::Split,
+            FamilyHeterogeneityAction::Drop,
+        ] {
+            let output =
+                build_single_family(&INDEL_FAMILY, 2, action, UnequalLengthConsensus::Reject);
+            let summary = output.family_heterogeneity_summary().clone().unwrap();
+            assert!(output.tcs_consensus().is_empty());
+            assert_eq!(output.errors().len(), 1);
+            assert_eq!(*summary.analyzed_families(), 0);
+            assert_eq!(*summary.bimodal_families(), 0);
+        }
+    }
+
+    #[test]
+    fn test_indel_family_aligned() {
+        // the reads are analyzed projected onto the modal-length frame, the deletion is a single heterogeneous position
+        for action in [
+            FamilyHeterogeneityAction::Report,
+            FamilyHeterogeneityAction::Split,
+            FamilyHeterogeneityAction::Drop,
+        ] {
+            let output =
+                build_single_family(&INDEL_FAMILY, 2, action, UnequalLengthConsensus::Align);
+            let summary = output.family_heterogeneity_summary().clone().unwrap();
+            assert!(output.errors().is_empty());
+            assert_eq!(output.tcs_consensus().len(), 1);
+            assert_eq!(*output.aligned_families(), 1);
+            assert_eq!(*summary.analyzed_families(), 1);
+            assert_eq!(*summary.bimodal_families(), 0);
+            let tcs_consensus = &output.tcs_consensus()[0];
+            assert_eq!(tcs_consensus.umi_information_block(), "AAAA");
+            assert_eq!(
+                tcs_consensus.r1_consensus().seq(),
+                INDEL_FAMILY[0].0.as_bytes()
+            );
+            assert!(!tcs_consensus.heterogeneity().as_ref().unwrap().bimodal());
+        }
+    }
+
+    // TCS with the R1 and R2 consensus of each pair, all bases of quality `I`
+    fn tcs_consensus_from_pairs(pairs: &[(&str, &str)]) -> Vec<TcsConsensus> {
+        pairs
+            .iter()
+            .map(|(r1, r2)| {
+                let mut consensus = TcsConsensus::new();
+                consensus.set_r1_consensus(Record::with_attrs(
+                    "r1",
+                    None,
+                    r1.as_bytes(),
+                    &vec![b'I'; r1.len()],
+                ));
+                consensus.set_r2_consensus(Record::with_attrs(
+                    "r2",
+                    None,
+                    r2.as_bytes(),
+                    &vec![b'I'; r2.len()],
+                ));
+                consensus
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_join_consensus_overlap_summary() {
+        let mut tcs_consensus = tcs_consensus_from_pairs(&[
+            ("ACGTACGTTACGT", "TACGTTACGTCGA"),
+            ("GGGGGGGTT", "AAAGGGGGGG"),
+            ("AAAAAAAAAA", "CCCCCCCCCC"),
+        ]);
+        assert!(OverlapSummary::from_tcs_consensus(&tcs_consensus, 4).is_none());
+
+        let overlap_search = OverlapSearchParams {
+            min_overlap: 4,
+            error_rate: ERROR_RATE_FOR_ENDJOINING,
+        };
+        join_consensus_fastq_vec(
+            &mut tcs_consensus,
+            4,
+            0,
+            overlap_search,
+            None,
+            &QcReference::HXB2,
+        )
+        .unwrap();
+        assert_eq!(*tcs_consensus[0].overlap().as_ref().unwrap().offset(), 3);
+        assert_eq!(*tcs_consensus[1].overlap().as_ref().unwrap().offset(), -3);
+        assert_eq!(
+            tcs_consensus[2].joined_consensus().as_ref().unwrap().seq(),
+            b"AAAAAAAAAACCCCCCCCCC"
+        );
+
+        let summary = OverlapSummary::from_tcs_consensus(&tcs_consensus, 4).unwrap();
+        assert_eq!(*summary.joined_tcs(), 3);
+        assert_eq!(*summary.no_overlap_tcs(), 1);
+        assert_eq!(*summary.short_insert_tcs(), 1);
+        assert_eq!(*summary.min_overlap_len(), Some(7));
+        assert_eq!(*summary.median_overlap_len(), Some(8.5));
+        assert_eq!(*summary.max_overlap_len(), Some(10));
+        assert_eq!(*summary.mismatch_rate(), 0.0);
+        assert_eq!(
+            *summary.overlap_len_distribution(),
+            BTreeMap::from([(0, 1), (7, 1), (10, 1)])
+        );
+
+        // the overlap of the options 1 and 2 is set by the params, not summarized
+        join_consensus_fastq_vec(
+            &mut tcs_consensus,
+            2,
+            3,
+            overlap_search,
+            None,
+            &QcReference::HXB2,
+        )
+        .unwrap();
+        assert!(OverlapSummary::from_tcs_consensus(&tcs_consensus, 2).is_none());
+    }
+
+    #[test]
+    fn test_join_consensus_population_overlap_fallback() {
+        // the third TCS has 2 mismatches at the overlap of the two others
+        let mut tcs_consensus = tcs_consensus_from_pairs(&[
+            ("GGGGACGTAC", "ACGTACTTTT"),
+            ("GGGGACGTAC", "ACGTACTTTT"),
+            ("GGGGACGTAC", "TCGAACTTTT"),
+        ]);
+
+        let overlap_search = OverlapSearchParams {
+            min_overlap: 4,
+            error_rate: ERROR_RATE_FOR_ENDJOINING,
+        };
+        join_consensus_fastq_vec(
+            &mut tcs_consensus,
+            4,
+            0,
+            overlap_search,
+            None,
+            &QcReference::HXB2,
+        )
+        .unwrap();
+        assert_eq!(
+            *tcs_consensus[0].overlap_choice(),
+            Some(TcsOverlapChoice::TcsOverlap)
+        );
+        assert_eq!(
+            *tcs_consensus[2].overlap_choice(),
+            Some(TcsOverlapChoice::PopulationOverlap)
+        );
+        let overlap = tcs_consensus[2].overlap().as_ref().unwrap();
+        assert_eq!(*overlap.offset(), 4);
+        assert_eq!(*overlap.overlap_len(), 6);
+        assert_eq!(*overlap.mismatches(), 2);
+        assert_eq!(
+            tcs_consensus[2].joined_consensus().as_ref().unwrap().seq(),
+            b"GGGGACGTACTTTT"
+        );
+
+        let summary = OverlapSummary::from_tcs_consensus(&tcs_consensus, 4).unwrap();
+        assert_eq!(*summary.population_overlap_tcs(), 1);
+
+        assert!(
+            join_consensus_fastq_vec(
+                &mut tcs_consensus,
+                6,
+                0,
+                overlap_search,
+                None,
+                &QcReference::HXB2
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_join_consensus_reference_guided_skips_unplaced_tcs() {
+        let fasta =
+            bio::io::fasta::Reader::from_file("tests/data/references/HXB2_PR.fasta").unwrap();
+        let reference = fasta.records().next().unwrap().unwrap().seq().to_vec();
+        let upstream = String::from_utf8(reference[..150].to_vec()).unwrap();
+        let downstream = String::from_utf8(reference[100..250].to_vec()).unwrap();
+        let far_downstream = String::from_utf8(reference[200..300].to_vec()).unwrap();
+        // the second TCS has R2 upstream of R1, it is skipped and the first TCS is still joined
+        let mut tcs_consensus =
+            tcs_consensus_from_pairs(&[(&upstream, &downstream), (&far_downstream, &upstream)]);
+
+        let tcs_errors = join_consensus_fastq_vec(
+            &mut tcs_consensus,
+            5,
+            0,
+            OverlapSearchParams::default(),
+            None,
+            &QcReference::HXB2,
+        )
+        .unwrap();
+        assert_eq!(tcs_errors.len(), 1);
+        assert_eq!(
+            tcs_consensus[0].joined_consensus().as_ref().unwrap().seq(),
+            &reference[..250]
+        );
+        assert!(tcs_consensus[1].joined_consensus().is_none());
+    }
 }
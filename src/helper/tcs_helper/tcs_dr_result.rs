@@ -0,0 +1,134 @@
+use std::fmt::Display;
+
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+
+// Batch level result of the TCS DR pipeline (TCS -> log -> SDRM)
+// One DrLibraryResult is recorded for each library directory in the batch,
+// with the stage at which the library failed, if any.
+// The log stage aggregates all libraries at once, so it is completed for each library that passed the TCS stage,
+// and its failure is recorded at the batch level.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DrStage {
+    Tcs,
+    Log,
+    Sdrm,
+}
+
+impl Display for DrStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DrStage::Tcs => write!(f, "TCS"),
+            DrStage::Log => write!(f, "Log"),
+            DrStage::Sdrm => write!(f, "SDRM"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct DrLibraryResult {
+    #[getset(get = "pub", set = "pub")]
+    library: String,
+    #[getset(get = "pub", set = "pub")]
+    completed_stages: Vec<DrStage>,
+    #[getset(get = "pub", set = "pub")]
+    failed_stage: Option<DrStage>,
+    #[getset(get = "pub", set = "pub")]
+    error: Option<String>,
+}
+
+impl DrLibraryResult {
+    pub fn new(library: &str) -> Self {
+        DrLibraryResult {
+            library: library.to_string(),
+            completed_stages: Vec::new(),
+            failed_stage: None,
+            error: None,
+        }
+    }
+
+    pub fn complete(&mut self, stage: DrStage) {
+        self.completed_stages.push(stage);
+    }
+
+    pub fn fail(&mut self, stage: DrStage, error: String) {
+        self.failed_stage = Some(stage);
+        self.error = Some(error);
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.failed_stage.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
+pub struct DrBatchResult {
+    #[getset(get = "pub", set = "pub")]
+    input_directory: String,
+    #[getset(get = "pub", set = "pub")]
+    dr_version: String,
+    #[getset(get = "pub", set = "pub")]
+    log_directory: String,
+    #[getset(get = "pub", set = "pub")]
+    log_error: Option<String>,
+    #[getset(get = "pub", set = "pub")]
+    library_results: Vec<DrLibraryResult>,
+}
+
+impl DrBatchResult {
+    pub fn new(input_directory: &str, dr_version: &str, log_directory: &str) -> Self {
+        DrBatchResult {
+            input_directory: input_directory.to_string(),
+            dr_version: dr_version.to_string(),
+            log_directory: log_directory.to_string(),
+            log_error: None,
+            library_results: Vec::new(),
+        }
+    }
+
+    pub fn add_library_result(&mut self, library_result: DrLibraryResult) {
+        self.library_results.push(library_result);
+    }
+
+    pub fn failed_libraries(&self) -> Vec<&DrLibraryResult> {
+        self.library_results
+            .iter()
+            .filter(|r| !r.is_successful())
+            .collect()
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.log_error.is_none() && self.library_results.iter().all(|r| r.is_successful())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dr_batch_result() {
+        let mut batch = DrBatchResult::new("batch", "v1", "batch_tcs_log");
+
+        let mut lib1 = DrLibraryResult::new("lib1");
+        lib1.complete(DrStage::Tcs);
+        lib1.complete(DrStage::Log);
+        lib1.complete(DrStage::Sdrm);
+        batch.add_library_result(lib1);
+        assert!(batch.is_successful());
+
+        let mut lib2 = DrLibraryResult::new("lib2");
+        lib2.fail(DrStage::Tcs, "No R1 file found".to_string());
+        batch.add_library_result(lib2);
+
+        assert!(!batch.is_successful());
+        let failed = batch.failed_libraries();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].library(), "lib2");
+        assert_eq!(*failed[0].failed_stage(), Some(DrStage::Tcs));
+
+        let json = serde_json::to_string(&batch).unwrap();
+        assert!(json.contains("\"failed_stage\":\"Tcs\""));
+    }
+}
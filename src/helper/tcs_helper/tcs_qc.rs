@@ -1,10 +1,17 @@
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 
+use bio::alignment::AlignmentOperation;
+use bio::alignment::pairwise::Aligner;
+use bio::io::fasta;
 use getset::{Getters, Setters};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use virust_locator::config::Args as LocatorArgs;
 use virust_locator::locator::Locator;
+use virust_locator::reference::retrieve_reference_sequence;
 
 #[derive(Debug, Clone, Getters, Setters)]
 pub struct TcsQcInput {
@@ -19,10 +26,9 @@ pub struct TcsQcInput {
 impl TcsQcInput {
     pub fn with_attrs(
         query: Vec<&[u8]>,
-        reference: String,
+        reference: QcReference,
         algorithm: QcAlgorithm,
     ) -> Option<Self> {
-        let reference = QcReference::from_string(&reference).unwrap_or_default();
         if query.is_empty() {
             return None;
         }
@@ -37,6 +43,7 @@ impl TcsQcInput {
         })
     }
 
+    /// Arguments of the locator for a built-in reference. A custom reference is not known to the locator, see `run_locator`.
     pub fn to_locator_args(&self) -> LocatorArgs {
         LocatorArgs {
             query: self.query.clone(),
@@ -46,9 +53,20 @@ impl TcsQcInput {
         }
     }
 
+    /// Locates the queries on the reference.
+    /// The built-in references are handled by the locator. The queries are aligned to a custom reference with the
+    /// semi-global alignment of the locator (see `locate_on_sequence`), whatever the algorithm.
     pub fn run_locator(&'_ self) -> Result<TcsQcOutput<'_>, Box<dyn Error + Send + Sync>> {
-        let args = self.to_locator_args();
-        let locator = Locator::build(&args)?;
+        let locator = match &self.reference {
+            QcReference::Custom { .. } => {
+                let ref_seq = self.reference.sequence()?;
+                self.query
+                    .par_iter()
+                    .map(|query| Some(locate_on_sequence(query.as_bytes(), &ref_seq)))
+                    .collect::<Vec<_>>()
+            }
+            _ => Locator::build(&self.to_locator_args())?,
+        };
         let mut query_locator_hashmap: HashMap<&[u8], Option<Locator>> = HashMap::new();
         for i in 0..self.query().len() {
             let query = self.query()[i].as_bytes();
@@ -91,16 +109,18 @@ impl Display for QcAlgorithm {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Reference genome of the QC and trimming of the TCS.
+/// - `HXB2` and `SIVmm239` are the references built in the locator.
+/// - `Custom` is the first record of a FASTA file, given as a path or by name in a reference directory (see `Params::validate`).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub enum QcReference {
+    #[default]
     HXB2,
     SIVmm239,
-}
-
-impl Default for QcReference {
-    fn default() -> Self {
-        QcReference::HXB2
-    }
+    Custom {
+        name: String,
+        path: PathBuf,
+    },
 }
 
 impl QcReference {
@@ -108,9 +128,11 @@ impl QcReference {
         match self {
             QcReference::HXB2 => "HXB2".to_string(),
             QcReference::SIVmm239 => "SIVmm239".to_string(),
+            QcReference::Custom { name, .. } => name.clone(),
         }
     }
 
+    /// The built-in reference of the name, `None` for any other name.
     pub fn from_string(reference: &str) -> Option<Self> {
         match reference {
             "HXB2" => Some(QcReference::HXB2),
@@ -118,6 +140,83 @@ impl QcReference {
             _ => None,
         }
     }
+
+    /// A custom reference from a FASTA file, named after the file stem.
+    pub fn from_fasta_path(path: &Path) -> Self {
+        QcReference::Custom {
+            name: path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.to_string_lossy().to_string()),
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// The nucleotide sequence of the reference, in upper case.
+    /// Returns an error if the FASTA file of a custom reference cannot be read or has no sequence.
+    pub fn sequence(&self) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        match self {
+            QcReference::Custom { path, .. } => {
+                let record = fasta::Reader::from_file(path)
+                    .map_err(|e| format!("Failed to read reference {}: {}", path.display(), e))?
+                    .records()
+                    .next()
+                    .ok_or(format!("No sequence in reference {}", path.display()))??;
+                if record.seq().is_empty() {
+                    return Err(format!("Empty sequence in reference {}", path.display()).into());
+                }
+                Ok(record.seq().to_ascii_uppercase())
+            }
+            _ => Ok(retrieve_reference_sequence(&self.to_string(), "nt")?
+                .sequence
+                .to_vec()),
+        }
+    }
+}
+
+/// Aligns a query to a reference sequence as the semi-global algorithm of the locator
+/// (match 1, mismatch -1, gap open -5, gap extend -1), for the references not built in the locator.
+fn locate_on_sequence(query: &[u8], ref_seq: &[u8]) -> Locator {
+    let score = |a: u8, b: u8| if a == b { 1i32 } else { -1i32 };
+    let mut aligner = Aligner::with_capacity(query.len(), ref_seq.len(), -5, -1, &score);
+    let aln = aligner.semiglobal(query, ref_seq);
+
+    let mut query_aligned_string = String::new();
+    let mut ref_aligned_string = String::new();
+    let (mut matches, mut mismatches, mut gaps) = (0, 0, 0);
+    for (query_pos, ref_pos, operation) in aln.path() {
+        match operation {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                query_aligned_string.push(query[query_pos - 1] as char);
+                ref_aligned_string.push(ref_seq[ref_pos - 1] as char);
+                if operation == AlignmentOperation::Match {
+                    matches += 1;
+                } else {
+                    mismatches += 1;
+                }
+            }
+            AlignmentOperation::Ins => {
+                query_aligned_string.push(query[query_pos - 1] as char);
+                ref_aligned_string.push('-');
+                gaps += 1;
+            }
+            AlignmentOperation::Del => {
+                query_aligned_string.push('-');
+                ref_aligned_string.push(ref_seq[ref_pos - 1] as char);
+                gaps += 1;
+            }
+            _ => {}
+        }
+    }
+
+    Locator::new(
+        aln.ystart + 1,
+        aln.yend,
+        matches as f64 / (matches + mismatches + gaps) as f64 * 100.0,
+        gaps > 0,
+        query_aligned_string,
+        ref_aligned_string,
+    )
 }
 
 #[derive(Debug, Clone, Getters, Setters)]
@@ -125,3 +224,39 @@ pub struct TcsQcOutput<'a> {
     #[getset(get = "pub")]
     results_map: HashMap<&'a [u8], Option<Locator>>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_locator_custom_reference() {
+        // HXB2 protease, with a substitution
+        let query = b"TTAACTTCCCTCAGGTCACTCTTTGGCAACGACCCCTCGTCACAATAAAGATAGGGGGGCAACTAAAGGAAGCTCTATTAGATACAGGAGCAGATGATACAGTATTAGAAGACATGAGTTTGCCAGGAAGATGGAAACCAAAAATGATAGGGGGAATTGGAGGTTTTATCAAAGTAAGACAGTATGATCAGATACTCATAGAAATCTGTGGACATAAAGCTATAGGTACAGTATTAGTAGGACCTACACCTGTCAACATAATTGGAAGAAATCTGTTGACTCAGATTGGTTGCACTTTAAATTTT".as_slice();
+        let hxb2 = TcsQcInput::with_attrs(vec![query], QcReference::HXB2, QcAlgorithm::SemiGlobal)
+            .unwrap();
+        let hxb2_locator = hxb2.run_locator().unwrap().results_map()[query]
+            .clone()
+            .unwrap();
+
+        let custom_reference =
+            QcReference::from_fasta_path(Path::new("tests/data/references/HXB2_PR.fasta"));
+        let custom =
+            TcsQcInput::with_attrs(vec![query], custom_reference, QcAlgorithm::SemiGlobal).unwrap();
+        let custom_locator = custom.run_locator().unwrap().results_map()[query]
+            .clone()
+            .unwrap();
+
+        assert_eq!(custom_locator.ref_start + 2199, hxb2_locator.ref_start);
+        assert_eq!(custom_locator.ref_end + 2199, hxb2_locator.ref_end);
+        assert_eq!(
+            custom_locator.query_aligned_string,
+            hxb2_locator.query_aligned_string
+        );
+        assert_eq!(custom_locator.indel, hxb2_locator.indel);
+
+        let missing =
+            QcReference::from_fasta_path(Path::new("tests/data/references/missing.fasta"));
+        assert!(missing.sequence().is_err());
+    }
+}
@@ -6,8 +6,11 @@ use getset::{Getters, Setters};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
+use crate::helper::consensus::{ConsensusMethod, ConsensusStrategy};
 use crate::helper::params::Params;
+use crate::helper::tcs_helper::FamilyHeterogeneitySummary;
 use crate::helper::tcs_helper::LOW_ABUNDANCE_THRESHOLD_FOR_RAW_READS;
+use crate::helper::tcs_helper::OverlapSummary;
 use crate::helper::tcs_helper::TcsConsensus;
 use crate::helper::tcs_helper::filter_r1_r2::FilterPairInvalidReason;
 use crate::helper::umis::UMISummary;
@@ -20,6 +23,9 @@ pub struct TcsReport {
     current_version: String,
     #[getset(get = "pub", set = "pub")]
     input_directory: String,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    output_directory: String,
     #[getset(get = "pub", set = "pub")]
     advanced_settings: AdvancedSettings,
 
@@ -51,6 +57,37 @@ pub struct RegionReport {
     tcs_consensus_results: Option<Vec<TcsConsensus>>,
     #[getset(get = "pub", set = "pub")]
     umi_summary: Option<UMISummary>,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    consensus_strategy: Option<ConsensusStrategy>,
+    // read pairs matching the primers of the region but rejected for a broken spacer of the patterned UMI
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    umi_pattern_mismatch_reads: usize,
+    // consensus built but dropped for more ambiguous bases (N) than the maximum of the region
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    tcs_dropped_for_ambiguity: usize,
+    // UMI families passing the cut-off whose consensus could not be built
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    tcs_not_built: usize,
+    // UMI families with reads of different lengths whose consensus was built through alignment
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    aligned_families: usize,
+    // TCS whose R1 and R2 consensus could not be end-joined, skipped while the other TCS of the region are joined
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    tcs_not_joined: usize,
+    // counts of the UMI family heterogeneity analysis, if enabled for the region
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    family_heterogeneity_summary: Option<FamilyHeterogeneitySummary>,
+    // distribution of the R1 and R2 overlaps of the joined TCS, if end-joined
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    overlap_summary: Option<OverlapSummary>,
 }
 
 impl RegionReport {
@@ -60,6 +97,14 @@ impl RegionReport {
             filtered_reads_for_region: 0,
             tcs_consensus_results: None,
             umi_summary: None,
+            consensus_strategy: None,
+            umi_pattern_mismatch_reads: 0,
+            tcs_dropped_for_ambiguity: 0,
+            tcs_not_built: 0,
+            aligned_families: 0,
+            tcs_not_joined: 0,
+            family_heterogeneity_summary: None,
+            overlap_summary: None,
         }
     }
 }
@@ -70,6 +115,7 @@ impl TcsReport {
             process_start_time: Local::now(),
             current_version: env!("CARGO_PKG_VERSION").to_string(),
             input_directory: String::new(),
+            output_directory: String::new(),
             advanced_settings: AdvancedSettings::new(),
             input_params: Params::new(),
             total_reads: 0,
@@ -106,6 +152,14 @@ pub struct AdvancedSettings {
     steepness: f32,
     #[getset(get = "pub", set = "pub")]
     midpoint: u8,
+    // resume from the checkpoints of a previous run of the same input and params
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    resume: bool,
+    // consensus method of the regions without their own consensus strategy in the params
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    consensus_method: ConsensusMethod,
 }
 
 impl AdvancedSettings {
@@ -114,6 +168,8 @@ impl AdvancedSettings {
             keep_original: false,
             steepness: 0.0,
             midpoint: 0,
+            resume: false,
+            consensus_method: ConsensusMethod::Weighted,
         }
     }
     pub fn from_attr(keep_original: bool, steepness: f32, midpoint: u8) -> Self {
@@ -121,6 +177,8 @@ impl AdvancedSettings {
             keep_original,
             steepness,
             midpoint,
+            resume: false,
+            consensus_method: ConsensusMethod::Weighted,
         }
     }
 
@@ -129,6 +187,8 @@ impl AdvancedSettings {
             keep_original: false,
             steepness: 0.2,
             midpoint: 30,
+            resume: false,
+            consensus_method: ConsensusMethod::Weighted,
         }
     }
 }
@@ -140,6 +200,7 @@ pub enum TcsReportWarnings {
     UMIDistErrorWithRegion(String, String),
     ConsensusErrorIndividualWithRegion(String, String),
     EndJoiningErrorWithRegion(String, String),
+    EndJoiningErrorIndividualWithRegion(String, String),
     QcAndTrimErrorWithRegion(String, String),
 }
 
@@ -166,6 +227,13 @@ impl Display for TcsReportWarnings {
                     region, msg
                 )
             }
+            TcsReportWarnings::EndJoiningErrorIndividualWithRegion(region, msg) => {
+                write!(
+                    f,
+                    "Encountered error processing Region: {} for end joining, individual TCS not joined, with following error messages: {}",
+                    region, msg
+                )
+            }
             TcsReportWarnings::QcAndTrimErrorWithRegion(region, msg) => {
                 write!(
                     f,
@@ -198,6 +266,22 @@ pub fn tablulate_failed_match_reasons(
         .collect::<Vec<_>>()
 }
 
+/// Counts the read pairs rejected for a UMI pattern mismatch by region.
+/// A pair matching the primers of several regions is counted for each of them.
+pub fn count_umi_pattern_mismatches_by_region(
+    failed_match_reasons: &[FilterPairInvalidReason],
+) -> HashMap<String, usize> {
+    let mut region_counts: HashMap<String, usize> = HashMap::new();
+    for reason in failed_match_reasons {
+        if let FilterPairInvalidReason::UMIPatternMismatch(regions) = reason {
+            for region in regions.split(", ") {
+                *region_counts.entry(region.to_string()).or_insert(0) += 1;
+            }
+        }
+    }
+    region_counts
+}
+
 #[derive(Serialize)]
 #[serde(untagged)]
 pub enum ReasonCount {
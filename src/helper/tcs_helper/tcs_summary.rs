@@ -8,6 +8,7 @@ use chrono::{DateTime, Local};
 use getset::{Getters, Setters};
 use serde::{Deserialize, Serialize};
 
+use crate::helper::consensus::ConsensusStrategy;
 use crate::helper::tcs_helper::*;
 
 #[derive(Debug, Clone, Serialize, Deserialize, Getters, Setters)]
@@ -135,6 +136,30 @@ pub struct RegionReportSummary {
     joined_tcs_number: usize,
     #[getset(get = "pub", set = "pub")]
     tcs_passed_qc_number: usize,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    consensus_strategy: Option<ConsensusStrategy>,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    umi_pattern_mismatch_reads: usize,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    tcs_dropped_for_ambiguity: usize,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    tcs_not_built: usize,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    aligned_families: usize,
+    #[serde(default)]
+    #[getset(get = "pub", set = "pub")]
+    tcs_not_joined: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub", set = "pub")]
+    family_heterogeneity_summary: Option<FamilyHeterogeneitySummary>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub", set = "pub")]
+    overlap_summary: Option<OverlapSummary>,
     // add a field of detection sensitivity
 }
 
@@ -150,12 +175,29 @@ impl RegionReportSummary {
             resampling_index: None,
             joined_tcs_number: 0,
             tcs_passed_qc_number: 0,
+            consensus_strategy: None,
+            umi_pattern_mismatch_reads: 0,
+            tcs_dropped_for_ambiguity: 0,
+            tcs_not_built: 0,
+            aligned_families: 0,
+            tcs_not_joined: 0,
+            family_heterogeneity_summary: None,
+            overlap_summary: None,
         }
     }
 
     pub fn from_region_report(region_report: &RegionReport) -> Self {
         let mut region_summary = RegionReportSummary::new(region_report.region_name().to_owned());
         region_summary.set_filtered_reads_for_region(*region_report.filtered_reads_for_region());
+        region_summary.set_consensus_strategy(*region_report.consensus_strategy());
+        region_summary.set_umi_pattern_mismatch_reads(*region_report.umi_pattern_mismatch_reads());
+        region_summary.set_tcs_dropped_for_ambiguity(*region_report.tcs_dropped_for_ambiguity());
+        region_summary.set_tcs_not_built(*region_report.tcs_not_built());
+        region_summary.set_aligned_families(*region_report.aligned_families());
+        region_summary.set_tcs_not_joined(*region_report.tcs_not_joined());
+        region_summary
+            .set_family_heterogeneity_summary(region_report.family_heterogeneity_summary().clone());
+        region_summary.set_overlap_summary(region_report.overlap_summary().clone());
 
         let tcs_consensus_results = region_report.tcs_consensus_results();
         if let Some(results) = tcs_consensus_results {
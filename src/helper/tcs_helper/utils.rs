@@ -4,6 +4,8 @@ use std::io::BufWriter;
 use std::io::{Result as IoResult, Write};
 use std::ops::Range;
 
+use bio::alignment::AlignmentOperation;
+use bio::alignment::pairwise::Aligner;
 use bio::alphabets::dna;
 use bio::io::fasta;
 use bio::io::fastq::{self, Record};
@@ -50,6 +52,24 @@ pub fn get_iupac_bases(c: char) -> Option<&'static [char]> {
         .find_map(|&(base, bases)| if base == c { Some(bases) } else { None })
 }
 
+/// The IUPAC code of a set of bases (in any order), e.g. `R` for `['G', 'A']`.
+/// Returns `None` if a base is not one of A, C, G, T.
+pub fn get_iupac_code(bases: &[char]) -> Option<char> {
+    let mut bases = bases
+        .iter()
+        .map(|base| base.to_ascii_uppercase())
+        .collect::<Vec<_>>();
+    bases.sort();
+    bases.dedup();
+    IUPAC_TUPLES.iter().map(|&(code, _)| code).find(|&code| {
+        get_iupac_bases(code).is_some_and(|code_bases| {
+            let mut code_bases = code_bases.to_vec();
+            code_bases.sort();
+            code_bases == bases
+        })
+    })
+}
+
 pub fn iupac_matches(a: char, b: char) -> bool {
     if a == b {
         return true;
@@ -74,6 +94,67 @@ pub fn diff_by_iupac(a: &str, b: &str) -> Vec<usize> {
         .collect()
 }
 
+/// Alignment of a primer to a window at the start of a read, see `align_primer_iupac`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrimerAlignment {
+    /// start of the primer in the window
+    pub start: usize,
+    /// end (exclusive) of the primer in the window
+    pub end: usize,
+    /// number of mismatches, inserted and deleted bases between the primer and the window
+    pub edits: usize,
+}
+
+/// Aligns the whole primer to a window of a read with IUPAC aware scoring.
+/// The alignment is semi-global: the primer is aligned end to end, while the ends of the window are free,
+/// so the position of the primer in the window is found from the alignment.
+/// A mismatch scores -1 and a gap of k bases scores -1 - k, so an indel is only preferred over mismatches when it explains more than one of them.
+/// Returns `None` if the window or the primer is empty.
+pub fn align_primer_iupac(window: &[u8], primer: &[u8]) -> Option<PrimerAlignment> {
+    if window.is_empty() || primer.is_empty() {
+        return None;
+    }
+    let score = |a: u8, b: u8| {
+        if iupac_matches(a as char, b as char) {
+            1i32
+        } else {
+            -1i32
+        }
+    };
+    let mut aligner = Aligner::with_capacity(primer.len(), window.len(), -1, -1, score);
+    let alignment = aligner.semiglobal(primer, window);
+
+    // bio reports an IUPAC match (e.g. R and A) as a substitution, so the mismatches are counted again here.
+    let (mut i, mut j) = (alignment.xstart, alignment.ystart);
+    let mut edits = 0;
+    for operation in &alignment.operations {
+        match operation {
+            AlignmentOperation::Match | AlignmentOperation::Subst => {
+                if !iupac_matches(primer[i] as char, window[j] as char) {
+                    edits += 1;
+                }
+                i += 1;
+                j += 1;
+            }
+            AlignmentOperation::Ins => {
+                edits += 1;
+                i += 1;
+            }
+            AlignmentOperation::Del => {
+                edits += 1;
+                j += 1;
+            }
+            AlignmentOperation::Xclip(_) | AlignmentOperation::Yclip(_) => {}
+        }
+    }
+
+    Some(PrimerAlignment {
+        start: alignment.ystart,
+        end: alignment.yend,
+        edits,
+    })
+}
+
 pub fn diff_byte_equal_length(a: &[u8], b: &[u8]) -> Vec<usize> {
     (0..a.len()).filter(|&i| a[i] != b[i]).collect()
 }
@@ -224,6 +305,15 @@ mod tests {
         assert!(!iupac_matches('A', 'C'));
     }
 
+    #[test]
+    fn test_get_iupac_code() {
+        assert_eq!(get_iupac_code(&['G', 'A']), Some('R'));
+        assert_eq!(get_iupac_code(&['T', 'c', 'A']), Some('H'));
+        assert_eq!(get_iupac_code(&['A']), Some('A'));
+        assert_eq!(get_iupac_code(&['A', 'C', 'G', 'T']), Some('N'));
+        assert_eq!(get_iupac_code(&['A', '-']), None);
+    }
+
     #[test]
     fn test_diff_by_iupac() {
         let a = "ACGTRC";
@@ -232,6 +322,40 @@ mod tests {
         assert_eq!(diff, vec![1, 2, 5]);
     }
 
+    #[test]
+    fn test_align_primer_iupac() {
+        let primer = b"TTATGGGATCAAAGCCTAAAGCCATGTGTA";
+
+        // primer after 4 leading Ns, no edits
+        let window = b"ACGTTTATGGGATCAAAGCCTAAAGCCATGTGTAAAATT";
+        let alignment = align_primer_iupac(window, primer).unwrap();
+        assert_eq!(alignment.start, 4);
+        assert_eq!(alignment.end, 34);
+        assert_eq!(alignment.edits, 0);
+
+        // one base deleted in the primer (GGG -> GG), the primer ends one base earlier
+        let window = b"ACGTTTATGGATCAAAGCCTAAAGCCATGTGTAAAATT";
+        let alignment = align_primer_iupac(window, primer).unwrap();
+        assert_eq!(alignment.start, 4);
+        assert_eq!(alignment.end, 33);
+        assert_eq!(alignment.edits, 1);
+
+        // one base inserted in the leading Ns, the primer starts one base later
+        let window = b"ACGTATTATGGGATCAAAGCCTAAAGCCATGTGTAAAATT";
+        let alignment = align_primer_iupac(window, primer).unwrap();
+        assert_eq!(alignment.start, 5);
+        assert_eq!(alignment.end, 35);
+        assert_eq!(alignment.edits, 0);
+
+        // IUPAC codes in the primer are not counted as edits
+        let alignment = align_primer_iupac(b"CCTTACAATGTGCTT", b"TTACAATRTGC").unwrap();
+        assert_eq!(alignment.start, 2);
+        assert_eq!(alignment.end, 13);
+        assert_eq!(alignment.edits, 0);
+
+        assert!(align_primer_iupac(b"", primer).is_none());
+    }
+
     #[test]
 
     fn test_trim_sequence_from_locator() {
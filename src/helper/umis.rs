@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 
 use csv::Writer;
@@ -18,7 +18,23 @@ pub struct UMIs {
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UMIFamily {
     pub umi_information_block: String,
+    /// Number of reads of the family, including the reads of the merged UMIs.
     pub frequency: usize,
+    /// UMIs merged into this family by the UMI clustering, their reads belong to the family.
+    #[serde(default)]
+    pub merged_umis: Vec<String>,
+}
+
+/// How the UMIs are grouped into families before applying the UMI cut-off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum UMIClustering {
+    /// Each distinct UMI is a family, as in the original TCS pipeline.
+    #[default]
+    Exact,
+    /// Directional network clustering: a UMI within Hamming distance 1 of a more abundant UMI is merged into it
+    /// if the count of the abundant UMI is at least twice the count of the UMI minus one.
+    /// Merged UMIs are chained, so a sequencing error of a merged UMI is merged into the same family.
+    Directional,
 }
 
 impl UMIFamily {
@@ -29,6 +45,50 @@ impl UMIFamily {
     }
 }
 
+/// How the UMI cut-off (the minimum family size minus one) is calculated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+pub enum UMICutOffModel {
+    /// Polynomial model of the original TCS pipeline, from the mean of the five largest family sizes
+    /// and the platform error rate (see `umi_cut_off`).
+    #[default]
+    Polynomial,
+    /// Fits the observed family size distribution as a mixture of an error component and a true family component
+    /// (see `fit_umi_family_size_mixture`), and cuts off the family sizes dominated by the error component.
+    /// Falls back to the polynomial model if the fit fails.
+    Mixture,
+}
+
+/// Diagnostics of the mixture fit of the family size distribution.
+/// The error component is geometric over the family sizes, the true family component is log-normal.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Getters)]
+pub struct UMICutOffFit {
+    /// fraction of UMIs in the error component
+    #[getset(get = "pub")]
+    error_weight: f64,
+    /// mean family size of the error component
+    #[getset(get = "pub")]
+    error_mean_size: f64,
+    /// mean of the log family size of the true family component
+    #[getset(get = "pub")]
+    true_log_mean: f64,
+    /// standard deviation of the log family size of the true family component
+    #[getset(get = "pub")]
+    true_log_sd: f64,
+    #[getset(get = "pub")]
+    log_likelihood: f64,
+    #[getset(get = "pub")]
+    iterations: usize,
+    #[getset(get = "pub")]
+    converged: bool,
+    /// largest family size up to the median of the true family component (the exponential of `true_log_mean`)
+    /// with a posterior probability of the error component above 0.5, 0 if none
+    #[getset(get = "pub")]
+    error_dominated_size: usize,
+    /// the polynomial cut-off is used instead of the fit
+    #[getset(get = "pub")]
+    fallback_to_polynomial: bool,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize, Getters, Setters)]
 pub struct UMISummary {
     #[getset(get = "pub")]
@@ -37,6 +97,23 @@ pub struct UMISummary {
     umi_freq: HashMap<String, usize>,
     #[getset(get = "pub")]
     umi_freq_distribution: HashMap<usize, usize>,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    umi_clustering: UMIClustering,
+    /// Number of UMIs merged into a more abundant UMI by the UMI clustering.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    merged_umi_number: usize,
+    /// Number of reads of the merged UMIs.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    merged_read_number: usize,
+    #[serde(default)]
+    #[getset(get = "pub")]
+    umi_cut_off_model: UMICutOffModel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[getset(get = "pub")]
+    umi_cut_off_fit: Option<UMICutOffFit>,
 }
 
 impl UMISummary {
@@ -104,6 +181,8 @@ impl UMIInformationBlocks {
     pub fn find_umi_family_by_error_cutoff(
         &self,
         error_cutoff: f32,
+        clustering: UMIClustering,
+        cut_off_model: UMICutOffModel,
     ) -> Result<(UMIFamilies, UMISummary), UMIDistError> {
         let umis: Vec<&str> = self.umi_information_blocks();
 
@@ -113,7 +192,27 @@ impl UMIInformationBlocks {
 
         let mut families: Vec<UMIFamily> = Vec::new();
 
-        let umi_freq = umis.iter().counts();
+        let umi_counts: HashMap<&str, usize> = umis.iter().copied().counts();
+        let umi_clusters = match clustering {
+            UMIClustering::Exact => umi_counts
+                .iter()
+                .map(|(umi, &count)| UMIFamily {
+                    umi_information_block: umi.to_string(),
+                    frequency: count,
+                    merged_umis: Vec::new(),
+                })
+                .collect::<Vec<_>>(),
+            UMIClustering::Directional => cluster_umis_directional(&umi_counts),
+        };
+        let merged_umi_number = umi_clusters.iter().map(|c| c.merged_umis.len()).sum();
+        let merged_read_number = umi_clusters
+            .iter()
+            .map(|c| c.frequency - umi_counts[c.umi_information_block.as_str()])
+            .sum();
+        let umi_freq = umi_clusters
+            .iter()
+            .map(|c| (c.umi_information_block.as_str(), c.frequency))
+            .collect::<HashMap<&str, usize>>();
 
         let mut freq_count: Vec<usize> = Vec::new();
         umi_freq.clone().into_iter().for_each(|(_, count)| {
@@ -132,17 +231,28 @@ impl UMIInformationBlocks {
         let max_freq: usize =
             (freq_count.iter().k_largest(5).sum::<usize>() as f64 / 5.0).round() as usize;
 
-        let umi_cut_off = umi_cut_off(max_freq, Some(error_cutoff));
+        let polynomial_cut_off = umi_cut_off(max_freq, Some(error_cutoff));
+        let (umi_cut_off, umi_cut_off_fit) = match cut_off_model {
+            UMICutOffModel::Polynomial => (polynomial_cut_off, None),
+            UMICutOffModel::Mixture => {
+                let fit = fit_umi_family_size_mixture(&freq_count_distribution);
+                let cut_off = if fit.fallback_to_polynomial {
+                    polynomial_cut_off
+                } else {
+                    fit.error_dominated_size.max(MIN_UMI_CUT_OFF)
+                };
+                (cut_off, Some(fit))
+            }
+        };
 
         let mut umi_distribution: HashMap<String, usize> = HashMap::new();
-        umi_freq.into_iter().for_each(|(umi, count)| {
-            umi_distribution.insert(umi.to_string(), count);
-            if count > umi_cut_off as usize {
-                let umi = UMIFamily {
-                    umi_information_block: umi.to_string(),
-                    frequency: count,
-                };
-                families.push(umi);
+        umi_clusters.into_iter().for_each(|umi_family| {
+            umi_distribution.insert(
+                umi_family.umi_information_block.clone(),
+                umi_family.frequency,
+            );
+            if umi_family.frequency > umi_cut_off as usize {
+                families.push(umi_family);
             }
         });
 
@@ -152,6 +262,11 @@ impl UMIInformationBlocks {
                 umi_cut_off,
                 umi_freq: umi_distribution,
                 umi_freq_distribution: freq_count_distribution,
+                umi_clustering: clustering,
+                merged_umi_number,
+                merged_read_number,
+                umi_cut_off_model: cut_off_model,
+                umi_cut_off_fit,
             },
         ))
     }
@@ -180,10 +295,81 @@ impl UMIs {
     pub fn find_umi_family_by_error_cutoff(
         &self,
         error_cutoff: f32,
+        clustering: UMIClustering,
+        cut_off_model: UMICutOffModel,
     ) -> Result<(UMIFamilies, UMISummary), UMIDistError> {
         let umi_information_blocks = UMIInformationBlocks::from_umis(self);
-        umi_information_blocks.find_umi_family_by_error_cutoff(error_cutoff)
+        umi_information_blocks.find_umi_family_by_error_cutoff(
+            error_cutoff,
+            clustering,
+            cut_off_model,
+        )
+    }
+}
+
+/// Directional network clustering of UMIs (see `UMIClustering::Directional`).
+/// UMIs are visited from the most abundant one, each unvisited UMI starts a family,
+/// and the UMIs reachable through the directional edges are merged into it.
+/// # Arguments
+/// * `umi_counts` - The number of reads of each distinct UMI.
+/// # Returns
+/// * `Vec<UMIFamily>` - One family per cluster, with the total number of reads and the merged UMIs.
+pub fn cluster_umis_directional(umi_counts: &HashMap<&str, usize>) -> Vec<UMIFamily> {
+    // most abundant first, ties are ordered by UMI to keep the clustering deterministic
+    let sorted_umis = umi_counts
+        .iter()
+        .map(|(&umi, &count)| (umi, count))
+        .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+        .collect::<Vec<_>>();
+
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut families = Vec::new();
+    for (parent, parent_count) in sorted_umis {
+        if !visited.insert(parent) {
+            continue;
+        }
+        let mut family = UMIFamily {
+            umi_information_block: parent.to_string(),
+            frequency: parent_count,
+            merged_umis: Vec::new(),
+        };
+
+        let mut queue = VecDeque::from([(parent.to_string(), parent_count)]);
+        while let Some((umi, count)) = queue.pop_front() {
+            for neighbor in hamming_one_neighbors(&umi) {
+                let Some((&neighbor, &neighbor_count)) =
+                    umi_counts.get_key_value(neighbor.as_str())
+                else {
+                    continue;
+                };
+                if count + 1 >= 2 * neighbor_count && visited.insert(neighbor) {
+                    family.frequency += neighbor_count;
+                    family.merged_umis.push(neighbor.to_string());
+                    queue.push_back((neighbor.to_string(), neighbor_count));
+                }
+            }
+        }
+        family.merged_umis.sort();
+        families.push(family);
+    }
+    families
+}
+
+/// All the sequences with one base (A, C, G or T) substituted in the UMI.
+fn hamming_one_neighbors(umi: &str) -> Vec<String> {
+    let mut neighbors = Vec::new();
+    let mut bases = umi.as_bytes().to_vec();
+    for i in 0..bases.len() {
+        let original = bases[i];
+        for base in [b'A', b'C', b'G', b'T'] {
+            if base != original {
+                bases[i] = base;
+                neighbors.push(String::from_utf8_lossy(&bases).to_string());
+            }
+        }
+        bases[i] = original;
     }
+    neighbors
 }
 
 /// Calculates the UMI cut-off based on the maximum frequency and error cutoff.
@@ -242,6 +428,154 @@ pub fn umi_cut_off(m: usize, error_cutoff: Option<f32>) -> usize {
     n.max(min_val)
 }
 
+/// The smallest UMI cut-off, a family needs at least 3 reads, as with the polynomial model.
+pub const MIN_UMI_CUT_OFF: usize = 2;
+
+const MIXTURE_MAX_ITERATIONS: usize = 1000;
+const MIXTURE_TOLERANCE: f64 = 1e-3;
+// lower bound of the standard deviation of the log family size, keeps the true family component from collapsing on one size
+const MIXTURE_MIN_LOG_SD: f64 = 0.05;
+
+/// Fits the family size distribution (family size -> number of UMIs) with an EM algorithm,
+/// as a mixture of a geometric error component (families of a few reads from UMI or PCR errors)
+/// and a log-normal true family component.
+/// The EM starts from a split of the sizes at the geometric mean of the family sizes weighted by reads.
+/// The error-dominated size is the largest family size up to the median of the true family component
+/// (the exponential of its mean log size) whose posterior probability of the error component is above 0.5.
+/// The fit falls back to the polynomial model if the distribution has fewer than 3 distinct sizes,
+/// if the EM does not converge, if one of the components is empty,
+/// or if the median true family is not larger than the mean error family.
+pub fn fit_umi_family_size_mixture(freq_distribution: &HashMap<usize, usize>) -> UMICutOffFit {
+    let sizes = freq_distribution
+        .iter()
+        .filter(|(size, count)| **size > 0 && **count > 0)
+        .map(|(&size, &count)| (size as f64, count as f64))
+        .sorted_by(|a, b| a.0.total_cmp(&b.0))
+        .collect::<Vec<_>>();
+    let total = sizes.iter().map(|(_, count)| count).sum::<f64>();
+
+    let mut fit = UMICutOffFit {
+        error_weight: 1.0,
+        error_mean_size: 1.0,
+        true_log_mean: 0.0,
+        true_log_sd: 0.0,
+        log_likelihood: f64::NEG_INFINITY,
+        iterations: 0,
+        converged: false,
+        error_dominated_size: 0,
+        fallback_to_polynomial: true,
+    };
+    if sizes.len() < 3 {
+        return fit;
+    }
+
+    // initial components: the error component on the sizes below the geometric mean of the family sizes weighted by reads
+    // (the exponential of the mean of the log sizes weighted by reads), the true family component at or above it
+    let log_mean_by_reads = sizes
+        .iter()
+        .map(|(size, count)| size * count * size.ln())
+        .sum::<f64>()
+        / sizes.iter().map(|(size, count)| size * count).sum::<f64>();
+    let split = log_mean_by_reads.exp();
+    let (error_part, true_part): (Vec<_>, Vec<_>) =
+        sizes.iter().copied().partition(|(size, _)| *size < split);
+    if error_part.is_empty() || true_part.is_empty() {
+        return fit;
+    }
+    let weighted_mean = |part: &[(f64, f64)], f: &dyn Fn(f64) -> f64| {
+        part.iter()
+            .map(|(size, count)| f(*size) * count)
+            .sum::<f64>()
+            / part.iter().map(|(_, count)| count).sum::<f64>()
+    };
+    let mut error_weight = error_part.iter().map(|(_, count)| count).sum::<f64>() / total;
+    let mut geometric_p = 1.0 / weighted_mean(&error_part, &|size| size);
+    let mut log_mean = weighted_mean(&true_part, &|size| size.ln());
+    let mut log_sd = weighted_mean(&true_part, &|size| (size.ln() - log_mean).powi(2))
+        .sqrt()
+        .max(MIXTURE_MIN_LOG_SD);
+
+    let error_density = |size: f64, p: f64| p * (1.0 - p).powf(size - 1.0);
+    let true_density = |size: f64, mean: f64, sd: f64| {
+        (-(size.ln() - mean).powi(2) / (2.0 * sd * sd)).exp()
+            / (size * sd * (2.0 * std::f64::consts::PI).sqrt())
+    };
+
+    let mut previous_log_likelihood = f64::NEG_INFINITY;
+    let mut responsibilities = vec![0.0; sizes.len()];
+    for iteration in 1..=MIXTURE_MAX_ITERATIONS {
+        // E step: posterior probability of the true family component for each size
+        let mut log_likelihood = 0.0;
+        for (i, (size, count)) in sizes.iter().enumerate() {
+            let error = error_weight * error_density(*size, geometric_p);
+            let family = (1.0 - error_weight) * true_density(*size, log_mean, log_sd);
+            let density = error + family;
+            responsibilities[i] = if density > 0.0 { family / density } else { 1.0 };
+            log_likelihood += count * density.max(f64::MIN_POSITIVE).ln();
+        }
+
+        // M step
+        let true_total = sizes
+            .iter()
+            .zip(&responsibilities)
+            .map(|((_, count), r)| count * r)
+            .sum::<f64>();
+        let error_total = total - true_total;
+        if true_total <= 0.0 || error_total <= 0.0 {
+            return fit;
+        }
+        error_weight = error_total / total;
+        geometric_p = error_total
+            / sizes
+                .iter()
+                .zip(&responsibilities)
+                .map(|((size, count), r)| count * (1.0 - r) * size)
+                .sum::<f64>();
+        log_mean = sizes
+            .iter()
+            .zip(&responsibilities)
+            .map(|((size, count), r)| count * r * size.ln())
+            .sum::<f64>()
+            / true_total;
+        log_sd = (sizes
+            .iter()
+            .zip(&responsibilities)
+            .map(|((size, count), r)| count * r * (size.ln() - log_mean).powi(2))
+            .sum::<f64>()
+            / true_total)
+            .sqrt()
+            .max(MIXTURE_MIN_LOG_SD);
+
+        fit.iterations = iteration;
+        fit.log_likelihood = log_likelihood;
+        if (log_likelihood - previous_log_likelihood).abs() < MIXTURE_TOLERANCE {
+            fit.converged = true;
+            break;
+        }
+        previous_log_likelihood = log_likelihood;
+    }
+
+    fit.error_weight = error_weight;
+    fit.error_mean_size = 1.0 / geometric_p;
+    fit.true_log_mean = log_mean;
+    fit.true_log_sd = log_sd;
+    // sizes above the median of the log-normal true family component (exp of its mean log size) are never cut off
+    let true_median_size = log_mean.exp();
+    fit.error_dominated_size = (1..=true_median_size.floor() as usize)
+        .rev()
+        .find(|&size| {
+            let error = error_weight * error_density(size as f64, geometric_p);
+            let family = (1.0 - error_weight) * true_density(size as f64, log_mean, log_sd);
+            error > family
+        })
+        .unwrap_or(0);
+    // the fit is not used if the true families are not larger than the error families
+    fit.fallback_to_polynomial = !fit.converged
+        || !fit.log_likelihood.is_finite()
+        || true_median_size <= fit.error_mean_size;
+    fit
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -286,11 +620,97 @@ mod tests {
         };
 
         let (umi_families, umi_summary) = umi_info_blocks
-            .find_umi_family_by_error_cutoff(0.02)
+            .find_umi_family_by_error_cutoff(0.02, UMIClustering::Exact, UMICutOffModel::Polynomial)
             .unwrap();
         dbg!(&umi_families);
         dbg!(&umi_summary);
         assert_eq!(umi_families.families.len(), 5);
         assert_eq!(umi_summary.umi_cut_off, 17);
     }
+
+    #[test]
+    fn test_find_umi_family_by_directional_clustering() {
+        let mut umi_info_vec = vec!["AAAAAAAAAA".to_string(); 100];
+        // one error in the UMI of the family AAAAAAAAAA, and one more error in that UMI
+        umi_info_vec.extend(vec!["AAAAAAAAAC".to_string(); 3]);
+        umi_info_vec.extend(vec!["AAAAAAAACC".to_string(); 1]);
+        // one base away, but too abundant to be an error of AAAAAAAAAA
+        umi_info_vec.extend(vec!["AAAAAAAAAG".to_string(); 60]);
+        umi_info_vec.extend(vec!["CCCCCCCCCC".to_string(); 80]);
+        umi_info_vec.extend(vec!["GGGGGGGGGG".to_string(); 90]);
+        umi_info_vec.extend(vec!["TTTTTTTTTT".to_string(); 70]);
+
+        let umi_info_blocks = UMIInformationBlocks {
+            umi_information_blocks: umi_info_vec,
+        };
+
+        let (umi_families, umi_summary) = umi_info_blocks
+            .find_umi_family_by_error_cutoff(
+                0.02,
+                UMIClustering::Directional,
+                UMICutOffModel::Polynomial,
+            )
+            .unwrap();
+        assert_eq!(umi_summary.umi_freq.len(), 5);
+        assert_eq!(umi_summary.merged_umi_number, 2);
+        assert_eq!(umi_summary.merged_read_number, 4);
+        let family = umi_families
+            .families
+            .iter()
+            .find(|f| f.umi_information_block == "AAAAAAAAAA")
+            .unwrap();
+        assert_eq!(family.frequency, 104);
+        assert_eq!(family.merged_umis, vec!["AAAAAAAAAC", "AAAAAAAACC"]);
+
+        let (_, umi_summary) = umi_info_blocks
+            .find_umi_family_by_error_cutoff(0.02, UMIClustering::Exact, UMICutOffModel::Polynomial)
+            .unwrap();
+        assert_eq!(umi_summary.umi_freq.len(), 7);
+        assert_eq!(umi_summary.merged_umi_number, 0);
+        assert_eq!(umi_summary.merged_read_number, 0);
+    }
+
+    #[test]
+    fn test_fit_umi_family_size_mixture() {
+        // error families of 1 to 5 reads (geometric, mean 1.25), and 200 true families of 20 to 40 reads
+        let mut freq_distribution: HashMap<usize, usize> =
+            HashMap::from([(1, 2000), (2, 400), (3, 80), (4, 16), (5, 3)]);
+        for size in 20..=40 {
+            freq_distribution.insert(size, if size == 30 { 20 } else { 9 });
+        }
+
+        let fit = fit_umi_family_size_mixture(&freq_distribution);
+        assert!(fit.converged);
+        assert!(!fit.fallback_to_polynomial);
+        assert!((fit.error_weight - 2499.0 / 2699.0).abs() < 0.01);
+        assert!((fit.error_mean_size - 1.25).abs() < 0.05);
+        assert!((fit.true_log_mean - 30f64.ln()).abs() < 0.1);
+        assert!((5..20).contains(&fit.error_dominated_size));
+
+        // too few distinct family sizes to fit
+        let fit = fit_umi_family_size_mixture(&HashMap::from([(1, 100), (50, 10)]));
+        assert!(fit.fallback_to_polynomial);
+    }
+
+    #[test]
+    fn test_find_umi_family_by_mixture_cut_off() {
+        let mut umi_info_vec = Vec::new();
+        // 300 true UMIs with 30 reads, and 600 error UMIs with 1 or 2 reads
+        for i in 0..900usize {
+            let umi = format!("{:010}", i);
+            let size = if i < 300 { 30 } else { 1 + i % 2 };
+            umi_info_vec.extend(vec![umi; size]);
+        }
+        let umi_info_blocks = UMIInformationBlocks {
+            umi_information_blocks: umi_info_vec,
+        };
+
+        let (umi_families, umi_summary) = umi_info_blocks
+            .find_umi_family_by_error_cutoff(0.02, UMIClustering::Exact, UMICutOffModel::Mixture)
+            .unwrap();
+        let fit = umi_summary.umi_cut_off_fit.as_ref().unwrap();
+        assert!(!fit.fallback_to_polynomial);
+        assert!((MIN_UMI_CUT_OFF..30).contains(&umi_summary.umi_cut_off));
+        assert_eq!(umi_families.families.len(), 300);
+    }
 }
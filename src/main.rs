@@ -4,6 +4,7 @@ use virust_tcs::cli::Commands;
 use virust_tcs::helper::*;
 use virust_tcs::pipelines::log::*;
 use virust_tcs::pipelines::params_generator;
+use virust_tcs::pipelines::sdrm::*;
 use virust_tcs::pipelines::tcs::*;
 use virust_tcs::pipelines::tick::*;
 
@@ -13,23 +14,53 @@ fn main() {
     match args.command {
         Commands::Run {
             input,
+            output,
             param,
             keep_original,
             steepness,
             midpoint,
+            consensus_method,
+            resume,
+            batch,
+            jobs,
         } => {
             let params_input_type = ParamsInputType::FromFilePath(param.clone());
-            tcs(
-                &input,
-                params_input_type,
-                keep_original,
-                steepness,
-                midpoint,
-            )
-            .unwrap_or_else(|err| {
-                eprintln!("Fatal Error: {} occurred during processing", err);
-                std::process::exit(1);
-            });
+            if batch {
+                let batch_result = tcs_batch(
+                    &input,
+                    output.as_deref(),
+                    params_input_type,
+                    keep_original,
+                    steepness,
+                    midpoint,
+                    consensus_method,
+                    resume,
+                    jobs,
+                )
+                .unwrap_or_else(|err| {
+                    eprintln!("Fatal Error: {} occurred during processing", err);
+                    std::process::exit(1);
+                });
+                println!("\n{}", batch_result);
+                if !batch_result.is_successful() {
+                    std::process::exit(1);
+                }
+            } else {
+                tcs(
+                    &input,
+                    output.as_deref(),
+                    params_input_type,
+                    keep_original,
+                    steepness,
+                    midpoint,
+                    consensus_method,
+                    resume,
+                )
+                .unwrap_or_else(|err| {
+                    eprintln!("Fatal Error: {} occurred during processing", err);
+                    std::process::exit(1);
+                });
+            }
         }
         Commands::Generate {} => {
             // Call the function to generate the param file here
@@ -37,21 +68,38 @@ fn main() {
         }
         Commands::DR {
             input,
+            output,
             version,
             keep_original,
+            resume,
+            jobs,
         } => {
-            let params_input_type = ParamsInputType::PresetID(version.clone().to_lowercase());
-            tcs(
+            let batch_result = tcs_dr(
                 &input,
-                params_input_type,
+                output.as_deref(),
+                Some(version),
                 keep_original,
-                consensus::DEFAULT_K as f32,
-                consensus::DEFAULT_Q0 as u8,
+                resume,
+                jobs,
             )
             .unwrap_or_else(|err| {
                 eprintln!("Fatal Error: {} occurred during processing", err);
                 std::process::exit(1);
             });
+            if let Some(log_error) = batch_result.log_error() {
+                eprintln!("Log stage failed: {}", log_error);
+            }
+            for failed in batch_result.failed_libraries() {
+                eprintln!(
+                    "Library {} failed at the {} stage: {}",
+                    failed.library(),
+                    failed.failed_stage().unwrap(),
+                    failed.error().as_deref().unwrap_or("unknown error")
+                );
+            }
+            if !batch_result.is_successful() {
+                std::process::exit(1);
+            }
         }
         Commands::DrParams { version } => {
             println!("Listing DR params...");
@@ -76,8 +124,10 @@ fn main() {
                 "Running SDRM pipeline with input: {}, version: {}",
                 input, version
             );
-            // TODO: Call the function to run the SDRM pipeline here
-            todo!();
+            run_sdrm(&input, &version).unwrap_or_else(|err| {
+                eprintln!("Fatal Error: {} occurred during processing", err);
+                std::process::exit(1);
+            });
         }
         Commands::Log { input, output } => {
             println!("Running TCS log pipeline with input: {}", input);
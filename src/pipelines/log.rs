@@ -205,7 +205,7 @@ fn merge_csv_summaries(summaries: &[TcsReportSummary]) -> Result<String, Box<dyn
 }
 
 // Find the matching FASTQ under the fastq_files/ within a TCS/Region output directory
-fn find_fastq(root: &PathBuf, target_name: &str) -> Option<PathBuf> {
+pub fn find_fastq(root: &PathBuf, target_name: &str) -> Option<PathBuf> {
     let candidate = root.join("fastq_files").join(target_name);
     if candidate.exists() {
         Some(candidate)
@@ -214,7 +214,7 @@ fn find_fastq(root: &PathBuf, target_name: &str) -> Option<PathBuf> {
     }
 }
 
-fn determine_joined_tcs_file_from_params(params: &Params, region_name: &str) -> Option<String> {
+pub fn determine_joined_tcs_file_from_params(params: &Params, region_name: &str) -> Option<String> {
     for region_param in &params.primer_pairs {
         if region_param.region == region_name {
             if region_param.trim {
@@ -119,7 +119,7 @@ pub fn exec() {
             0
         };
 
-        print!("Need TCS QC? Support HIV-1 and SVI (y/n, default as n):\n>  ");
+        print!("Need TCS QC? Support HIV-1, SIV and custom references (y/n, default as n):\n>  ");
         let tcs_qc = match collect_input().as_str() {
             "y" | "Y" => true,
             _ => false,
@@ -172,9 +172,20 @@ pub fn exec() {
             forward: forward_primer,
             cdna: cdna_primer,
             majority: majority_cutoff,
+            consensus_strategy: None,
+            primer_mismatch_tolerance: None,
+            primer_matching: None,
+            umi_clustering: None,
+            umi_cut_off_model: None,
+            family_heterogeneity: None,
+            max_ambiguous_bases: None,
+            iupac_min_share: None,
+            unequal_length_consensus: None,
             end_join,
             end_join_option,
             overlap: overlap_size,
+            min_overlap: None,
+            overlap_error_rate: None,
             tcs_qc,
             ref_genome,
             ref_start,
@@ -203,6 +214,7 @@ pub fn exec() {
         platform_error_rate: error_rate,
         platform_format: platform,
         email: email,
+        reference_dir: None,
         primer_pairs: regions,
     };
 
@@ -261,17 +273,18 @@ fn collect_input() -> String {
 fn collect_end_join_option() -> u8 {
     loop {
         print!(
-            "End-join option? Choose from (1-4):\n\
+            "End-join option? Choose from (1-5):\n\
             1: simple join, no overlap\n\
             2: known overlap\n\
             3: unknow overlap, use sample consensus to determine overlap, all sequence pairs have same overlap\n\
-            4: unknow overlap, determine overlap by individual sequence pairs, sequence pairs can have different overlap\n\
+            4: unknow overlap, determine overlap by individual sequence pairs, sequence pairs can have different overlap, fall back to the sample consensus overlap\n\
+            5: reference-guided, join by the reference coordinates of R1 and R2 located separately, gaps filled with N\n\
             >  "
         );
         match collect_input().parse::<u8>() {
-            Ok(num) if (1..=4).contains(&num) => break num,
+            Ok(num) if (1..=5).contains(&num) => break num,
             _ => {
-                eprintln!("Invalid input. Please enter a number from 1 to 4.");
+                eprintln!("Invalid input. Please enter a number from 1 to 5.");
                 continue;
             }
         }
@@ -284,18 +297,21 @@ fn get_ref() -> String {
             "Choose reference genome (1-3):\n\
         1: HIV-1 HXB2\n\
         2: SIV MAC239\n\
+        3: custom reference, path of a FASTA file\n\
         >  "
         );
         match collect_input().parse::<u8>() {
-            Ok(num) if (1..=2).contains(&num) => {
-                if num == 1 {
-                    break "HXB2".to_string();
-                } else {
-                    break "SIVmm239".to_string();
+            Ok(1) => break "HXB2".to_string(),
+            Ok(2) => break "SIVmm239".to_string(),
+            Ok(3) => {
+                print!("Enter the path of the reference FASTA file:\n>  ");
+                match collect_input().as_str() {
+                    "" => continue,
+                    path => break path.to_string(),
                 }
             }
             _ => {
-                eprintln!("Invalid input. Please enter a number from 1 to 2.");
+                eprintln!("Invalid input. Please enter a number from 1 to 3.");
                 continue;
             }
         }
@@ -1,30 +1,92 @@
-//TODO: SDRM pipeline
-
 use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
+
+use bio::io::fastq;
+
+use crate::helper::drm_helper::*;
+use crate::helper::json::FromJsonString;
+use crate::helper::params::Params;
+use crate::pipelines::log::{determine_joined_tcs_file_from_params, find_fastq};
+
+// SDRM pipeline
+// Reads the joined TCS of each region from a TCS DR output directory (a library directory with tcs_params.json),
+// calls the surveillance DRMs according to the DRM version, and writes sdrm_report.json and sdrm_report.csv
+// next to tcs_report.json.
+
+pub fn run_sdrm(input: &str, version: &str) -> Result<SdrmReport, Box<dyn Error>> {
+    let input_dir = PathBuf::from(input);
+    if !input_dir.is_dir() {
+        return Err(format!("Input directory {} does not exist", input).into());
+    }
+
+    let params_path = input_dir.join("tcs_params.json");
+    if !params_path.exists() {
+        return Err(format!(
+            "No tcs_params.json found in {}, run the TCS DR pipeline first",
+            input
+        )
+        .into());
+    }
+    let params = Params::from_json_string(&fs::read_to_string(&params_path)?)?;
+
+    let drm_version = DrmVersion::build_from_version(version)?;
+    let drm_list = DrmList::build()?;
 
-use crate::helper::muscle::get_muscle_version;
-use crate::helper::r::{check_r_installed, get_sdrm_r_script};
+    let mut sdrm_report = SdrmReport::new(drm_version.version(), input);
 
-pub fn run_sdrm(input: String, version: String) -> Result<(), Box<dyn Error>> {
-    // Placeholder implementation
-    println!(
-        "Running SDRM pipeline with input: {}, version: {}",
-        input, version
-    );
+    let mut regions = drm_version
+        .seq_drm_correlation()
+        .keys()
+        .cloned()
+        .collect::<Vec<String>>();
+    regions.sort();
 
-    // check environment, ensure MSA aligner (MUSCLE) is available
+    for region in regions {
+        let Some(joined_fastq_name) = determine_joined_tcs_file_from_params(&params, &region)
+        else {
+            sdrm_report.add_warning(format!(
+                "Region {} is not in tcs_params.json, skipped",
+                region
+            ));
+            continue;
+        };
 
-    let muscle_version = get_muscle_version("muscle");
+        let Some(joined_fastq) = find_fastq(&input_dir.join(&region), &joined_fastq_name) else {
+            sdrm_report.add_warning(format!(
+                "Region {}: {} not found, skipped",
+                region, joined_fastq_name
+            ));
+            continue;
+        };
 
-    println!("Detected MUSCLE version: {:?}", muscle_version); //placeholder
+        let records = fastq::Reader::from_file(&joined_fastq)?
+            .records()
+            .collect::<Result<Vec<fastq::Record>, _>>()?;
+        let sequences = records.iter().map(|r| r.seq()).collect::<Vec<&[u8]>>();
 
-    // check if R and required R packages are installed
+        let config = DrmRegionConfig::from_drm_version(&drm_version, &drm_list, &region)?;
+        let region_report = call_drms(&config, &sequences).map_err(|e| e.to_string())?;
 
-    check_r_installed()?;
+        println!(
+            "Region {}: {} TCS, {} located, {} DRMs observed",
+            region,
+            region_report.tcs_number(),
+            region_report.located_tcs_number(),
+            region_report.drm_calls().len()
+        );
 
-    let r_script: &'static str = get_sdrm_r_script();
+        sdrm_report.add_region_report(region_report);
+    }
 
-    println!("Using R script:\n{}", r_script); //placeholder
+    fs::write(
+        input_dir.join("sdrm_report.json"),
+        serde_json::to_string_pretty(&sdrm_report)?,
+    )?;
+    fs::write(
+        input_dir.join("sdrm_report.csv"),
+        sdrm_report.to_csv_string()?,
+    )?;
 
-    todo!("Implement the SDRM pipeline logic here");
+    Ok(sdrm_report)
 }
@@ -1,20 +1,29 @@
+use std::any::Any;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
 use std::time::Duration;
 
+use bio::io::fastq::Record;
 use chrono::Local;
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
 
 use crate::cli::BANNER;
 use crate::helper::consensus::*;
-use crate::helper::io::read_fastq_file;
+use crate::helper::drm_helper::DrmVersion;
+use crate::helper::io::{FASTQ_CHUNK_SIZE, PairedFastqReader, find_directories};
 use crate::helper::json::FromJsonString;
-use crate::helper::params::Params;
+use crate::helper::params::{Params, ValidatedParams};
 use crate::helper::tcs_helper::*;
+use crate::helper::umis::UMIClustering;
+use crate::pipelines::log::run_log;
+use crate::pipelines::sdrm::run_sdrm;
 
 #[derive(Debug, Clone)]
 pub enum ParamsInputType {
@@ -22,13 +31,17 @@ pub enum ParamsInputType {
     FromFilePath(String),
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn tcs(
     input: &str,
+    output: Option<&str>,
     param: ParamsInputType,
     keep_original: bool,
     steepness: f32,
     midpoint: u8,
-) -> Result<(), Box<dyn Error>> {
+    consensus_method: ConsensusMethod,
+    resume: bool,
+) -> Result<TcsReport, Box<dyn Error>> {
     println!("\n{}\n", BANNER);
     let spinner = ProgressBar::new_spinner();
     spinner.set_style(
@@ -39,13 +52,45 @@ pub fn tcs(
     spinner.set_message("Initializing TCS pipeline...");
     spinner.enable_steady_tick(Duration::from_millis(100));
 
+    tcs_with_progress(
+        input,
+        output,
+        param,
+        keep_original,
+        steepness,
+        midpoint,
+        consensus_method,
+        resume,
+        &spinner,
+    )
+}
+
+/// Runs the TCS pipeline on one library, the final message of the run is set on `progress`
+/// (the spinner of `tcs`, or a hidden progress bar for the libraries of a concurrent batch).
+#[allow(clippy::too_many_arguments)]
+fn tcs_with_progress(
+    input: &str,
+    output: Option<&str>,
+    param: ParamsInputType,
+    keep_original: bool,
+    steepness: f32,
+    midpoint: u8,
+    consensus_method: ConsensusMethod,
+    resume: bool,
+    progress: &ProgressBar,
+) -> Result<TcsReport, Box<dyn Error>> {
     // initialize the TCS report and logger
     // this will create a new TCS report and a logger that will log the progress of the TCS pipeline.
-    // the logger will log to a file named run_log.txt in the input directory.
+    // the logger will log to a file named run_log.txt in the output directory (the input directory if no output directory is given).
     // the TCS report will be used to store the results of the TCS pipeline.
-    let (mut tcs_report, mut logger, mut report_logger) = tcs_init(input)?;
+    // with a separate output directory, the input directory is never modified, so the original files are always kept.
+    let (mut tcs_report, mut logger, mut report_logger) = tcs_init(input, output)?;
+    let output = tcs_report.output_directory().clone();
+    let keep_original = keep_original || output != input;
 
-    let advanced_settings = AdvancedSettings::from_attr(keep_original, steepness, midpoint);
+    let mut advanced_settings = AdvancedSettings::from_attr(keep_original, steepness, midpoint);
+    advanced_settings.set_resume(resume);
+    advanced_settings.set_consensus_method(consensus_method);
     tcs_report.set_advanced_settings(advanced_settings);
 
     // log the start of the TCS pipeline
@@ -86,9 +131,12 @@ pub fn tcs(
     log_line(&mut logger, "Writing TCS report to file")?;
     tcs_report.set_process_end_time(Local::now());
     tcs_report_write(&tcs_report, &mut report_logger)?;
-    export_input_params(&tcs_report, input)?;
-    tcs_sequence_data_write(&tcs_report, input)?;
-    raw_sequence_invalid_reason_write(&tcs_report, input)?;
+    export_input_params(&tcs_report, &output)?;
+    tcs_sequence_data_write(&tcs_report, &output)?;
+    raw_sequence_invalid_reason_write(&tcs_report, &output)?;
+
+    // the run is complete, its checkpoints are no longer needed
+    TcsCheckpoint::remove(Path::new(&output))?;
 
     let final_message = if success {
         log_line(
@@ -111,8 +159,8 @@ pub fn tcs(
         )
     };
 
-    spinner.finish_with_message(final_message);
-    Ok(())
+    progress.finish_with_message(final_message);
+    Ok(tcs_report)
 }
 
 // MARK: tcs main function
@@ -133,10 +181,22 @@ pub fn tcs_main(
         &format!("TCS (Rust) Version: {}", env!("CARGO_PKG_VERSION")),
     )?;
     log_line(logger, &format!("Input directory: {}", input))?;
+    log_line(
+        logger,
+        &format!("Output directory: {}", tcs_report.output_directory()),
+    )?;
     log_line(logger, &format!("Params type: {:?}", param))?;
     log_line(logger, &format!("Keep original: {}", keep_original))?;
     log_line(logger, &format!("Steepness: {}", steepness))?;
     log_line(logger, &format!("Midpoint: {}", midpoint))?;
+    log_line(
+        logger,
+        &format!(
+            "Consensus method: {:?}",
+            advanced_settings.consensus_method()
+        ),
+    )?;
+    log_line(logger, &format!("Resume: {}", advanced_settings.resume()))?;
     log_line(logger, "Validating input files")?;
 
     // Validate the input files and get the fastq files
@@ -218,77 +278,99 @@ pub fn tcs_main(
         regions.push(region_params.region.to_string());
     }
 
-    let pairs = match read_fastq_file(&fastq_files) {
-        Ok(pairs) => pairs,
-        Err(e) => {
-            log_line(logger, &format!("Error reading fastq files: {}", e))?;
-            tcs_report.add_error(e.to_string());
-            return Ok((tcs_report, Some((r1_file.clone(), r2_file.clone()))));
+    // Open the checkpoints of the run
+    // The result of each stage is saved in the checkpoint directory of the output directory.
+    // When the run is resumed, the stages with a checkpoint from a previous run with the same params and input files
+    // are loaded instead of computed again. The checkpoints of a run with different params or input files are discarded.
+    let fingerprint = CheckpointFingerprint::new(&params, &advanced_settings, &fastq_files)?;
+    let (checkpoint, checkpoint_status) = TcsCheckpoint::open(
+        Path::new(tcs_report.output_directory()),
+        &fingerprint,
+        *advanced_settings.resume(),
+    )?;
+    log_line(logger, &format!("Checkpoints: {}", checkpoint_status))?;
+
+    let demultiplexed = match checkpoint.load_demultiplexed() {
+        Some(demultiplexed) => {
+            log_line(logger, "De-multiplexed pairs loaded from checkpoint")?;
+            demultiplexed
+        }
+        None => {
+            let mut reader = match PairedFastqReader::from_files(&fastq_files) {
+                Ok(reader) => reader,
+                Err(e) => {
+                    log_line(logger, &format!("Error reading fastq files: {}", e))?;
+                    tcs_report.add_error(e.to_string());
+                    return Ok((tcs_report, Some((r1_file.clone(), r2_file.clone()))));
+                }
+            };
+
+            // Process the pairs in parallel
+            // The fastq files are streamed in chunks of FASTQ_CHUNK_SIZE pairs, so that only one chunk of raw records is held in memory.
+            // Each chunk will be filtered based on the validated params using Rayon.
+            // The filter_r1_r2_pairs function will return a PairedRecordFilterResult enum.
+            // If the pair is valid, it will return a FilteredPair struct, which is kept for downstream processing.
+            // If the pair is invalid, it will return a reason for failure.
+            // If there is an error processing the pairs, it will log the error to the logger and return a TcsReport with the error.
+            // The TcsReport with error will be handled in the downstream processing.
+            // The results of the chunks are merged in the order of the fastq files, so the output is identical to processing all pairs at once.
+            log_line(logger, "Reading Fastq files")?;
+
+            let mut total_reads = 0;
+            let mut groups: HashMap<String, Vec<FilteredPair>> = HashMap::new();
+            let mut fails = Vec::new();
+            let mut errors = Vec::new();
+            loop {
+                let chunk = reader.read_chunk(FASTQ_CHUNK_SIZE);
+                if chunk.is_empty() {
+                    break;
+                }
+                total_reads += chunk.len();
+
+                let (chunk_groups, chunk_fails, chunk_errors) =
+                    filter_r1_r2_pairs_in_parallel(&chunk, &validated_params);
+                for (region, mut vec) in chunk_groups {
+                    groups.entry(region).or_default().append(&mut vec);
+                }
+                fails.extend(chunk_fails);
+                errors.extend(chunk_errors.iter().map(|e| e.to_string()));
+            }
+
+            let demultiplexed = DemultiplexedCheckpoint {
+                total_reads,
+                groups,
+                fails,
+                errors,
+                read_issues: reader.issues().iter().map(|e| e.to_string()).collect(),
+            };
+            checkpoint.save_demultiplexed(&demultiplexed)?;
+            demultiplexed
         }
     };
+    let DemultiplexedCheckpoint {
+        total_reads,
+        mut groups,
+        fails,
+        errors,
+        read_issues,
+    } = demultiplexed;
 
-    log_line(logger, "Reading Fastq files")?;
     log_line(
         logger,
-        &format!("Number of raw fastq records: {}", pairs.len()),
+        &format!("Number of raw fastq records: {}", total_reads),
     )?;
 
-    tcs_report.set_total_reads(pairs.len());
+    // Problems in the raw fastq files (parse errors, truncated gzip files, R1 R2 record count mismatches).
+    // The readable pairs are still processed, but the run is not reported as successful.
+    for issue in read_issues {
+        log_line(logger, &format!("Error reading fastq files: {}", issue))?;
+        tcs_report.add_error(issue);
+    }
 
-    // Process the pairs in parallel
-    // This will filter the R1 and R2 pairs based on the validated params.
-    // It will use Rayon to process the pairs in parallel.
-    // The filter_r1_r2_pairs function will return a PairedRecordFilterResult enum.
-    // If the pair is valid, it will return a FilteredPair struct.
-    // If the pair is invalid, it will return a reason for failure.
-    // If there is an error processing the pairs, it will log the error to the logger and return a TcsReport with the error.
-    // The TcsReport with error will be handled in the downstream processing.
-    let (mut groups, fails, errors) = pairs
-        .par_iter()
-        .fold(
-            // Each thread starts with its own empty results
-            || (HashMap::new(), Vec::new(), Vec::new()),
-            |(mut ok, mut fail, mut err), pair| {
-                match filter_r1_r2_pairs(&pair.0, &pair.1, &validated_params) {
-                    Ok(filter_result) => match filter_result {
-                        PairedRecordFilterResult::Valid(filtered_pair) => {
-                            let region = filtered_pair.region.clone();
-                            ok.entry(region)
-                                .or_insert_with(Vec::new)
-                                .push(filtered_pair);
-                        }
-                        PairedRecordFilterResult::Invalid(reason) => {
-                            fail.push(reason);
-                        }
-                    },
-                    Err(e) => {
-                        err.push(e);
-                    }
-                }
-                (ok, fail, err)
-            },
-        )
-        .reduce(
-            // Combine the results from all threads
-            || {
-                (
-                    HashMap::<String, Vec<FilteredPair>>::new(),
-                    Vec::new(),
-                    Vec::new(),
-                )
-            },
-            |(mut ok1, mut fail1, mut err1), (ok2, fail2, err2)| {
-                for (region, mut vec) in ok2 {
-                    ok1.entry(region).or_insert_with(Vec::new).append(&mut vec);
-                }
-                fail1.extend(fail2);
-                err1.extend(err2);
-                (ok1, fail1, err1)
-            },
-        );
+    tcs_report.set_total_reads(total_reads);
 
     for region in &regions {
-        groups.entry(region.clone()).or_insert_with(Vec::new);
+        groups.entry(region.clone()).or_default();
     }
 
     // log the de-multiplexed pairs
@@ -297,12 +379,12 @@ pub fn tcs_main(
     log_line(logger, "De-multiplexed pairs")?;
     for (region, filtered_pairs) in &groups {
         if !filtered_pairs.is_empty()
-            && (filtered_pairs.len() as f64 / pairs.len() as f64)
+            && (filtered_pairs.len() as f64 / total_reads as f64)
                 < LOW_ABUNDANCE_THRESHOLD_FOR_RAW_READS
         {
             tcs_report.add_warning(TcsReportWarnings::LowAbundanceWarning(
                 region.clone(),
-                filtered_pairs.len() as f64 / pairs.len() as f64,
+                filtered_pairs.len() as f64 / total_reads as f64,
             ));
         }
         log_line(
@@ -353,25 +435,27 @@ pub fn tcs_main(
     // We will also add these errors to the TcsReportWarnings enum, with a type of R1R2filteringwarning.
     for error in errors {
         log_line(logger, &format!("Error: {}", error))?;
-        tcs_report.add_warning(TcsReportWarnings::R1R2filteringwarning(error.to_string()));
+        tcs_report.add_warning(TcsReportWarnings::R1R2filteringwarning(error));
     }
 
     // now processing consensus calling
-    // We will use the ConsensusStrategy::Weighted with the steepness and midpoint parameters.
+    // The default strategy is ConsensusStrategy::Weighted with the steepness and midpoint parameters.
     // The ConsensusStrategy::Weighted will use the steepness and midpoint parameters to calculate the consensus sequence.
     // The steepness parameter will control the steepness of the curve, and the midpoint parameter will control the midpoint of the curve.
-    // The ConsensusStrategy::Weighted will be used to calculate the consensus sequence for each region
-    let consensus_strategy =
-        ConsensusStrategy::Weighted(ConsensusParams::new(steepness as f64, midpoint as f64));
+    // With the Bayesian consensus method, the default strategy is ConsensusStrategy::Bayesian with the platform error rate of each region.
+    // Each region can override the default with its own strategy (majority cut-off or explicit consensus_strategy in the params),
+    // the strategy used is recorded in the RegionReport.
+    let consensus_method = *advanced_settings.consensus_method();
 
     log_line(logger, "Starting consensus calling")?;
 
     // Process each region in sequence
-    // We will iterate over each region and call the TcsConsensus::build_from_filtered_pairs function to build the consensus sequence for each region.
-    // The TcsConsensus::build_from_filtered_pairs function will take the filtered pairs for each region and return a tuple containing the consensus results, errors, and UMI summary.
+    // We will iterate over each region, find the UMI families of the filtered pairs and build the consensus sequence of each UMI family.
+    // The consensus building will take the filtered pairs for each region and return a tuple containing the consensus results, errors, and UMI summary.
     // If there is an error processing a region, we will log the error and continue to the next region.
     // We will also create a RegionReport for each region and add it to the TcsReport.
     let mut region_reports = Vec::new(); // This will hold the reports for each region for the field `region_reports` in TcsReport
+    let umi_pattern_mismatches = count_umi_pattern_mismatches_by_region(&fails);
     for (region, filtered_pairs) in &groups {
         let region_params =
             validated_params
@@ -380,21 +464,40 @@ pub fn tcs_main(
                     "No parameters found for region: {}",
                     region
                 )))?;
+        let default_consensus_strategy = consensus_method.strategy(
+            steepness as f64,
+            midpoint as f64,
+            region_params.platform_error_rate as f64,
+        );
+        let consensus_strategy = region_params.consensus_strategy_or(default_consensus_strategy);
         let mut region_report = RegionReport::new();
         region_report.set_region_name(region.clone());
         region_report.set_filtered_reads_for_region(filtered_pairs.len());
+        region_report.set_consensus_strategy(Some(consensus_strategy));
+        let umi_pattern_mismatch_reads = umi_pattern_mismatches.get(region).copied().unwrap_or(0);
+        region_report.set_umi_pattern_mismatch_reads(umi_pattern_mismatch_reads);
+        if umi_pattern_mismatch_reads > 0 {
+            log_line(
+                logger,
+                &format!(
+                    "Region: {}, {} paired sequences rejected for a UMI not matching the UMI pattern",
+                    region, umi_pattern_mismatch_reads
+                ),
+            )?;
+        }
         log_line(
             logger,
             &format!(
-                "Processing region: {}, with {} valid pairs",
+                "Processing region: {}, with {} valid pairs, consensus strategy: {:?}",
                 region,
-                filtered_pairs.len()
+                filtered_pairs.len(),
+                consensus_strategy
             ),
         )?;
 
         // Build consensus for the region
         // TODO: need to remove low-quality reads (aka ambiguous reads) after consensus calling.
-        // This will call the TcsConsensus::build_from_filtered_pairs function to build the consensus sequence for the region.
+        // This will call tcs_consensus::find_umi_families and tcs_consensus::build_from_umi_families to build the consensus sequences for the region.
         // We use Rayon to process the pairs in parallel.
         // We capture UMIDistError, if it occurs, we log it in the logger and add a warning to the TcsReport, and continue to the next region.
         // If the consensus calling is successful, we will log the number of UMIs found, the UMI cut-off, and the number of UMIs passing the error cutoff.
@@ -402,26 +505,92 @@ pub fn tcs_main(
         // Errors during consensus calling for individual UMI families will be logged, and warnings will be added to the TcsReport.
         // The UMI summary will be collected and added to the RegionReport as part of the TcsReport.
 
-        let (mut consensus_results, consensus_errors, umi_summary) =
-            match tcs_consensus::build_from_filtered_pairs(
-                filtered_pairs,
-                consensus_strategy,
-                params.platform_error_rate,
-            ) {
-                Ok(tcs_consensus_building_output) => (
-                    tcs_consensus_building_output.tcs_consensus().clone(),
-                    tcs_consensus_building_output.errors().clone(),
-                    tcs_consensus_building_output.umi_summary().clone(),
-                ),
+        // The UMI families and the consensus are checkpointed as separate stages.
+        let consensus_output = match checkpoint
+            .load::<TcsConsensusBuildingOutput>(CheckpointStage::Consensus, Some(region))
+        {
+            Some(consensus_output) => {
+                log_line(
+                    logger,
+                    &format!("Consensus for region {} loaded from checkpoint", region),
+                )?;
+                Ok(consensus_output)
+            }
+            None => {
+                let umi_families = match checkpoint
+                    .load::<UmiFamiliesCheckpoint>(CheckpointStage::UmiFamilies, Some(region))
+                {
+                    Some(umi_families) => {
+                        log_line(
+                            logger,
+                            &format!("UMI families for region {} loaded from checkpoint", region),
+                        )?;
+                        umi_families
+                    }
+                    None => {
+                        let umi_families = tcs_consensus::find_umi_families(
+                            filtered_pairs,
+                            params.platform_error_rate,
+                            region_params.umi_clustering,
+                            region_params.umi_cut_off_model,
+                        )
+                        .map_err(|e| e.to_string());
+                        checkpoint.save(
+                            CheckpointStage::UmiFamilies,
+                            Some(region),
+                            &umi_families,
+                        )?;
+                        umi_families
+                    }
+                };
+                match umi_families {
+                    Ok((umi_families, umi_summary)) => {
+                        let consensus_output = tcs_consensus::build_from_umi_families(
+                            filtered_pairs,
+                            &umi_families,
+                            umi_summary,
+                            consensus_strategy,
+                            region_params.family_heterogeneity,
+                            region_params.max_ambiguous_bases,
+                            region_params.unequal_length_consensus,
+                        );
+                        checkpoint.save(
+                            CheckpointStage::Consensus,
+                            Some(region),
+                            &consensus_output,
+                        )?;
+                        Ok(consensus_output)
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+        };
+
+        let (mut consensus_results, consensus_errors, umi_summary, heterogeneity_summary) =
+            match consensus_output {
+                Ok(tcs_consensus_building_output) => {
+                    region_report.set_tcs_dropped_for_ambiguity(
+                        *tcs_consensus_building_output.ambiguous_dropped_number(),
+                    );
+                    region_report.set_tcs_not_built(tcs_consensus_building_output.errors().len());
+                    region_report
+                        .set_aligned_families(*tcs_consensus_building_output.aligned_families());
+                    (
+                        tcs_consensus_building_output.tcs_consensus().clone(),
+                        tcs_consensus_building_output.errors().clone(),
+                        tcs_consensus_building_output.umi_summary().clone(),
+                        tcs_consensus_building_output
+                            .family_heterogeneity_summary()
+                            .clone(),
+                    )
+                }
                 Err(e) => {
                     log_line(
                         logger,
                         &format!("UMI Distribution Error for Region {}: {}", region, e),
                     )?;
-                    tcs_report.add_warning(TcsReportWarnings::UMIDistErrorWithRegion(
-                        region.clone(),
-                        e.to_string(),
-                    ));
+                    tcs_report
+                        .add_warning(TcsReportWarnings::UMIDistErrorWithRegion(region.clone(), e));
                     region_reports.push(region_report);
                     continue; // Skip to the next region if there's an error
                 }
@@ -439,6 +608,27 @@ pub fn tcs_main(
             )?;
         }
 
+        log_line(
+            logger,
+            &format!(
+                "Region: {}, {} TCS dropped for more than {:?} ambiguous bases, {} TCS not built for consensus errors",
+                region,
+                region_report.tcs_dropped_for_ambiguity(),
+                region_params.max_ambiguous_bases,
+                region_report.tcs_not_built()
+            ),
+        )?;
+        if region_params.unequal_length_consensus == UnequalLengthConsensus::Align {
+            log_line(
+                logger,
+                &format!(
+                    "Region: {}, {} UMI families with reads of different lengths aligned for the consensus",
+                    region,
+                    region_report.aligned_families()
+                ),
+            )?;
+        }
+
         let passed_umi_families_distribution = umi_summary.get_passed_umis_hashmap();
         log_line(
             logger,
@@ -450,7 +640,33 @@ pub fn tcs_main(
                 passed_umi_families_distribution.len()
             ),
         )?;
+        if *umi_summary.umi_clustering() != UMIClustering::Exact {
+            log_line(
+                logger,
+                &format!(
+                    "Region: {}, UMI clustering {:?} merged {} UMIs ({} reads) into more abundant UMIs",
+                    region,
+                    umi_summary.umi_clustering(),
+                    umi_summary.merged_umi_number(),
+                    umi_summary.merged_read_number()
+                ),
+            )?;
+        }
         region_report.set_umi_summary(Some(umi_summary));
+        if let Some(heterogeneity_summary) = &heterogeneity_summary {
+            log_line(
+                logger,
+                &format!(
+                    "Region: {}, {} of {} UMI families are bimodal (PCR recombination or UMI collision), {} split, {} dropped",
+                    region,
+                    heterogeneity_summary.bimodal_families(),
+                    heterogeneity_summary.analyzed_families(),
+                    heterogeneity_summary.split_families(),
+                    heterogeneity_summary.dropped_families()
+                ),
+            )?;
+        }
+        region_report.set_family_heterogeneity_summary(heterogeneity_summary);
         log_line(
             logger,
             &format!(
@@ -491,11 +707,41 @@ pub fn tcs_main(
             region_reports.push(region_report);
             continue; // Skip end-joining if no consensus sequences are available
         }
-        if let Err(error) = join_consensus_fastq_vec(
-            &mut consensus_results,
-            region_params.end_join_option,
-            region_params.overlap as usize,
-        ) {
+        let end_joined =
+            match checkpoint.load::<TcsStageCheckpoint>(CheckpointStage::EndJoined, Some(region)) {
+                Some(end_joined) => {
+                    log_line(
+                        logger,
+                        &format!(
+                            "End-joined TCS for region {} loaded from checkpoint",
+                            region
+                        ),
+                    )?;
+                    end_joined
+                }
+                None => {
+                    let (tcs_errors, error) = match join_consensus_fastq_vec(
+                        &mut consensus_results,
+                        region_params.end_join_option,
+                        region_params.overlap as usize,
+                        region_params.overlap_search,
+                        region_params.iupac_min_share,
+                        &region_params.reference,
+                    ) {
+                        Ok(tcs_errors) => (tcs_errors, None),
+                        Err(e) => (Vec::new(), Some(e.to_string())),
+                    };
+                    let end_joined = TcsStageCheckpoint {
+                        tcs_consensus: consensus_results,
+                        error,
+                        tcs_errors,
+                    };
+                    checkpoint.save(CheckpointStage::EndJoined, Some(region), &end_joined)?;
+                    end_joined
+                }
+            };
+        let mut consensus_results = end_joined.tcs_consensus;
+        if let Some(error) = end_joined.error {
             log_line(
                 logger,
                 &format!(
@@ -505,9 +751,20 @@ pub fn tcs_main(
             )?;
             tcs_report.add_warning(TcsReportWarnings::EndJoiningErrorWithRegion(
                 region.clone(),
-                error.to_string(),
+                error,
             ));
         };
+        region_report.set_tcs_not_joined(end_joined.tcs_errors.len());
+        for err in end_joined.tcs_errors {
+            log_line(
+                logger,
+                &format!("End-joining Error for Region {}: {}", region, err),
+            )?;
+            tcs_report.add_warning(TcsReportWarnings::EndJoiningErrorIndividualWithRegion(
+                region.clone(),
+                err,
+            ));
+        }
 
         log_line(
             logger,
@@ -516,15 +773,59 @@ pub fn tcs_main(
                 region
             ),
         )?;
+        let overlap_summary =
+            OverlapSummary::from_tcs_consensus(&consensus_results, region_params.end_join_option);
+        if let Some(overlap_summary) = &overlap_summary {
+            log_line(
+                logger,
+                &format!(
+                    "Region: {}, {} joined TCS, {} without an overlap, {} with a short insert, {} with the population overlap, overlap length min/median/max {:?}/{:?}/{:?}, overlap mismatch rate {:.4}",
+                    region,
+                    overlap_summary.joined_tcs(),
+                    overlap_summary.no_overlap_tcs(),
+                    overlap_summary.short_insert_tcs(),
+                    overlap_summary.population_overlap_tcs(),
+                    overlap_summary.min_overlap_len(),
+                    overlap_summary.median_overlap_len(),
+                    overlap_summary.max_overlap_len(),
+                    overlap_summary.mismatch_rate()
+                ),
+            )?;
+        }
+        region_report.set_overlap_summary(overlap_summary);
 
         if region_params.tcs_qc {
             log_line(logger, &format!("QC (and trimming) for region: {}", region))?;
 
-            if let Err(error) = qc_and_trim_consensus_fastq_vec(
-                &mut consensus_results,
-                region_params.qc_config.as_ref(),
-                region_params.trim_config.as_ref(),
-            ) {
+            let qc = match checkpoint.load::<TcsStageCheckpoint>(CheckpointStage::Qc, Some(region))
+            {
+                Some(qc) => {
+                    log_line(
+                        logger,
+                        &format!("QC'd TCS for region {} loaded from checkpoint", region),
+                    )?;
+                    qc
+                }
+                None => {
+                    let error = qc_and_trim_consensus_fastq_vec(
+                        &mut consensus_results,
+                        region_params.qc_config.as_ref(),
+                        region_params.trim_config.as_ref(),
+                    )
+                    .err()
+                    .map(|e| e.to_string());
+                    let qc = TcsStageCheckpoint {
+                        tcs_consensus: consensus_results,
+                        error,
+                        tcs_errors: Vec::new(),
+                    };
+                    checkpoint.save(CheckpointStage::Qc, Some(region), &qc)?;
+                    qc
+                }
+            };
+            let consensus_results = qc.tcs_consensus;
+
+            if let Some(error) = qc.error {
                 log_line(
                     logger,
                     &format!(
@@ -534,7 +835,7 @@ pub fn tcs_main(
                 )?;
                 tcs_report.add_warning(TcsReportWarnings::QcAndTrimErrorWithRegion(
                     region.clone(),
-                    error.to_string(),
+                    error,
                 ));
             } else {
                 log_line(
@@ -567,8 +868,69 @@ pub fn tcs_main(
     Ok((tcs_report, Some((r1_file.clone(), r2_file.clone()))))
 }
 
+// MARK: filter pairs in parallel
+type FilteredChunk = (
+    HashMap<String, Vec<FilteredPair>>,
+    Vec<FilterPairInvalidReason>,
+    Vec<Box<dyn Error + Send + Sync>>,
+);
+
+// Filters a chunk of R1 R2 pairs in parallel, returning the valid pairs grouped by region, the reasons of the invalid pairs, and the errors.
+// Each thread folds its own results, and the results are reduced in the order of the input pairs.
+fn filter_r1_r2_pairs_in_parallel(
+    pairs: &[(Record, Record)],
+    validated_params: &ValidatedParams,
+) -> FilteredChunk {
+    pairs
+        .par_iter()
+        .fold(
+            // Each thread starts with its own empty results
+            || (HashMap::new(), Vec::new(), Vec::new()),
+            |(mut ok, mut fail, mut err), pair| {
+                match filter_r1_r2_pairs(&pair.0, &pair.1, validated_params) {
+                    Ok(filter_result) => match filter_result {
+                        PairedRecordFilterResult::Valid(filtered_pair) => {
+                            let region = filtered_pair.region.clone();
+                            ok.entry(region)
+                                .or_insert_with(Vec::new)
+                                .push(filtered_pair);
+                        }
+                        PairedRecordFilterResult::Invalid(reason) => {
+                            fail.push(reason);
+                        }
+                    },
+                    Err(e) => {
+                        err.push(e);
+                    }
+                }
+                (ok, fail, err)
+            },
+        )
+        .reduce(
+            // Combine the results from all threads
+            || {
+                (
+                    HashMap::<String, Vec<FilteredPair>>::new(),
+                    Vec::new(),
+                    Vec::new(),
+                )
+            },
+            |(mut ok1, mut fail1, mut err1), (ok2, fail2, err2)| {
+                for (region, mut vec) in ok2 {
+                    ok1.entry(region).or_insert_with(Vec::new).append(&mut vec);
+                }
+                fail1.extend(fail2);
+                err1.extend(err2);
+                (ok1, fail1, err1)
+            },
+        )
+}
+
 //MARK: tcs_init function
-fn tcs_init(input: &str) -> Result<(TcsReport, BufWriter<File>, BufWriter<File>), Box<dyn Error>> {
+fn tcs_init(
+    input: &str,
+    output: Option<&str>,
+) -> Result<(TcsReport, BufWriter<File>, BufWriter<File>), Box<dyn Error>> {
     // Initialize the TCS report
     let mut tcs_report = TcsReport::new();
 
@@ -584,31 +946,323 @@ fn tcs_init(input: &str) -> Result<(TcsReport, BufWriter<File>, BufWriter<File>)
 
     tcs_report.set_input_directory(input.to_string());
 
+    // Create the output directory if it does not exist, the input directory is used if no output directory is given
+    let output = output.unwrap_or(input);
+    let output_dir = Path::new(output);
+    fs::create_dir_all(output_dir)?;
+    if fs::canonicalize(output_dir)? == fs::canonicalize(input_dir)? {
+        tcs_report.set_output_directory(input.to_string());
+    } else {
+        tcs_report.set_output_directory(output.to_string());
+    }
+
     let logfile = OpenOptions::new()
         .create(true)
         .append(true)
-        .open(input_dir.join("run_log.txt"))?;
+        .open(output_dir.join("run_log.txt"))?;
 
     let logger: BufWriter<File> = BufWriter::new(logfile);
 
-    let report_file = File::create(input_dir.join("tcs_report.json"))?;
+    let report_file = File::create(output_dir.join("tcs_report.json"))?;
 
     let report_file = BufWriter::new(report_file);
 
     Ok((tcs_report, logger, report_file))
 }
 
+// MARK: tcs_batch function
+
+// Runs the TCS pipeline on every library subdirectory of a batch directory.
+// Up to `jobs` libraries are processed at the same time. Each library is run on its own thread, pulling the next library from a shared queue,
+// and the parallel parts of the TCS pipeline all run on the global rayon thread pool, so concurrent libraries share the same worker threads
+// instead of each creating a pool of their own.
+// With an output directory, each library is written to <output>/<library name>.
+// When libraries run concurrently, the banner is printed once and the per-library spinners are hidden,
+// each library prints one line prefixed with its name when it starts and when it ends.
+// A failure in one library does not stop the batch, it is recorded in the TcsBatchResult.
+
+#[allow(clippy::too_many_arguments)]
+pub fn tcs_batch(
+    input: &str,
+    output: Option<&str>,
+    param: ParamsInputType,
+    keep_original: bool,
+    steepness: f32,
+    midpoint: u8,
+    consensus_method: ConsensusMethod,
+    resume: bool,
+    jobs: usize,
+) -> Result<TcsBatchResult, Box<dyn Error>> {
+    let mut libraries = find_directories(input)?;
+    libraries.sort();
+
+    let next_library = AtomicUsize::new(0);
+    let outcomes = Mutex::new(Vec::new());
+    let workers = jobs.max(1).min(libraries.len());
+    let concurrent = workers > 1;
+    if concurrent {
+        println!("\n{}\n", BANNER);
+    }
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                loop {
+                    let i = next_library.fetch_add(1, Ordering::SeqCst);
+                    let Some(library) = libraries.get(i) else {
+                        break;
+                    };
+                    let library_output = output.map(|output| {
+                        Path::new(output)
+                            .join(library.file_name().unwrap_or_default())
+                            .to_string_lossy()
+                            .to_string()
+                    });
+                    let library_name = library
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .to_string();
+                    let library = library.to_string_lossy().to_string();
+                    // a panic in one library is caught and recorded as its error, so that the batch goes on
+                    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                        if !concurrent {
+                            return tcs(
+                                &library,
+                                library_output.as_deref(),
+                                param.clone(),
+                                keep_original,
+                                steepness,
+                                midpoint,
+                                consensus_method,
+                                resume,
+                            )
+                            .map_err(|e| e.to_string());
+                        }
+                        println!("[{}] Starting TCS pipeline", library_name);
+                        let progress = ProgressBar::hidden();
+                        let outcome = tcs_with_progress(
+                            &library,
+                            library_output.as_deref(),
+                            param.clone(),
+                            keep_original,
+                            steepness,
+                            midpoint,
+                            consensus_method,
+                            resume,
+                            &progress,
+                        )
+                        .map_err(|e| e.to_string());
+                        match &outcome {
+                            Ok(_) => {
+                                println!("[{}] {}", library_name, progress.message().trim_end())
+                            }
+                            Err(e) => println!("[{}] TCS pipeline failed: {}", library_name, e),
+                        }
+                        outcome
+                    }))
+                    .unwrap_or_else(|payload| Err(panic_message(payload.as_ref())));
+                    let library_result = TcsLibraryResult::from_tcs_outcome(&library, outcome);
+                    outcomes
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .push((i, library_result));
+                }
+            });
+        }
+    });
+
+    let mut outcomes = outcomes
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    outcomes.sort_by_key(|(i, _)| *i);
+
+    let mut batch_result = TcsBatchResult::new(input);
+    for (_, library_result) in outcomes {
+        batch_result.add_library_result(library_result);
+    }
+
+    Ok(batch_result)
+}
+
+/// Message of the panic of a library, from its payload (a `&str` or a `String` for the panics with a message).
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    let message = payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    format!("TCS pipeline panicked: {}", message)
+}
+
 // MARK: tcs_dr main function
 
+// The TCS DR pipeline runs on a batch directory, each subdirectory is one library with its R1 and R2 files.
+// 1. TCS pipeline with the DR preset params on each library, up to `jobs` libraries at a time (see tcs_batch)
+// With an output directory, the libraries are written to <output>/<library name> and the input directory is not modified.
+// 2. log pipeline to aggregate the batch into <output>_tcs_log (<input>_tcs_log without an output directory),
+// the Log stage is completed for each library that passed the TCS pipeline, or the log error is recorded for the batch
+// 3. SDRM pipeline on each library that passed the TCS pipeline
+// A failure in one library does not stop the batch, it is recorded in the DrBatchResult,
+// which is also written to tcs_dr_result.json in the output (or input) directory.
+
 pub fn tcs_dr(
     input: &str,
+    output: Option<&str>,
     version: Option<String>,
     keep_original: bool,
-) -> Result<(), Box<dyn Error>> {
-    todo!(
-        "TCS DR function called with input: {}, version: {:?}, keep_original: {}",
+    resume: bool,
+    jobs: usize,
+) -> Result<DrBatchResult, Box<dyn Error>> {
+    let version = version.unwrap_or("v1".to_string()).to_lowercase();
+    // fail early on a version without a preset or a DRM config, before any library is processed
+    Params::from_preset(&version)?;
+    DrmVersion::build_from_version(&version)?;
+
+    let input = input.trim_end_matches('/');
+    let output = output.map(|output| output.trim_end_matches('/'));
+    // the TCS results of the batch, which are used by the log and SDRM stages
+    let batch_directory = output.unwrap_or(input);
+    let log_directory = format!("{}_tcs_log", batch_directory);
+    let mut batch_result = DrBatchResult::new(input, &version, &log_directory);
+
+    let tcs_batch_result = tcs_batch(
         input,
-        version,
-        keep_original
-    );
+        output,
+        ParamsInputType::PresetID(version.clone()),
+        keep_original,
+        DEFAULT_K as f32,
+        DEFAULT_Q0 as u8,
+        ConsensusMethod::Weighted,
+        resume,
+        jobs,
+    )?;
+
+    let mut library_results = Vec::new();
+    for tcs_result in tcs_batch_result.library_results() {
+        let library_directory = Path::new(batch_directory)
+            .join(
+                Path::new(tcs_result.library())
+                    .file_name()
+                    .unwrap_or_default(),
+            )
+            .to_string_lossy()
+            .to_string();
+        let mut library_result = DrLibraryResult::new(&library_directory);
+        if tcs_result.is_successful() {
+            library_result.complete(DrStage::Tcs);
+        } else {
+            library_result.fail(DrStage::Tcs, tcs_result.errors().join("; "));
+        }
+        library_results.push(library_result);
+    }
+
+    match run_log(batch_directory.to_string(), log_directory.clone()) {
+        Ok(_) => {
+            for library_result in library_results.iter_mut() {
+                if library_result.is_successful() {
+                    library_result.complete(DrStage::Log);
+                }
+            }
+        }
+        Err(e) => {
+            batch_result.set_log_error(Some(e.to_string()));
+        }
+    }
+
+    for library_result in library_results.iter_mut() {
+        if !library_result.is_successful() {
+            continue;
+        }
+        match run_sdrm(library_result.library(), &version) {
+            Ok(_) => library_result.complete(DrStage::Sdrm),
+            Err(e) => library_result.fail(DrStage::Sdrm, e.to_string()),
+        }
+    }
+
+    batch_result.set_library_results(library_results);
+
+    fs::write(
+        Path::new(batch_directory).join("tcs_dr_result.json"),
+        serde_json::to_string_pretty(&batch_result)?,
+    )?;
+
+    Ok(batch_result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::io::{BufRead, BufReader};
+
+    use flate2::read::MultiGzDecoder;
+
+    // relative path and content of each file of a directory tree
+    fn directory_snapshot(dir: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let mut snapshot = BTreeMap::new();
+        let mut directories = vec![dir.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            for entry in fs::read_dir(&directory).unwrap() {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    directories.push(path);
+                } else {
+                    let content = fs::read(&path).unwrap();
+                    snapshot.insert(path.strip_prefix(dir).unwrap().to_path_buf(), content);
+                }
+            }
+        }
+        snapshot
+    }
+
+    #[test]
+    fn test_tcs_output_directory_keeps_input() {
+        let dir = std::env::temp_dir().join("virust_tcs_pipeline_output_directory");
+        let _ = fs::remove_dir_all(&dir);
+        let input = dir.join("input");
+        let output = dir.join("output");
+        fs::create_dir_all(&input).unwrap();
+        // the first 1000 pairs of the HIV DR control library
+        for read in ["r1", "r2"] {
+            let file = File::open(format!("tests/data/hivdr_control/{}.fastq.gz", read)).unwrap();
+            let lines = BufReader::new(MultiGzDecoder::new(file))
+                .lines()
+                .take(4000)
+                .map(|line| line.unwrap() + "\n")
+                .collect::<String>();
+            fs::write(input.join(format!("{}.fastq", read)), lines).unwrap();
+        }
+        let before = directory_snapshot(&input);
+
+        tcs(
+            input.to_str().unwrap(),
+            Some(output.to_str().unwrap()),
+            ParamsInputType::FromFilePath("tests/data/dr_v1.json".to_string()),
+            false,
+            DEFAULT_K as f32,
+            DEFAULT_Q0 as u8,
+            ConsensusMethod::default(),
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(directory_snapshot(&input), before);
+        assert!(output.join("tcs_report.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("index out of bounds")).unwrap_err();
+        assert_eq!(
+            panic_message(payload.as_ref()),
+            "TCS pipeline panicked: index out of bounds"
+        );
+        let payload = panic::catch_unwind(|| panic!("{} reads", 3)).unwrap_err();
+        assert_eq!(
+            panic_message(payload.as_ref()),
+            "TCS pipeline panicked: 3 reads"
+        );
+    }
 }